@@ -1,22 +1,653 @@
+use crate::err::RedisError;
+use crate::value::RedisValue;
+use crate::zset::{LexBound, Score, ScoreBound};
 use std::fmt::{Debug, Display};
 
 pub mod parser;
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum Command<'a> {
-  Ping,
+  Ping(Option<&'a [u8]>),
   CommandDocs,
+  /// COMMAND GETKEYS, the embedded command's name and arguments.
+  GetKeys(Vec<&'a str>),
+  /// COMMAND INFO of these command names.
+  DescribeCommands(Vec<&'a str>),
+  /// COMMAND LIST and COMMAND COUNT.
+  ListCommands,
+  CountCommands,
   DbSize,
+  Info(Option<&'a str>),
   Config,
   Get(&'a str),
+  MGet(Vec<&'a str>),
+  GetEx(&'a str, Option<Expiry>),
   Set(&'a str, &'a [u8]),
+  /// SET given any of the NX, XX and GET options.
+  SetWith(&'a str, &'a [u8], SetOptions),
+  Append(&'a str, &'a [u8]),
+  /// SETRANGE key, byte offset and the value written there.
+  SetRange(&'a str, usize, &'a [u8]),
+  /// BITPOS key, the bit looked for and a byte range, counting from the end
+  /// when negative.
+  BitPos(&'a str, bool, Option<(i64, i64)>),
   SetEx(&'a str, &'a [u8], usize),
   Lpush(&'a str, Vec<&'a [u8]>),
   Rpush(&'a str, Vec<&'a [u8]>),
   LpushX(&'a str, Vec<&'a [u8]>),
   RpushX(&'a str, Vec<&'a [u8]>),
-  Lpop(&'a str, usize),
-  Rpop(&'a str, usize),
+  /// Pops one element, or up to `count` of them as an array when given.
+  Lpop(&'a str, Option<usize>),
+  Rpop(&'a str, Option<usize>),
+  Llen(&'a str),
+  Lindex(&'a str, i64),
+  Lrange(&'a str, i64, i64),
+  Sort(&'a str, SortOptions),
   Del(Vec<&'a str>),
+  /// COPY source, destination and REPLACE.
+  Copy(&'a str, &'a str, bool),
   Incr(&'a str),
+  IncrByFloat(&'a str, Score),
+  Decr(&'a str),
+  Hset(&'a str, Vec<(&'a [u8], &'a [u8])>),
+  HsetNx(&'a str, &'a [u8], &'a [u8]),
+  Hget(&'a str, &'a [u8]),
+  Hmget(&'a str, Vec<&'a [u8]>),
+  Hlen(&'a str),
+  Hkeys(&'a str),
+  Hvals(&'a str),
+  Hscan(&'a str, usize, ScanOptions<'a>),
+  Scan(usize, ScanOptions<'a>),
+  /// KEYS pattern.
+  Keys(&'a str),
+  /// HRANDFIELD key, count and WITHVALUES.
+  HRandField(&'a str, Option<i64>, bool),
+  Sadd(&'a str, Vec<&'a [u8]>),
+  Sscan(&'a str, usize, ScanOptions<'a>),
+  SInterCard(Vec<&'a str>, Option<usize>),
+  Zadd(&'a str, Vec<(Score, &'a [u8])>),
+  Zscan(&'a str, usize, ScanOptions<'a>),
+  /// ZRANGEBYSCORE key, min, max, WITHSCORES and LIMIT offset count.
+  ZRangeByScore(&'a str, ScoreBound, ScoreBound, bool, Option<(usize, i64)>),
+  ZRangeByLex(&'a str, LexBound<'a>, LexBound<'a>, Option<(usize, i64)>),
+  ReplicaOf(&'a str, &'a str),
+  Lolwut,
+  Role,
+  /// CLUSTER INFO, SLOTS and SHARDS, answered as a standalone server so
+  /// cluster-aware clients can probe it.
+  ClusterInfo,
+  ClusterSlots,
+  ClusterShards,
+  MemoryUsage(&'a str),
+  MemoryStats,
+  MemoryDoctor,
+  ObjectRefCount(&'a str),
+  ObjectEncoding(&'a str),
+  ObjectFreq(&'a str),
+  /// DEBUG subcommand and its arguments, checked when executed so unknown
+  /// subcommands get a proper error.
+  Debug(&'a str, Vec<&'a str>),
+  SlowlogGet(Option<usize>),
+  SlowlogLen,
+  SlowlogReset,
+  ClientId,
+  ClientKill(KillFilter<'a>),
+  ClientNoEvict(bool),
+  Subscribe(Vec<&'a [u8]>),
+  Unsubscribe(Vec<&'a [u8]>),
+  Psubscribe(Vec<&'a [u8]>),
+  Punsubscribe(Vec<&'a [u8]>),
+  Publish(&'a [u8], &'a [u8]),
+  /// PUBSUB CHANNELS, the active channels matching the optional pattern.
+  PubSubChannels(Option<&'a [u8]>),
+  PubSubNumSub(Vec<&'a [u8]>),
+  PubSubNumPat,
+  Quit,
+  Monitor,
+  BgRewriteAof,
+  Multi,
+  Exec,
+  Discard,
+  Reset,
+  /// HELLO and the protocol version to switch to, if any.
+  Hello(Option<i64>),
+  FlushAll,
+  /// WAITAOF numlocal, numreplicas and timeout in milliseconds.
+  WaitAof(usize, usize, u64),
+  Save,
+  LastSave,
+  Shutdown(bool),
+  /// FAILOVER, or FAILOVER ABORT when set.
+  Failover(bool),
+}
+
+/// Which connections CLIENT KILL targets.
+#[derive(Debug, PartialEq, Eq)]
+pub enum KillFilter<'a> {
+  Id(u64),
+  Addr(&'a str),
+}
+
+/// TTL change requested by GETEX.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Expiry {
+  /// Expire in that many seconds.
+  Ex(u64),
+  /// Expire in that many milliseconds.
+  Px(u64),
+  /// Expire at that unix time in milliseconds.
+  PxAt(u64),
+  Persist,
+}
+
+/// Only set the key if it doesn't exist yet (NX) or already does (XX).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetCondition {
+  Nx,
+  Xx,
+}
+
+/// NX, XX, GET, KEEPTTL, EX, PX and PXAT arguments of SET.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SetOptions {
+  pub condition: Option<SetCondition>,
+  /// Reply with the value the key held before.
+  pub get: bool,
+  /// Leave the key's expiry alone instead of clearing it.
+  pub keep_ttl: bool,
+  /// Unix time in milliseconds the key expires at.
+  pub expire_at: Option<u64>,
+  /// Milliseconds from now the key expires in, from EX or PX. Made into
+  /// `expire_at` before the command runs.
+  pub ttl: Option<u64>,
+}
+
+/// MATCH, COUNT and TYPE arguments shared by the SCAN family.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ScanOptions<'a> {
+  pub pattern: Option<&'a [u8]>,
+  pub count: Option<usize>,
+  /// Type name keys must have, only SCAN looks at it.
+  pub kind: Option<&'a str>,
+}
+
+/// ALPHA, ASC/DESC and LIMIT offset count of SORT.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SortOptions {
+  /// Compare elements as bytes rather than as numbers.
+  pub alpha: bool,
+  pub desc: bool,
+  pub limit: Option<(usize, i64)>,
+}
+
+/// Where a command's keys are among its arguments, the name being 0.
+enum KeySpec {
+  NoKeys,
+  /// Every `step`th argument from `first` to `last`, a negative `last`
+  /// counting from the end.
+  Range(usize, i64, usize),
+  /// A key count at this index, followed by that many keys.
+  NumKeys(usize),
+}
+
+const READONLY: &[&str] = &["readonly"];
+const READONLY_FAST: &[&str] = &["readonly", "fast"];
+const WRITE: &[&str] = &["write"];
+const WRITE_FAST: &[&str] = &["write", "fast"];
+const WRITE_DENYOOM: &[&str] = &["write", "denyoom"];
+const WRITE_DENYOOM_FAST: &[&str] = &["write", "denyoom", "fast"];
+const FAST: &[&str] = &["fast"];
+const NO_FLAGS: &[&str] = &[];
+
+/// Every implemented command by its lowercase name, with its arity, where its
+/// keys are and its flags. COMMAND LIST, COUNT, GETKEYS and INFO all go by
+/// it, and the `write` flag decides what is journaled and what a read-only
+/// replica turns down.
+static COMMANDS: &[(&str, i64, KeySpec, &[&str])] = &[
+  ("get", 2, KeySpec::Range(1, 1, 1), READONLY_FAST),
+  ("mget", -2, KeySpec::Range(1, -1, 1), READONLY_FAST),
+  ("set", -3, KeySpec::Range(1, 1, 1), WRITE_DENYOOM),
+  ("append", 3, KeySpec::Range(1, 1, 1), WRITE_DENYOOM_FAST),
+  ("setrange", 4, KeySpec::Range(1, 1, 1), WRITE_DENYOOM),
+  ("bitpos", -3, KeySpec::Range(1, 1, 1), READONLY),
+  ("setex", 4, KeySpec::Range(1, 1, 1), WRITE_DENYOOM),
+  ("getex", -2, KeySpec::Range(1, 1, 1), WRITE_FAST),
+  ("incr", 2, KeySpec::Range(1, 1, 1), WRITE_DENYOOM_FAST),
+  (
+    "incrbyfloat",
+    3,
+    KeySpec::Range(1, 1, 1),
+    WRITE_DENYOOM_FAST,
+  ),
+  ("decr", 2, KeySpec::Range(1, 1, 1), WRITE_DENYOOM_FAST),
+  ("lpush", -3, KeySpec::Range(1, 1, 1), WRITE_DENYOOM_FAST),
+  ("rpush", -3, KeySpec::Range(1, 1, 1), WRITE_DENYOOM_FAST),
+  ("lpushx", -3, KeySpec::Range(1, 1, 1), WRITE_DENYOOM_FAST),
+  ("rpushx", -3, KeySpec::Range(1, 1, 1), WRITE_DENYOOM_FAST),
+  ("lpop", -2, KeySpec::Range(1, 1, 1), WRITE_FAST),
+  ("rpop", -2, KeySpec::Range(1, 1, 1), WRITE_FAST),
+  ("llen", 2, KeySpec::Range(1, 1, 1), READONLY_FAST),
+  ("lindex", 3, KeySpec::Range(1, 1, 1), READONLY),
+  ("lrange", 4, KeySpec::Range(1, 1, 1), READONLY),
+  ("sort", -2, KeySpec::Range(1, 1, 1), READONLY),
+  ("hget", 3, KeySpec::Range(1, 1, 1), READONLY_FAST),
+  ("hset", -4, KeySpec::Range(1, 1, 1), WRITE_DENYOOM_FAST),
+  ("hsetnx", 4, KeySpec::Range(1, 1, 1), WRITE_DENYOOM_FAST),
+  ("hmget", -3, KeySpec::Range(1, 1, 1), READONLY_FAST),
+  ("hlen", 2, KeySpec::Range(1, 1, 1), READONLY_FAST),
+  ("hkeys", 2, KeySpec::Range(1, 1, 1), READONLY),
+  ("hvals", 2, KeySpec::Range(1, 1, 1), READONLY),
+  ("hscan", -3, KeySpec::Range(1, 1, 1), READONLY),
+  ("hrandfield", -2, KeySpec::Range(1, 1, 1), READONLY),
+  ("sadd", -3, KeySpec::Range(1, 1, 1), WRITE_DENYOOM_FAST),
+  ("sscan", -3, KeySpec::Range(1, 1, 1), READONLY),
+  ("zadd", -4, KeySpec::Range(1, 1, 1), WRITE_DENYOOM_FAST),
+  ("zscan", -3, KeySpec::Range(1, 1, 1), READONLY),
+  ("zrangebyscore", -4, KeySpec::Range(1, 1, 1), READONLY),
+  ("zrangebylex", -4, KeySpec::Range(1, 1, 1), READONLY),
+  ("del", -2, KeySpec::Range(1, -1, 1), WRITE),
+  ("copy", -3, KeySpec::Range(1, 2, 1), WRITE_DENYOOM),
+  ("object", -2, KeySpec::Range(2, 2, 1), READONLY),
+  ("memory", -2, KeySpec::Range(2, 2, 1), READONLY),
+  ("sintercard", -3, KeySpec::NumKeys(1), READONLY),
+  ("ping", -1, KeySpec::NoKeys, FAST),
+  ("command", -1, KeySpec::NoKeys, NO_FLAGS),
+  ("scan", -2, KeySpec::NoKeys, READONLY),
+  ("keys", 2, KeySpec::NoKeys, READONLY),
+  ("dbsize", 1, KeySpec::NoKeys, READONLY_FAST),
+  ("info", -1, KeySpec::NoKeys, NO_FLAGS),
+  ("config", -2, KeySpec::NoKeys, NO_FLAGS),
+  ("replicaof", 3, KeySpec::NoKeys, NO_FLAGS),
+  ("lolwut", -1, KeySpec::NoKeys, READONLY_FAST),
+  ("role", 1, KeySpec::NoKeys, FAST),
+  ("debug", -2, KeySpec::NoKeys, NO_FLAGS),
+  ("slowlog", -2, KeySpec::NoKeys, NO_FLAGS),
+  ("client", -2, KeySpec::NoKeys, NO_FLAGS),
+  ("cluster", -2, KeySpec::NoKeys, NO_FLAGS),
+  ("subscribe", -2, KeySpec::NoKeys, NO_FLAGS),
+  ("unsubscribe", -1, KeySpec::NoKeys, NO_FLAGS),
+  ("psubscribe", -2, KeySpec::NoKeys, NO_FLAGS),
+  ("punsubscribe", -1, KeySpec::NoKeys, NO_FLAGS),
+  ("publish", 3, KeySpec::NoKeys, FAST),
+  ("pubsub", -2, KeySpec::NoKeys, NO_FLAGS),
+  ("quit", -1, KeySpec::NoKeys, FAST),
+  ("monitor", 1, KeySpec::NoKeys, NO_FLAGS),
+  ("multi", 1, KeySpec::NoKeys, FAST),
+  ("exec", 1, KeySpec::NoKeys, NO_FLAGS),
+  ("discard", 1, KeySpec::NoKeys, FAST),
+  ("reset", 1, KeySpec::NoKeys, FAST),
+  ("hello", -1, KeySpec::NoKeys, FAST),
+  ("flushall", -1, KeySpec::NoKeys, WRITE),
+  ("waitaof", 4, KeySpec::NoKeys, NO_FLAGS),
+  ("save", 1, KeySpec::NoKeys, NO_FLAGS),
+  ("bgrewriteaof", 1, KeySpec::NoKeys, NO_FLAGS),
+  ("lastsave", 1, KeySpec::NoKeys, FAST),
+  ("shutdown", -1, KeySpec::NoKeys, NO_FLAGS),
+  ("failover", -1, KeySpec::NoKeys, NO_FLAGS),
+];
+
+fn key_spec(name: &str) -> Option<&'static KeySpec> {
+  match COMMANDS.iter().find(|(command, ..)| *command == name) {
+    Some((_, _, spec, _)) => Some(spec),
+    // not implemented, but tools ask for its keys all the same
+    None if name == "mset" => Some(&KeySpec::Range(1, -1, 2)),
+    None => None,
+  }
+}
+
+/// Names of every implemented command, lowercase.
+pub fn names() -> impl Iterator<Item = &'static str> {
+  COMMANDS.iter().map(|(name, ..)| *name)
+}
+
+/// The COMMAND INFO entry of `name`: its name, arity, flags and the first
+/// key, last key and step, or nil for a command that isn't implemented.
+pub fn info(name: &str) -> RedisValue {
+  let name = name.to_lowercase();
+  let Some((name, arity, spec, flags)) = COMMANDS.iter().find(|(command, ..)| *command == name)
+  else {
+    return RedisValue::NullBulk;
+  };
+  let (first, last, step) = match spec {
+    KeySpec::Range(first, last, step) => (*first as i64, *last, *step as i64),
+    // the key count comes first, there is no fixed range to report
+    KeySpec::NoKeys | KeySpec::NumKeys(_) => (0, 0, 0),
+  };
+  RedisValue::Nested(vec![
+    RedisValue::from(name.as_bytes().to_vec()),
+    RedisValue::Integer(*arity),
+    RedisValue::Nested(
+      flags
+        .iter()
+        .map(|flag| RedisValue::Status((*flag).into()))
+        .collect(),
+    ),
+    RedisValue::Integer(first),
+    RedisValue::Integer(last),
+    RedisValue::Integer(step),
+  ])
+}
+
+/// Flags of the command `name`, lowercase, none for an unknown one.
+/// The arity of `name` in the table, negative for at least that many
+/// arguments, name included.
+pub fn arity(name: &str) -> Option<i64> {
+  COMMANDS
+    .iter()
+    .find(|(command, ..)| *command == name)
+    .map(|(_, arity, ..)| *arity)
+}
+
+fn flags(name: &str) -> &'static [&'static str] {
+  COMMANDS
+    .iter()
+    .find(|(command, ..)| *command == name)
+    .map_or(NO_FLAGS, |(.., flags)| flags)
+}
+
+/// The key arguments of the command `args`, as COMMAND GETKEYS reports.
+pub fn keys<'a>(args: &[&'a str]) -> Result<Vec<&'a str>, RedisError> {
+  let name = args
+    .first()
+    .map(|name| name.to_lowercase())
+    .unwrap_or_default();
+  let arity = || RedisError::Other("Invalid number of arguments specified for command".to_string());
+  let keys = match key_spec(&name) {
+    None => return Err(RedisError::Other("Invalid command specified".to_string())),
+    Some(&KeySpec::NoKeys) => Vec::new(),
+    Some(&KeySpec::Range(first, last, step)) => {
+      let last = if last < 0 {
+        args.len() as i64 + last
+      } else {
+        last
+      };
+      if last < first as i64 || last as usize >= args.len() {
+        return Err(arity());
+      }
+      args[first..=last as usize]
+        .iter()
+        .step_by(step)
+        .copied()
+        .collect()
+    }
+    Some(&KeySpec::NumKeys(index)) => {
+      let count = args
+        .get(index)
+        .and_then(|n| n.parse::<usize>().ok())
+        .ok_or_else(arity)?;
+      args
+        .get(index + 1..index + 1 + count)
+        .ok_or_else(arity)?
+        .to_vec()
+    }
+  };
+  if keys.is_empty() {
+    return Err(RedisError::Other(
+      "The command has no key arguments".to_string(),
+    ));
+  }
+  Ok(keys)
+}
+
+/// Encodes `args` as a RESP multibulk, the way clients send commands.
+pub fn to_resp(args: &[&[u8]]) -> Vec<u8> {
+  let mut out = format!("*{}\r\n", args.len()).into_bytes();
+  for arg in args {
+    out.extend(format!("${}\r\n", arg.len()).as_bytes());
+    out.extend(*arg);
+    out.extend(b"\r\n");
+  }
+  out
+}
+
+impl<'a> Command<'a> {
+  /// Whether the command modifies the dataset, as its `write` flag says.
+  pub fn is_mutating(&self) -> bool {
+    // GETEX only writes when it changes the TTL
+    !matches!(self, Command::GetEx(_, None)) && flags(self.name()).contains(&"write")
+  }
+
+  /// The command with its relative TTL turned into the deadline that makes
+  /// at `now`, how the journal keeps it so a replay doesn't push the
+  /// deadline back. `None` for commands without one.
+  pub fn with_deadline(&self, now: u64) -> Option<Command<'a>> {
+    match *self {
+      Command::SetEx(key, value, ttl) if ttl > 0 => {
+        let options = SetOptions {
+          expire_at: Some(now + ttl as u64 * 1000),
+          ..Default::default()
+        };
+        Some(Command::SetWith(key, value, options))
+      }
+      Command::SetWith(key, value, ref options) if options.ttl.is_some() => {
+        let options = SetOptions {
+          condition: options.condition,
+          get: options.get,
+          keep_ttl: options.keep_ttl,
+          expire_at: options.ttl.map(|ttl| now + ttl),
+          ttl: None,
+        };
+        Some(Command::SetWith(key, value, options))
+      }
+      Command::GetEx(key, Some(Expiry::Ex(seconds))) if seconds > 0 => Some(Command::GetEx(
+        key,
+        Some(Expiry::PxAt(now + seconds * 1000)),
+      )),
+      Command::GetEx(key, Some(Expiry::Px(millis))) if millis > 0 => {
+        Some(Command::GetEx(key, Some(Expiry::PxAt(now + millis))))
+      }
+      _ => None,
+    }
+  }
+
+  /// The lowercase name the command is listed under.
+  pub fn name(&self) -> &'static str {
+    match self {
+      Command::Ping(_) => "ping",
+      Command::CommandDocs
+      | Command::DescribeCommands(_)
+      | Command::GetKeys(_)
+      | Command::ListCommands
+      | Command::CountCommands => "command",
+      Command::DbSize => "dbsize",
+      Command::Info(_) => "info",
+      Command::Config => "config",
+      Command::Get(_) => "get",
+      Command::MGet(_) => "mget",
+      Command::GetEx(..) => "getex",
+      Command::Set(..) | Command::SetWith(..) => "set",
+      Command::Append(..) => "append",
+      Command::SetRange(..) => "setrange",
+      Command::BitPos(..) => "bitpos",
+      Command::SetEx(..) => "setex",
+      Command::Lpush(..) => "lpush",
+      Command::Rpush(..) => "rpush",
+      Command::LpushX(..) => "lpushx",
+      Command::RpushX(..) => "rpushx",
+      Command::Lpop(..) => "lpop",
+      Command::Rpop(..) => "rpop",
+      Command::Llen(_) => "llen",
+      Command::Lindex(..) => "lindex",
+      Command::Lrange(..) => "lrange",
+      Command::Sort(..) => "sort",
+      Command::Del(_) => "del",
+      Command::Copy(..) => "copy",
+      Command::Incr(_) => "incr",
+      Command::IncrByFloat(..) => "incrbyfloat",
+      Command::Decr(_) => "decr",
+      Command::Hset(..) => "hset",
+      Command::HsetNx(..) => "hsetnx",
+      Command::Hget(..) => "hget",
+      Command::Hmget(..) => "hmget",
+      Command::Hlen(_) => "hlen",
+      Command::Hkeys(_) => "hkeys",
+      Command::Hvals(_) => "hvals",
+      Command::Hscan(..) => "hscan",
+      Command::Scan(..) => "scan",
+      Command::Keys(_) => "keys",
+      Command::HRandField(..) => "hrandfield",
+      Command::Sadd(..) => "sadd",
+      Command::Sscan(..) => "sscan",
+      Command::SInterCard(..) => "sintercard",
+      Command::Zadd(..) => "zadd",
+      Command::Zscan(..) => "zscan",
+      Command::ZRangeByScore(..) => "zrangebyscore",
+      Command::ZRangeByLex(..) => "zrangebylex",
+      Command::ReplicaOf(..) => "replicaof",
+      Command::Lolwut => "lolwut",
+      Command::Role => "role",
+      Command::ClusterInfo | Command::ClusterSlots | Command::ClusterShards => "cluster",
+      Command::MemoryUsage(_) | Command::MemoryStats | Command::MemoryDoctor => "memory",
+      Command::ObjectRefCount(_) | Command::ObjectEncoding(_) | Command::ObjectFreq(_) => "object",
+      Command::Debug(..) => "debug",
+      Command::SlowlogGet(_) | Command::SlowlogLen | Command::SlowlogReset => "slowlog",
+      Command::ClientId | Command::ClientKill(_) | Command::ClientNoEvict(_) => "client",
+      Command::Subscribe(_) => "subscribe",
+      Command::Unsubscribe(_) => "unsubscribe",
+      Command::Psubscribe(_) => "psubscribe",
+      Command::Punsubscribe(_) => "punsubscribe",
+      Command::Publish(..) => "publish",
+      Command::PubSubChannels(_) | Command::PubSubNumSub(_) | Command::PubSubNumPat => "pubsub",
+      Command::Quit => "quit",
+      Command::Monitor => "monitor",
+      Command::BgRewriteAof => "bgrewriteaof",
+      Command::Multi => "multi",
+      Command::Exec => "exec",
+      Command::Discard => "discard",
+      Command::Reset => "reset",
+      Command::Hello(_) => "hello",
+      Command::FlushAll => "flushall",
+      Command::WaitAof(..) => "waitaof",
+      Command::Save => "save",
+      Command::LastSave => "lastsave",
+      Command::Shutdown(_) => "shutdown",
+      Command::Failover(_) => "failover",
+    }
+  }
+
+  /// Keys the command reads or writes, whose access frequency it bumps.
+  /// OBJECT and MEMORY USAGE inspect their key without accessing it.
+  pub fn accessed_keys(&self) -> Vec<&'a str> {
+    match self {
+      Command::Get(key)
+      | Command::GetEx(key, _)
+      | Command::Set(key, _)
+      | Command::SetWith(key, ..)
+      | Command::Append(key, _)
+      | Command::SetRange(key, ..)
+      | Command::BitPos(key, ..)
+      | Command::SetEx(key, ..)
+      | Command::Lpush(key, _)
+      | Command::Rpush(key, _)
+      | Command::LpushX(key, _)
+      | Command::RpushX(key, _)
+      | Command::Lpop(key, _)
+      | Command::Rpop(key, _)
+      | Command::Llen(key)
+      | Command::Lindex(key, _)
+      | Command::Lrange(key, ..)
+      | Command::Sort(key, _)
+      | Command::Incr(key)
+      | Command::IncrByFloat(key, _)
+      | Command::Decr(key)
+      | Command::Hset(key, _)
+      | Command::HsetNx(key, ..)
+      | Command::Hget(key, _)
+      | Command::Hmget(key, _)
+      | Command::Hlen(key)
+      | Command::Hkeys(key)
+      | Command::Hvals(key)
+      | Command::Hscan(key, ..)
+      | Command::HRandField(key, ..)
+      | Command::Sadd(key, _)
+      | Command::Sscan(key, ..)
+      | Command::Zadd(key, _)
+      | Command::Zscan(key, ..)
+      | Command::ZRangeByScore(key, ..)
+      | Command::ZRangeByLex(key, ..) => vec![*key],
+      Command::Del(keys) | Command::MGet(keys) | Command::SInterCard(keys, _) => keys.clone(),
+      Command::Copy(source, destination, _) => vec![*source, *destination],
+      _ => Vec::new(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{info, keys, names, Command, Expiry};
+  use crate::err::RedisError;
+  use crate::value::RedisValue;
+
+  #[test]
+  fn test_keys() {
+    assert_eq!(keys(&["SET", "key", "value"]).unwrap(), vec!["key"]);
+    assert_eq!(keys(&["MSET", "a", "1", "b", "2"]).unwrap(), vec!["a", "b"]);
+    assert_eq!(keys(&["DEL", "a", "b", "c"]).unwrap(), vec!["a", "b", "c"]);
+    assert_eq!(
+      keys(&["SINTERCARD", "2", "a", "b"]).unwrap(),
+      vec!["a", "b"]
+    );
+    assert!(matches!(
+      keys(&["PING"]),
+      Err(RedisError::Other(e)) if e == "The command has no key arguments"
+    ));
+    assert!(matches!(keys(&["SET"]), Err(RedisError::Other(_))));
+    assert!(matches!(
+      keys(&["NOSUCH", "key"]),
+      Err(RedisError::Other(_))
+    ));
+  }
+
+  #[test]
+  fn test_names() {
+    let names = names().collect::<Vec<_>>();
+    for name in ["get", "set", "lpush", "command"] {
+      assert!(names.contains(&name));
+    }
+    assert!(!names.contains(&"mset"));
+  }
+
+  fn flags(name: &str) -> Vec<String> {
+    let RedisValue::Nested(entry) = info(name) else {
+      panic!("{name} has no COMMAND INFO entry");
+    };
+    let RedisValue::Nested(flags) = &entry[2] else {
+      panic!("{name} has no flags");
+    };
+    flags
+      .iter()
+      .map(|flag| match flag {
+        RedisValue::Status(flag) => flag.to_string(),
+        _ => panic!("{name} has a flag that isn't a status"),
+      })
+      .collect()
+  }
+
+  #[test]
+  fn test_info() {
+    assert!(flags("SET").contains(&"write".to_string()));
+    assert!(flags("get").contains(&"readonly".to_string()));
+    assert!(matches!(
+      info("set"),
+      RedisValue::Nested(entry) if matches!(entry[1], RedisValue::Integer(-3))
+        && matches!(entry[3], RedisValue::Integer(1))
+    ));
+    assert!(matches!(info("nosuch"), RedisValue::NullBulk));
+  }
+
+  #[test]
+  fn test_is_mutating() {
+    assert!(Command::Set("k", b"v").is_mutating());
+    assert!(Command::FlushAll.is_mutating());
+    assert!(Command::GetEx("k", Some(Expiry::Persist)).is_mutating());
+    assert!(!Command::GetEx("k", None).is_mutating());
+    assert!(!Command::Get("k").is_mutating());
+    for name in names() {
+      let flags = flags(name);
+      let both = ["readonly", "write"]
+        .iter()
+        .all(|f| flags.contains(&f.to_string()));
+      assert!(!both, "{name} is both readonly and write");
+    }
+  }
 }
@@ -1,210 +1,624 @@
-use crate::cmd::Command;
-use crate::err::RedisError;
-
-use std::{fmt, num::ParseIntError};
-
-use nom::{
-  branch::alt,
-  bytes::complete::{escaped, tag, tag_no_case, take, take_while},
-  character::complete::{alphanumeric1 as alphanumeric, char, digit0, one_of},
-  combinator::{cut, map, opt},
-  error::{
-    context, convert_error, ContextError, Error, ErrorKind, ParseError, VerboseError,
-    VerboseErrorKind,
-  },
-  multi::separated_list0,
-  number::complete::double,
-  sequence::{delimited, preceded, separated_pair, terminated},
-  Err, IResult,
+use crate::cmd::{
+  self, Command, Expiry, KillFilter, ScanOptions, SetCondition, SetOptions, SortOptions,
 };
+use crate::err::RedisError;
+use crate::zset::{LexBound, Score, ScoreBound};
 
-enum CmdCode {
-  Ping,
-  Set,
-  Get,
-  SetEx,
-  Lpush,
-  Rpush,
-  LpushX,
-  RpushX,
-  Lpop,
-  Rpop,
-  Hget,
-  Hset,
-  Del,
-  Incr,
-  DbSize,
-  Config,
-  CommandDocs,
+/// The arguments of a command left to parse, each one taken at most once.
+struct Args<'a> {
+  name: &'a str,
+  rest: &'a [Vec<u8>],
 }
 
-fn value_len<'a>(i: &'a str) -> IResult<&'a str, usize, ParseFailure> {
-  let (i, _) = tag("$")(i)?;
-  let (i, _u) = take_while(|c: char| c.is_numeric())(i)?;
-  let (i, _) = tag("\r\n")(i)?;
+impl<'a> Args<'a> {
+  fn is_empty(&self) -> bool {
+    self.rest.is_empty()
+  }
 
-  Ok((i, _u.parse::<usize>().unwrap()))
-}
+  /// The next argument if there is one.
+  fn opt_bytes(&mut self) -> Option<&'a [u8]> {
+    let (first, rest) = self.rest.split_first()?;
+    self.rest = rest;
+    Some(first)
+  }
 
-fn cmd<'a>(i: &str) -> IResult<&str, CmdCode, ParseFailure> {
-  let (i, _) = opt(value_len)(i)?;
-  let (i, v) = alt((
-    map(tag_no_case("PING"), |_| CmdCode::Ping),
-    map(tag_no_case("SETEX"), |_| CmdCode::SetEx),
-    map(tag_no_case("SET"), |_| CmdCode::Set),
-    map(tag_no_case("GET"), |_| CmdCode::Get),
-    map(tag_no_case("LPUSHX"), |_| CmdCode::LpushX),
-    map(tag_no_case("RPUSHX"), |_| CmdCode::RpushX),
-    map(tag_no_case("LPUSH"), |_| CmdCode::Lpush),
-    map(tag_no_case("RPUSH"), |_| CmdCode::Rpush),
-    map(tag_no_case("LPOP"), |_| CmdCode::Lpop),
-    map(tag_no_case("RPOP"), |_| CmdCode::Rpop),
-    map(tag_no_case("HGET"), |_| CmdCode::Hget),
-    map(tag_no_case("HSET"), |_| CmdCode::Hset),
-    map(tag_no_case("DEL"), |_| CmdCode::Del),
-    map(tag_no_case("INCR"), |_| CmdCode::Incr),
-    map(tag_no_case("DBSIZE"), |_| CmdCode::DbSize),
-    map(tag_no_case("COMMAND"), |_| CmdCode::CommandDocs),
-    map(tag_no_case("CONFIG"), |_| CmdCode::Config),
-  ))(i)?;
-  let (i, _) = tag("\r\n")(i)?;
-
-  Ok((i, v))
-}
+  /// The next argument as is, an arity error if there is none.
+  fn bytes(&mut self) -> Result<&'a [u8], RedisError> {
+    self.opt_bytes().ok_or_else(|| wrong_arity(self.name))
+  }
 
-fn u_number<'a>(i: &'a str) -> IResult<&'a str, usize, ParseFailure> {
-  let (i, v) = string(i)?;
-  Ok((i, v.parse::<usize>().unwrap()))
-}
+  /// The next argument, which keys and names have to be valid UTF-8 for.
+  fn string(&mut self) -> Result<&'a str, RedisError> {
+    std::str::from_utf8(self.bytes()?)
+      .map_err(|_| RedisError::Parse("argument is not valid UTF-8".to_string()))
+  }
 
-fn value(i: &str) -> IResult<&str, &str, ParseFailure> {
-  let (i, _) = tag("$")(i)?;
-  let (i, size_str) = digit0(i)?;
-  let str_size = size_str.parse::<usize>().unwrap();
-  let (i, _) = tag("\r\n")(i)?;
-  let value = &i[0..str_size];
+  fn i_number(&mut self) -> Result<i64, RedisError> {
+    self.string()?.parse::<i64>().map_err(|_| not_an_integer())
+  }
 
-  Ok((&i[str_size..], value))
+  fn u_number(&mut self) -> Result<usize, RedisError> {
+    self
+      .string()?
+      .parse::<usize>()
+      .map_err(|_| not_an_integer())
+  }
+
+  fn score(&mut self) -> Result<Score, RedisError> {
+    match self.string()?.parse::<f64>() {
+      Ok(score) if !score.is_nan() => Ok(Score(score)),
+      _ => Err(RedisError::Parse("value is not a valid float".to_string())),
+    }
+  }
+
+  /// Takes the next argument if it is `name`, case-insensitively.
+  fn keyword(&mut self, name: &str) -> bool {
+    match self.rest.split_first() {
+      Some((first, rest)) if first.eq_ignore_ascii_case(name.as_bytes()) => {
+        self.rest = rest;
+        true
+      }
+      _ => false,
+    }
+  }
+
+  fn opt_string(&mut self) -> Result<Option<&'a str>, RedisError> {
+    match self.is_empty() {
+      true => Ok(None),
+      false => self.string().map(Some),
+    }
+  }
+
+  fn opt_i_number(&mut self) -> Result<Option<i64>, RedisError> {
+    match self.is_empty() {
+      true => Ok(None),
+      false => self.i_number().map(Some),
+    }
+  }
+
+  /// Every argument left.
+  fn rest_bytes(&mut self) -> Vec<&'a [u8]> {
+    let rest = std::mem::take(&mut self.rest);
+    rest.iter().map(Vec::as_slice).collect()
+  }
+
+  fn rest_strings(&mut self) -> Result<Vec<&'a str>, RedisError> {
+    let mut strings = Vec::with_capacity(self.rest.len());
+    while !self.is_empty() {
+      strings.push(self.string()?);
+    }
+    Ok(strings)
+  }
+
+  /// The error for a subcommand of this command that doesn't exist.
+  fn unknown_subcommand(&self, subcommand: &[u8]) -> RedisError {
+    RedisError::Parse(format!(
+      "unknown subcommand '{}' for '{}'",
+      String::from_utf8_lossy(subcommand),
+      self.name.to_lowercase()
+    ))
+  }
 }
 
-fn string(i: &str) -> IResult<&str, &str, ParseFailure> {
-  let (i, value) = value(i)?;
-  let (i, _) = tag("\r\n")(i)?;
+fn not_an_integer() -> RedisError {
+  RedisError::Parse("value is not an integer or out of range".to_string())
+}
 
-  Ok((i, value))
+fn syntax_error() -> RedisError {
+  RedisError::Parse("syntax error".to_string())
 }
 
-fn push<'a, F>(i: &'a str, f: F) -> IResult<&'a str, Command, ParseFailure>
-where
-  F: Fn(&'a str, Vec<&'a [u8]>) -> Command<'a>,
-{
-  let (i, key) = string(i)?;
-  let (i, raw_values) = separated_list0(tag("\r\n"), value)(i)?;
-  let values = raw_values.iter().map(|v| v.as_bytes()).collect::<Vec<_>>();
+/// A score bound like `1.5`, `(1.5`, `-inf` or `+inf`.
+fn score_bound(args: &mut Args) -> Result<ScoreBound, RedisError> {
+  let v = args.string()?;
+  let (exclusive, v) = match v.strip_prefix('(') {
+    Some(v) => (true, v),
+    None => (false, v),
+  };
+  let not_a_float = || RedisError::Parse("min or max is not a float".to_string());
+  match v.parse::<f64>() {
+    Ok(score) if score.is_nan() => Err(not_a_float()),
+    Ok(score) if exclusive => Ok(ScoreBound::Exclusive(Score(score))),
+    Ok(score) => Ok(ScoreBound::Inclusive(Score(score))),
+    Err(_) => Err(not_a_float()),
+  }
+}
 
-  Ok((i, f(key, values)))
+/// A lexicographic bound: `-`, `+`, `[member` or `(member`.
+fn lex_bound<'a>(args: &mut Args<'a>) -> Result<LexBound<'a>, RedisError> {
+  let bound = match args.bytes()? {
+    b"-" => LexBound::Min,
+    b"+" => LexBound::Max,
+    [b'[', member @ ..] => LexBound::Inclusive(member),
+    [b'(', member @ ..] => LexBound::Exclusive(member),
+    _ => {
+      return Err(RedisError::Parse(
+        "min or max not valid string range item".to_string(),
+      ))
+    }
+  };
+  Ok(bound)
 }
 
-fn pop<'a, F>(i: &'a str, f: F) -> IResult<&'a str, Command<'a>, ParseFailure>
-where
-  F: Fn(&'a str, usize) -> Command<'a>,
-{
-  let (i, key) = string(i)?;
-  let (i, count) = u_number(i)?;
+/// `LIMIT offset count` if it comes next, a negative count meaning
+/// everything after offset.
+fn limit(args: &mut Args) -> Result<Option<(usize, i64)>, RedisError> {
+  if !args.keyword("LIMIT") {
+    return Ok(None);
+  }
+  Ok(Some((args.u_number()?, args.i_number()?)))
+}
 
-  Ok((i, f(key, count)))
+/// MATCH, COUNT and TYPE in any order.
+fn scan_options<'a>(args: &mut Args<'a>) -> Result<ScanOptions<'a>, RedisError> {
+  let mut options = ScanOptions::default();
+  loop {
+    if args.keyword("MATCH") {
+      options.pattern = Some(args.bytes()?);
+    } else if args.keyword("COUNT") {
+      options.count = Some(args.u_number()?);
+    } else if args.keyword("TYPE") {
+      options.kind = Some(args.string()?);
+    } else {
+      return Ok(options);
+    }
+  }
 }
 
-fn root<'a>(i: &'a str) -> IResult<&'a str, Command<'a>, ParseFailure> {
-  let (i, cmd) = cmd(i)?;
-  match cmd {
-    CmdCode::Set => {
-      let (i, key) = string(i)?;
-      let (i, value) = string(i)?;
-      Ok((i, Command::Set(key, value.as_bytes())))
-    }
-    CmdCode::Get => {
-      let (i, key) = string(i)?;
-      Ok((i, Command::Get(key)))
-    }
-    CmdCode::SetEx => {
-      let (i, key) = string(i)?;
-      let (i, ttl) = u_number(i)?;
-      let (i, value) = string(i)?;
-      let cmd = Command::SetEx(key, value.as_bytes(), ttl);
-      Ok((i, cmd))
-    }
-    CmdCode::Lpush => push(i, Command::Lpush),
-    CmdCode::Rpush => push(i, Command::Rpush),
-    CmdCode::LpushX => push(i, Command::LpushX),
-    CmdCode::RpushX => push(i, Command::RpushX),
-    CmdCode::Lpop => pop(i, Command::Lpop),
-    CmdCode::Rpop => pop(i, Command::Rpop),
-    CmdCode::CommandDocs => Ok((i, Command::CommandDocs)),
-    CmdCode::Ping => Ok((i, Command::Ping)),
-    CmdCode::Incr => {
-      let (i, key) = string(i)?;
-      Ok((i, Command::Incr(key)))
-    }
-    CmdCode::Del => {
-      let (i, raw_values) = separated_list0(tag("\r\n"), value)(i)?;
-      let values = raw_values.to_vec();
-      Ok((i, Command::Del(values)))
-    }
-    CmdCode::DbSize => Ok((i, Command::DbSize)),
-    CmdCode::Hget => {
-      let (i, key) = string(i)?;
-      let (i, value) = string(i)?;
-      Ok((i, Command::Set(key, value.as_bytes())))
-    }
-    CmdCode::Config => Ok((i, Command::Config)),
-    _ => todo!(),
+/// ALPHA, ASC, DESC and LIMIT in any order, the last of ASC and DESC
+/// winning.
+fn sort_options(args: &mut Args) -> Result<SortOptions, RedisError> {
+  let mut options = SortOptions::default();
+  loop {
+    if args.keyword("ALPHA") {
+      options.alpha = true;
+    } else if args.keyword("ASC") {
+      options.desc = false;
+    } else if args.keyword("DESC") {
+      options.desc = true;
+    } else if let Some(window) = limit(args)? {
+      options.limit = Some(window);
+    } else {
+      return Ok(options);
+    }
   }
 }
 
-#[derive(Debug)]
-pub struct ParseFailure(String);
+/// NX, XX, GET, KEEPTTL, EX, PX and PXAT in any order, at most one of
+/// the last four. `None` when none is given, anything else there is a
+/// syntax error.
+fn set_options(args: &mut Args) -> Result<Option<SetOptions>, RedisError> {
+  let mut options = SetOptions::default();
+  let mut given = false;
+  // KEEPTTL, EX, PX and PXAT taken, at most one may be
+  let mut expiries = 0;
+  loop {
+    let condition = if args.keyword("NX") {
+      Some(SetCondition::Nx)
+    } else if args.keyword("XX") {
+      Some(SetCondition::Xx)
+    } else {
+      None
+    };
+    match (condition, options.condition) {
+      (Some(a), Some(b)) if a != b => return Err(syntax_error()),
+      (Some(condition), _) => options.condition = Some(condition),
+      (None, _) if args.keyword("GET") => options.get = true,
+      (None, _) if args.keyword("KEEPTTL") => {
+        options.keep_ttl = true;
+        expiries += 1;
+      }
+      (None, _) if args.keyword("PXAT") => {
+        options.expire_at = Some(args.u_number()? as u64);
+        expiries += 1;
+      }
+      (None, _) if args.keyword("EX") => {
+        let seconds = set_ttl(args)?;
+        options.ttl = Some(seconds.checked_mul(1000).ok_or_else(invalid_expire_time)?);
+        expiries += 1;
+      }
+      (None, _) if args.keyword("PX") => {
+        options.ttl = Some(set_ttl(args)?);
+        expiries += 1;
+      }
+      (None, _) if args.is_empty() => break,
+      (None, _) => return Err(syntax_error()),
+    }
+    given = true;
+  }
+  if expiries > 1 {
+    return Err(syntax_error());
+  }
+  Ok(given.then_some(options))
+}
 
-impl fmt::Display for ParseFailure {
-  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    write!(f, "parsing failure: `{:?}`", self.0)
+/// The positive number after EX or PX.
+fn set_ttl(args: &mut Args) -> Result<u64, RedisError> {
+  match args.u_number()? {
+    0 => Err(invalid_expire_time()),
+    ttl => Ok(ttl as u64),
   }
 }
 
-pub fn parse(i: &str) -> Result<Command, RedisError> {
-  let (_, cmd) = root(i)?;
-  Ok(cmd)
+fn invalid_expire_time() -> RedisError {
+  RedisError::Parse("invalid expire time in 'set' command".to_string())
 }
 
-impl From<nom::Err<ParseFailure>> for RedisError {
-  fn from(value: nom::Err<ParseFailure>) -> Self {
-    match value {
-      Err::Incomplete(_) => todo!(),
-      Err::Error(e) => RedisError::Parse(format!("{e}")),
-      Err::Failure(_) => todo!(),
+fn client<'a>(args: &mut Args<'a>) -> Result<Command<'a>, RedisError> {
+  let subcommand = args.bytes()?;
+  match subcommand.to_ascii_uppercase().as_slice() {
+    b"ID" => Ok(Command::ClientId),
+    b"KILL" => {
+      let filter = if args.keyword("ID") {
+        KillFilter::Id(args.u_number()? as u64)
+      } else {
+        args.keyword("ADDR");
+        KillFilter::Addr(args.string()?)
+      };
+      Ok(Command::ClientKill(filter))
     }
+    b"NO-EVICT" => match args.bytes()?.to_ascii_uppercase().as_slice() {
+      b"ON" => Ok(Command::ClientNoEvict(true)),
+      b"OFF" => Ok(Command::ClientNoEvict(false)),
+      _ => Err(syntax_error()),
+    },
+    _ => Err(args.unknown_subcommand(subcommand)),
   }
 }
 
-impl From<ParseIntError> for ParseFailure {
-  fn from(value: ParseIntError) -> Self {
-    ParseFailure(format!("can't parse int: {value}"))
-  }
+fn wrong_arity(name: &str) -> RedisError {
+  RedisError::Parse(format!(
+    "wrong number of arguments for '{}' command",
+    name.to_lowercase()
+  ))
+}
+
+fn push<'a, F>(args: &mut Args<'a>, f: F) -> Result<Command<'a>, RedisError>
+where
+  F: Fn(&'a str, Vec<&'a [u8]>) -> Command<'a>,
+{
+  let key = args.string()?;
+  Ok(f(key, args.rest_bytes()))
+}
+
+fn pop<'a, F>(args: &mut Args<'a>, f: F) -> Result<Command<'a>, RedisError>
+where
+  F: Fn(&'a str, Option<usize>) -> Command<'a>,
+{
+  let key = args.string()?;
+  let count = match args.opt_i_number()?.map(usize::try_from) {
+    Some(Err(_)) => {
+      return Err(RedisError::Parse(
+        "value is out of range, must be positive".to_string(),
+      ))
+    }
+    count => count.map(Result::unwrap),
+  };
+  Ok(f(key, count))
 }
 
-impl ParseError<&str> for ParseFailure {
-  fn from_error_kind(input: &str, kind: ErrorKind) -> Self {
-    ParseFailure(format!("{:?}, {}", input, kind.description()))
+/// Parses the command `args`, its name first, dispatching on the name
+/// case-insensitively. Keys and other names have to be UTF-8, values may be
+/// any bytes. The argument count has to fit the arity in the command table
+/// and arguments left over once the command is complete are a syntax error.
+pub fn parse(args: &[Vec<u8>]) -> Result<Command<'_>, RedisError> {
+  let (name, rest) = args
+    .split_first()
+    .ok_or_else(|| RedisError::Parse("empty command".to_string()))?;
+  let name =
+    std::str::from_utf8(name).map_err(|_| RedisError::Parse("unknown command".to_string()))?;
+  if let Some(arity) = cmd::arity(&name.to_lowercase()) {
+    let given = args.len() as i64;
+    if (arity >= 0 && given != arity) || given < -arity {
+      return Err(wrong_arity(name));
+    }
   }
+  let args = &mut Args { name, rest };
 
-  fn append(input: &str, kind: ErrorKind, other: Self) -> Self {
-    ParseFailure(format!(
-      "{}, kind = {}, other = {}",
-      input,
-      kind.description(),
-      other
-    ))
+  let command = match name.to_ascii_uppercase().as_str() {
+    "PING" => Ok(Command::Ping(args.opt_bytes())),
+    "SET" => {
+      let key = args.string()?;
+      let value = args.bytes()?;
+      match set_options(args)? {
+        None => Ok(Command::Set(key, value)),
+        Some(options) => Ok(Command::SetWith(key, value, options)),
+      }
+    }
+    "SETEX" => {
+      let key = args.string()?;
+      let ttl = args.u_number()?;
+      let value = args.bytes()?;
+      Ok(Command::SetEx(key, value, ttl))
+    }
+    "APPEND" => Ok(Command::Append(args.string()?, args.bytes()?)),
+    "SETRANGE" => {
+      let key = args.string()?;
+      let offset = usize::try_from(args.i_number()?)
+        .map_err(|_| RedisError::Parse("offset is out of range".to_string()))?;
+      Ok(Command::SetRange(key, offset, args.bytes()?))
+    }
+    "GET" => Ok(Command::Get(args.string()?)),
+    "MGET" => {
+      let mut keys = vec![args.string()?];
+      keys.extend(args.rest_strings()?);
+      Ok(Command::MGet(keys))
+    }
+    "GETEX" => {
+      let key = args.string()?;
+      let expiry = if args.keyword("EX") {
+        Some(Expiry::Ex(args.u_number()? as u64))
+      } else if args.keyword("PX") {
+        Some(Expiry::Px(args.u_number()? as u64))
+      } else if args.keyword("PXAT") {
+        Some(Expiry::PxAt(args.u_number()? as u64))
+      } else if args.keyword("PERSIST") {
+        Some(Expiry::Persist)
+      } else {
+        None
+      };
+      Ok(Command::GetEx(key, expiry))
+    }
+    "BITPOS" => {
+      let key = args.string()?;
+      let bit = match args.bytes()? {
+        b"0" => false,
+        b"1" => true,
+        _ => {
+          return Err(RedisError::Parse(
+            "The bit argument must be 1 or 0.".to_string(),
+          ))
+        }
+      };
+      let start = args.opt_i_number()?;
+      let end = args.opt_i_number()?;
+      let range = start.map(|start| (start, end.unwrap_or(-1)));
+      Ok(Command::BitPos(key, bit, range))
+    }
+    "LPUSH" => push(args, Command::Lpush),
+    "RPUSH" => push(args, Command::Rpush),
+    "LPUSHX" => push(args, Command::LpushX),
+    "RPUSHX" => push(args, Command::RpushX),
+    "LPOP" => pop(args, Command::Lpop),
+    "RPOP" => pop(args, Command::Rpop),
+    "LLEN" => Ok(Command::Llen(args.string()?)),
+    "LINDEX" => Ok(Command::Lindex(args.string()?, args.i_number()?)),
+    "LRANGE" => {
+      let key = args.string()?;
+      Ok(Command::Lrange(key, args.i_number()?, args.i_number()?))
+    }
+    "SORT" => {
+      let key = args.string()?;
+      Ok(Command::Sort(key, sort_options(args)?))
+    }
+    "COMMAND" => {
+      if args.keyword("GETKEYS") {
+        Ok(Command::GetKeys(args.rest_strings()?))
+      } else if args.keyword("LIST") {
+        Ok(Command::ListCommands)
+      } else if args.keyword("COUNT") {
+        Ok(Command::CountCommands)
+      } else if args.keyword("INFO") {
+        Ok(Command::DescribeCommands(args.rest_strings()?))
+      } else {
+        // bare COMMAND or COMMAND DOCS, for any commands named
+        args.rest_bytes();
+        Ok(Command::CommandDocs)
+      }
+    }
+    "INCR" => Ok(Command::Incr(args.string()?)),
+    "INCRBYFLOAT" => Ok(Command::IncrByFloat(args.string()?, args.score()?)),
+    "DECR" => Ok(Command::Decr(args.string()?)),
+    "DEL" => Ok(Command::Del(args.rest_strings()?)),
+    "COPY" => {
+      let source = args.string()?;
+      let destination = args.string()?;
+      Ok(Command::Copy(source, destination, args.keyword("REPLACE")))
+    }
+    "DBSIZE" => Ok(Command::DbSize),
+    "HGET" => Ok(Command::Hget(args.string()?, args.bytes()?)),
+    "HSET" => {
+      let key = args.string()?;
+      if !args.rest.len().is_multiple_of(2) {
+        return Err(wrong_arity(name));
+      }
+      let mut pairs = Vec::new();
+      while !args.is_empty() {
+        pairs.push((args.bytes()?, args.bytes()?));
+      }
+      Ok(Command::Hset(key, pairs))
+    }
+    "HSETNX" => {
+      let key = args.string()?;
+      Ok(Command::HsetNx(key, args.bytes()?, args.bytes()?))
+    }
+    "HMGET" => {
+      let key = args.string()?;
+      Ok(Command::Hmget(key, args.rest_bytes()))
+    }
+    "HLEN" => Ok(Command::Hlen(args.string()?)),
+    "HKEYS" => Ok(Command::Hkeys(args.string()?)),
+    "HVALS" => Ok(Command::Hvals(args.string()?)),
+    "HSCAN" => {
+      let key = args.string()?;
+      let cursor = args.u_number()?;
+      Ok(Command::Hscan(key, cursor, scan_options(args)?))
+    }
+    "SSCAN" => {
+      let key = args.string()?;
+      let cursor = args.u_number()?;
+      Ok(Command::Sscan(key, cursor, scan_options(args)?))
+    }
+    "ZSCAN" => {
+      let key = args.string()?;
+      let cursor = args.u_number()?;
+      Ok(Command::Zscan(key, cursor, scan_options(args)?))
+    }
+    "KEYS" => Ok(Command::Keys(args.string()?)),
+    "SCAN" => {
+      let cursor = args.u_number()?;
+      Ok(Command::Scan(cursor, scan_options(args)?))
+    }
+    "HRANDFIELD" => {
+      let key = args.string()?;
+      match args.opt_i_number()? {
+        Some(count) => Ok(Command::HRandField(
+          key,
+          Some(count),
+          args.keyword("WITHVALUES"),
+        )),
+        None => Ok(Command::HRandField(key, None, false)),
+      }
+    }
+    "SADD" => {
+      let key = args.string()?;
+      Ok(Command::Sadd(key, args.rest_bytes()))
+    }
+    "SINTERCARD" => {
+      let numkeys = args.u_number()?;
+      let keys = (0..numkeys)
+        .map(|_| args.string())
+        .collect::<Result<Vec<_>, _>>()?;
+      let limit = match args.keyword("LIMIT") {
+        true => Some(args.u_number()?),
+        false => None,
+      };
+      Ok(Command::SInterCard(keys, limit))
+    }
+    "ZADD" => {
+      let key = args.string()?;
+      let mut pairs = Vec::new();
+      while !args.is_empty() {
+        pairs.push((args.score()?, args.bytes()?));
+      }
+      Ok(Command::Zadd(key, pairs))
+    }
+    "ZRANGEBYSCORE" => {
+      let key = args.string()?;
+      let min = score_bound(args)?;
+      let max = score_bound(args)?;
+      let with_scores = args.keyword("WITHSCORES");
+      let window = limit(args)?;
+      let with_scores = args.keyword("WITHSCORES") || with_scores;
+      Ok(Command::ZRangeByScore(key, min, max, with_scores, window))
+    }
+    "ZRANGEBYLEX" => {
+      let key = args.string()?;
+      let min = lex_bound(args)?;
+      let max = lex_bound(args)?;
+      Ok(Command::ZRangeByLex(key, min, max, limit(args)?))
+    }
+    "INFO" => Ok(Command::Info(args.opt_string()?)),
+    "CONFIG" => {
+      // a stub answering whatever is asked of it
+      args.rest_bytes();
+      Ok(Command::Config)
+    }
+    "REPLICAOF" => Ok(Command::ReplicaOf(args.string()?, args.string()?)),
+    "LOLWUT" => {
+      // VERSION and the art's dimensions, there being no art
+      args.rest_bytes();
+      Ok(Command::Lolwut)
+    }
+    "ROLE" => Ok(Command::Role),
+    "MEMORY" => {
+      let subcommand = args.bytes()?;
+      match subcommand.to_ascii_uppercase().as_slice() {
+        b"USAGE" => Ok(Command::MemoryUsage(args.string()?)),
+        b"STATS" => Ok(Command::MemoryStats),
+        b"DOCTOR" => Ok(Command::MemoryDoctor),
+        _ => Err(args.unknown_subcommand(subcommand)),
+      }
+    }
+    "OBJECT" => {
+      let subcommand = args.bytes()?;
+      match subcommand.to_ascii_uppercase().as_slice() {
+        b"REFCOUNT" => Ok(Command::ObjectRefCount(args.string()?)),
+        b"ENCODING" => Ok(Command::ObjectEncoding(args.string()?)),
+        b"FREQ" => Ok(Command::ObjectFreq(args.string()?)),
+        _ => Err(args.unknown_subcommand(subcommand)),
+      }
+    }
+    "DEBUG" => {
+      let subcommand = args.string()?;
+      Ok(Command::Debug(subcommand, args.rest_strings()?))
+    }
+    "SLOWLOG" => {
+      let subcommand = args.bytes()?;
+      match subcommand.to_ascii_uppercase().as_slice() {
+        b"GET" => match args.is_empty() {
+          true => Ok(Command::SlowlogGet(None)),
+          false => Ok(Command::SlowlogGet(Some(args.u_number()?))),
+        },
+        b"LEN" => Ok(Command::SlowlogLen),
+        b"RESET" => Ok(Command::SlowlogReset),
+        _ => Err(args.unknown_subcommand(subcommand)),
+      }
+    }
+    "CLIENT" => client(args),
+    "CLUSTER" => {
+      let subcommand = args.bytes()?;
+      match subcommand.to_ascii_uppercase().as_slice() {
+        b"INFO" => Ok(Command::ClusterInfo),
+        b"SLOTS" => Ok(Command::ClusterSlots),
+        b"SHARDS" => Ok(Command::ClusterShards),
+        _ => Err(args.unknown_subcommand(subcommand)),
+      }
+    }
+    "SUBSCRIBE" => Ok(Command::Subscribe(args.rest_bytes())),
+    "UNSUBSCRIBE" => Ok(Command::Unsubscribe(args.rest_bytes())),
+    "PSUBSCRIBE" => Ok(Command::Psubscribe(args.rest_bytes())),
+    "PUNSUBSCRIBE" => Ok(Command::Punsubscribe(args.rest_bytes())),
+    "PUBLISH" => Ok(Command::Publish(args.bytes()?, args.bytes()?)),
+    "PUBSUB" => {
+      let subcommand = args.bytes()?;
+      match subcommand.to_ascii_uppercase().as_slice() {
+        b"CHANNELS" => Ok(Command::PubSubChannels(args.opt_bytes())),
+        b"NUMSUB" => Ok(Command::PubSubNumSub(args.rest_bytes())),
+        b"NUMPAT" => Ok(Command::PubSubNumPat),
+        _ => Err(args.unknown_subcommand(subcommand)),
+      }
+    }
+    "QUIT" => Ok(Command::Quit),
+    "MONITOR" => Ok(Command::Monitor),
+    "BGREWRITEAOF" => Ok(Command::BgRewriteAof),
+    "MULTI" => Ok(Command::Multi),
+    "EXEC" => Ok(Command::Exec),
+    "DISCARD" => Ok(Command::Discard),
+    "RESET" => Ok(Command::Reset),
+    "HELLO" => match args.opt_string()? {
+      None => Ok(Command::Hello(None)),
+      Some(version) => match version.parse() {
+        Ok(version) => Ok(Command::Hello(Some(version))),
+        Err(_) => Err(RedisError::Parse(
+          "Protocol version is not an integer or out of range".to_string(),
+        )),
+      },
+    },
+    "FLUSHALL" => {
+      // always synchronous
+      let _ = args.keyword("ASYNC") || args.keyword("SYNC");
+      Ok(Command::FlushAll)
+    }
+    "WAITAOF" => {
+      let numlocal = args.u_number()?;
+      let numreplicas = args.u_number()?;
+      let timeout = args.u_number()?;
+      Ok(Command::WaitAof(numlocal, numreplicas, timeout as u64))
+    }
+    "SAVE" => Ok(Command::Save),
+    "LASTSAVE" => Ok(Command::LastSave),
+    "SHUTDOWN" => Ok(Command::Shutdown(args.keyword("NOSAVE"))),
+    "FAILOVER" => Ok(Command::Failover(args.keyword("ABORT"))),
+    _ => Err(RedisError::Parse(format!(
+      "unknown command '{}'",
+      name.to_lowercase()
+    ))),
+  }?;
+  if !args.is_empty() {
+    return Err(syntax_error());
   }
+  Ok(command)
 }
 
 #[cfg(test)]
@@ -212,42 +626,136 @@ mod tests {
   use super::*;
   use crate::cmd::Command;
 
+  fn args(args: &[&str]) -> Vec<Vec<u8>> {
+    args.iter().map(|arg| arg.as_bytes().to_vec()).collect()
+  }
+
   #[test]
   fn test_get() {
-    let raw_cmd = "$3\r\nGET\r\n$3\r\naaa\r\n";
-    assert_eq!(parse(raw_cmd).unwrap(), Command::Get("aaa"));
+    let cmd = args(&["GET", "aaa"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::Get("aaa"));
   }
 
   #[test]
-  fn test_ping() {
-    let raw_cmd = "PING\r\n";
-    assert_eq!(parse(raw_cmd).unwrap(), Command::Ping);
+  fn test_keys() {
+    let cmd = args(&["KEYS", "user:*"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::Keys("user:*"));
   }
 
   #[test]
-  fn test_set() {
-    let raw_cmd = "$3\r\nSET\r\n$3\r\naaa\r\n$3\r\naaa\r\n";
+  fn test_copy() {
+    let cmd = args(&["COPY", "a", "b"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::Copy("a", "b", false));
+
+    let cmd = args(&["COPY", "a", "b", "replace"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::Copy("a", "b", true));
+  }
+
+  #[test]
+  fn test_mget() {
+    let cmd = args(&["MGET", "a", "b"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::MGet(vec!["a", "b"]));
+    assert!(parse(&args(&["MGET"])).is_err());
+  }
+
+  #[test]
+  fn test_binary_args() {
+    let value = b"\xff\x00\r\n$3\r\n".to_vec();
+    let cmd = vec![b"SET".to_vec(), b"k".to_vec(), value.clone()];
+    assert_eq!(parse(&cmd).unwrap(), Command::Set("k", &value));
+
+    let cmd = vec![b"hset".to_vec(), b"h".to_vec(), vec![0], vec![0xfe]];
+    assert_eq!(
+      parse(&cmd).unwrap(),
+      Command::Hset("h", vec![(b"\x00", b"\xfe")])
+    );
+
+    let cmd = vec![b"GET".to_vec(), vec![0xff]];
+    assert!(matches!(parse(&cmd), Err(RedisError::Parse(_))));
+  }
+
+  #[test]
+  fn test_arity_and_unknown() {
+    assert!(matches!(
+      parse(&args(&["GET"])),
+      Err(RedisError::Parse(e)) if e == "wrong number of arguments for 'get' command"
+    ));
+    assert!(matches!(
+      parse(&args(&["NOSUCH", "k"])),
+      Err(RedisError::Parse(e)) if e == "unknown command 'nosuch'"
+    ));
+    assert!(matches!(
+      parse(&args(&["SETEX", "k", "ten", "v"])),
+      Err(RedisError::Parse(e)) if e == "value is not an integer or out of range"
+    ));
+    assert!(matches!(parse(&[]), Err(RedisError::Parse(_))));
+
+    let wrong_arity = |cmd: &[&str]| {
+      let name = cmd[0].to_lowercase();
+      matches!(
+        parse(&args(cmd)),
+        Err(RedisError::Parse(e)) if e == format!("wrong number of arguments for '{name}' command")
+      )
+    };
+    assert!(wrong_arity(&["GET", "a", "b"]));
+    assert!(wrong_arity(&["HSET", "h", "a"]));
+    assert!(wrong_arity(&["HSET", "h", "a", "1", "b"]));
+    assert!(wrong_arity(&["SADD", "s"]));
+    assert!(wrong_arity(&["DBSIZE", "x"]));
+    // within the arity, but nothing takes the extra argument
+    assert!(matches!(
+      parse(&args(&["GETEX", "k", "PERSIST", "x"])),
+      Err(RedisError::Parse(e)) if e == "syntax error"
+    ));
+    assert_eq!(
+      parse(&args(&["COMMAND", "DOCS", "get"])).unwrap(),
+      Command::CommandDocs
+    );
+    assert_eq!(
+      parse(&args(&["FLUSHALL", "ASYNC"])).unwrap(),
+      Command::FlushAll
+    );
+  }
+
+  #[test]
+  fn test_hello() {
+    assert_eq!(parse(&args(&["HELLO"])).unwrap(), Command::Hello(None));
     assert_eq!(
-      parse(raw_cmd).unwrap(),
-      Command::Set("aaa", "aaa".as_bytes())
+      parse(&args(&["hello", "3"])).unwrap(),
+      Command::Hello(Some(3))
     );
+    assert!(parse(&args(&["HELLO", "three"])).is_err());
+  }
+
+  #[test]
+  fn test_ping() {
+    let cmd = args(&["PING"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::Ping(None));
+
+    let cmd = args(&["PING", "hello"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::Ping(Some(b"hello")));
+  }
+
+  #[test]
+  fn test_set() {
+    let cmd = args(&["SET", "aaa", "aaa"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::Set("aaa", "aaa".as_bytes()));
   }
 
   #[test]
   fn test_setex() {
-    let raw_cmd = "$3\r\nSETEX\r\n$3\r\naaa\r\n$1\r\n5\r\n$3\r\naaa\r\n";
+    let cmd = args(&["SETEX", "aaa", "5", "aaa"]);
     assert_eq!(
-      parse(raw_cmd).unwrap(),
+      parse(&cmd).unwrap(),
       Command::SetEx("aaa", "aaa".as_bytes(), 5)
     );
   }
 
   #[test]
   fn test_lpush() {
-    let raw_cmd =
-      "$5\r\nLPUSH\r\n$3\r\naaa\r\n$1\r\n1\r\n$1\r\n2\r\n$1\r\n3\r\n$1\r\n4\r\n$1\r\n5\r\n";
+    let cmd = args(&["LPUSH", "aaa", "1", "2", "3", "4", "5"]);
     assert_eq!(
-      parse(raw_cmd).unwrap(),
+      parse(&cmd).unwrap(),
       Command::Lpush(
         "aaa",
         vec!["1", "2", "3", "4", "5"]
@@ -260,10 +768,9 @@ mod tests {
 
   #[test]
   fn test_rpush() {
-    let raw_cmd =
-      "$5\r\nRPUSH\r\n$3\r\naaa\r\n$1\r\n1\r\n$1\r\n2\r\n$1\r\n3\r\n$1\r\n4\r\n$1\r\n5\r\n";
+    let cmd = args(&["RPUSH", "aaa", "1", "2", "3", "4", "5"]);
     assert_eq!(
-      parse(raw_cmd).unwrap(),
+      parse(&cmd).unwrap(),
       Command::Rpush(
         "aaa",
         vec!["1", "2", "3", "4", "5"]
@@ -276,10 +783,9 @@ mod tests {
 
   #[test]
   fn test_lpushx() {
-    let raw_cmd =
-      "$6\r\nLPUSHX\r\n$3\r\naaa\r\n$1\r\n1\r\n$1\r\n2\r\n$1\r\n3\r\n$1\r\n4\r\n$1\r\n5\r\n";
+    let cmd = args(&["LPUSHX", "aaa", "1", "2", "3", "4", "5"]);
     assert_eq!(
-      parse(raw_cmd).unwrap(),
+      parse(&cmd).unwrap(),
       Command::LpushX(
         "aaa",
         vec!["1", "2", "3", "4", "5"]
@@ -292,10 +798,9 @@ mod tests {
 
   #[test]
   fn test_rpushx() {
-    let raw_cmd =
-      "$6\r\nRPUSHX\r\n$3\r\naaa\r\n$1\r\n1\r\n$1\r\n2\r\n$1\r\n3\r\n$1\r\n4\r\n$1\r\n5\r\n";
+    let cmd = args(&["RPUSHX", "aaa", "1", "2", "3", "4", "5"]);
     assert_eq!(
-      parse(raw_cmd).unwrap(),
+      parse(&cmd).unwrap(),
       Command::RpushX(
         "aaa",
         vec!["1", "2", "3", "4", "5"]
@@ -308,28 +813,513 @@ mod tests {
 
   #[test]
   fn test_lpop() {
-    let raw_cmd = "$4\r\nLPOP\r\n$2\r\naa\r\n$1\r\n2\r\n";
-    assert_eq!(parse(raw_cmd).unwrap(), Command::Lpop("aa", 2));
+    let cmd = args(&["LPOP", "aa", "2"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::Lpop("aa", Some(2)));
+
+    let cmd = args(&["LPOP", "aa"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::Lpop("aa", None));
+
+    let cmd = args(&["LPOP", "aa", "-1"]);
+    assert!(parse(&cmd).is_err());
   }
 
   #[test]
   fn test_rpop() {
-    let raw_cmd = "$4\r\nRPOP\r\n$2\r\naa\r\n$1\r\n2\r\n";
-    assert_eq!(parse(raw_cmd).unwrap(), Command::Rpop("aa", 2));
+    let cmd = args(&["RPOP", "aa", "2"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::Rpop("aa", Some(2)));
   }
 
   #[test]
   fn test_del() {
-    let raw_cmd = "$3\r\nDEL\r\n$3\r\naaa\r\n$3\r\nbbb\r\n$3\r\nccc\r\n";
+    let cmd = args(&["DEL", "aaa", "bbb", "ccc"]);
     assert_eq!(
-      parse(raw_cmd).unwrap(),
+      parse(&cmd).unwrap(),
       Command::Del(vec!["aaa", "bbb", "ccc"])
     );
   }
 
   #[test]
   fn test_conf() {
-    let raw_cmd = "$6\r\nCONFIG\r\n$3\r\nGET\r\n$3\r\nbbb\r\n";
-    assert_eq!(parse(raw_cmd).unwrap(), Command::Config);
+    let cmd = args(&["CONFIG", "GET", "bbb"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::Config);
+  }
+
+  #[test]
+  fn test_replicaof() {
+    let cmd = args(&["REPLICAOF", "NO", "ONE"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::ReplicaOf("NO", "ONE"));
+  }
+
+  #[test]
+  fn test_lolwut() {
+    let cmd = args(&["LOLWUT"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::Lolwut);
+  }
+
+  #[test]
+  fn test_hset() {
+    let cmd = args(&["HSET", "h", "a", "1", "b", "2"]);
+    assert_eq!(
+      parse(&cmd).unwrap(),
+      Command::Hset("h", vec![(b"a", b"1"), (b"b", b"2")])
+    );
+  }
+
+  #[test]
+  fn test_hsetnx() {
+    let cmd = args(&["HSETNX", "h", "a", "1"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::HsetNx("h", b"a", b"1"));
+  }
+
+  #[test]
+  fn test_hmget() {
+    let cmd = args(&["HMGET", "h", "a", "b"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::Hmget("h", vec![b"a", b"b"]));
+  }
+
+  #[test]
+  fn test_hscan() {
+    let cmd = args(&["HSCAN", "h", "0", "COUNT", "20", "MATCH", "a*"]);
+    assert_eq!(
+      parse(&cmd).unwrap(),
+      Command::Hscan(
+        "h",
+        0,
+        ScanOptions {
+          pattern: Some(b"a*"),
+          count: Some(20),
+          kind: None,
+        }
+      )
+    );
+  }
+
+  #[test]
+  fn test_sscan_zscan() {
+    let options = ScanOptions {
+      pattern: Some(b"m*"),
+      count: None,
+      kind: None,
+    };
+    let cmd = args(&["SSCAN", "s", "5", "MATCH", "m*"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::Sscan("s", 5, options));
+
+    let cmd = args(&["ZSCAN", "z", "0", "COUNT", "3"]);
+    assert_eq!(
+      parse(&cmd).unwrap(),
+      Command::Zscan(
+        "z",
+        0,
+        ScanOptions {
+          pattern: None,
+          count: Some(3),
+          kind: None,
+        }
+      )
+    );
+  }
+
+  #[test]
+  fn test_scan() {
+    let cmd = args(&["SCAN", "0", "TYPE", "list", "MATCH", "a*"]);
+    assert_eq!(
+      parse(&cmd).unwrap(),
+      Command::Scan(
+        0,
+        ScanOptions {
+          pattern: Some(b"a*"),
+          count: None,
+          kind: Some("list"),
+        }
+      )
+    );
+  }
+
+  #[test]
+  fn test_memory_usage() {
+    let cmd = args(&["MEMORY", "USAGE", "aaa"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::MemoryUsage("aaa"));
+  }
+
+  #[test]
+  fn test_lrange() {
+    let cmd = args(&["LRANGE", "aa", "0", "-1"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::Lrange("aa", 0, -1));
+  }
+
+  #[test]
+  fn test_sort() {
+    let cmd = args(&["SORT", "l"]);
+    assert_eq!(
+      parse(&cmd).unwrap(),
+      Command::Sort("l", SortOptions::default())
+    );
+
+    let cmd = args(&["SORT", "l", "LIMIT", "1", "2", "alpha", "DESC"]);
+    assert_eq!(
+      parse(&cmd).unwrap(),
+      Command::Sort(
+        "l",
+        SortOptions {
+          alpha: true,
+          desc: true,
+          limit: Some((1, 2)),
+        }
+      )
+    );
+  }
+
+  #[test]
+  fn test_client() {
+    let cmd = args(&["CLIENT", "KILL", "ID", "7"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::ClientKill(KillFilter::Id(7)));
+
+    let cmd = args(&["CLIENT", "KILL", "127.0.0.1:6380"]);
+    assert_eq!(
+      parse(&cmd).unwrap(),
+      Command::ClientKill(KillFilter::Addr("127.0.0.1:6380"))
+    );
+
+    let cmd = args(&["CLIENT", "NO-EVICT", "on"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::ClientNoEvict(true));
+
+    let cmd = args(&["CLUSTER", "info"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::ClusterInfo);
+    let cmd = args(&["CLUSTER", "SLOTS"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::ClusterSlots);
+  }
+
+  #[test]
+  fn test_failover() {
+    let cmd = args(&["FAILOVER"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::Failover(false));
+
+    let cmd = args(&["FAILOVER", "abort"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::Failover(true));
+  }
+
+  #[test]
+  fn test_shutdown() {
+    let cmd = args(&["SHUTDOWN"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::Shutdown(false));
+
+    let cmd = args(&["SHUTDOWN", "NOSAVE"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::Shutdown(true));
+  }
+
+  #[test]
+  fn test_object_refcount() {
+    let cmd = args(&["OBJECT", "refcount", "key"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::ObjectRefCount("key"));
+  }
+
+  #[test]
+  fn test_getex() {
+    let cmd = args(&["GETEX", "aaa"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::GetEx("aaa", None));
+
+    let cmd = args(&["GETEX", "aaa", "PX", "500"]);
+    assert_eq!(
+      parse(&cmd).unwrap(),
+      Command::GetEx("aaa", Some(Expiry::Px(500)))
+    );
+
+    let cmd = args(&["GETEX", "aaa", "PXAT", "1700000000000"]);
+    assert_eq!(
+      parse(&cmd).unwrap(),
+      Command::GetEx("aaa", Some(Expiry::PxAt(1_700_000_000_000)))
+    );
+
+    let cmd = args(&["GETEX", "aaa", "persist"]);
+    assert_eq!(
+      parse(&cmd).unwrap(),
+      Command::GetEx("aaa", Some(Expiry::Persist))
+    );
+  }
+
+  #[test]
+  fn test_sintercard() {
+    let cmd = args(&["SINTERCARD", "2", "a", "b"]);
+    assert_eq!(
+      parse(&cmd).unwrap(),
+      Command::SInterCard(vec!["a", "b"], None)
+    );
+
+    let cmd = args(&["SINTERCARD", "1", "a", "LIMIT", "3"]);
+    assert_eq!(
+      parse(&cmd).unwrap(),
+      Command::SInterCard(vec!["a"], Some(3))
+    );
+  }
+
+  #[test]
+  fn test_object_encoding() {
+    let cmd = args(&["OBJECT", "ENCODING", "key"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::ObjectEncoding("key"));
+  }
+
+  #[test]
+  fn test_object_freq() {
+    let cmd = args(&["OBJECT", "FREQ", "key"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::ObjectFreq("key"));
+  }
+
+  #[test]
+  fn test_debug() {
+    let cmd = args(&["DEBUG", "RELOAD"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::Debug("RELOAD", vec![]));
+
+    let cmd = args(&["DEBUG", "sleep", "0.5"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::Debug("sleep", vec!["0.5"]));
+  }
+
+  #[test]
+  fn test_slowlog() {
+    let cmd = args(&["SLOWLOG", "GET"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::SlowlogGet(None));
+
+    let cmd = args(&["SLOWLOG", "get", "5"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::SlowlogGet(Some(5)));
+  }
+
+  #[test]
+  fn test_info() {
+    let cmd = args(&["INFO"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::Info(None));
+
+    let cmd = args(&["INFO", "stats"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::Info(Some("stats")));
+  }
+
+  #[test]
+  fn test_memory_stats() {
+    let cmd = args(&["MEMORY", "stats"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::MemoryStats);
+
+    let cmd = args(&["MEMORY", "DOCTOR"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::MemoryDoctor);
+  }
+
+  #[test]
+  fn test_pubsub() {
+    let cmd = args(&["SUBSCRIBE", "a", "b"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::Subscribe(vec![b"a", b"b"]));
+
+    let cmd = args(&["UNSUBSCRIBE"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::Unsubscribe(vec![]));
+
+    let cmd = args(&["PUBLISH", "a", "hi"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::Publish(b"a", b"hi"));
+
+    let cmd = args(&["PUBSUB", "channels", "n*"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::PubSubChannels(Some(b"n*")));
+    let cmd = args(&["PUBSUB", "NUMSUB", "a", "b"]);
+    assert_eq!(
+      parse(&cmd).unwrap(),
+      Command::PubSubNumSub(vec![b"a", b"b"])
+    );
+    let cmd = args(&["PUBSUB", "NUMPAT"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::PubSubNumPat);
+  }
+
+  #[test]
+  fn test_quit() {
+    let cmd = args(&["quit"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::Quit);
+    assert_eq!(parse(&args(&["MONITOR"])).unwrap(), Command::Monitor);
+    assert_eq!(
+      parse(&args(&["BGREWRITEAOF"])).unwrap(),
+      Command::BgRewriteAof
+    );
+  }
+
+  #[test]
+  fn test_transactions() {
+    assert_eq!(parse(&args(&["MULTI"])).unwrap(), Command::Multi);
+    assert_eq!(parse(&args(&["EXEC"])).unwrap(), Command::Exec);
+    assert_eq!(parse(&args(&["DISCARD"])).unwrap(), Command::Discard);
+    assert_eq!(parse(&args(&["RESET"])).unwrap(), Command::Reset);
+  }
+
+  #[test]
+  fn test_command_getkeys() {
+    let cmd = args(&["COMMAND", "GETKEYS", "SET", "key", "v"]);
+    assert_eq!(
+      parse(&cmd).unwrap(),
+      Command::GetKeys(vec!["SET", "key", "v"])
+    );
+    assert_eq!(parse(&args(&["COMMAND"])).unwrap(), Command::CommandDocs);
+    let cmd = args(&["COMMAND", "list"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::ListCommands);
+    let cmd = args(&["COMMAND", "COUNT"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::CountCommands);
+    let cmd = args(&["COMMAND", "INFO", "get", "set"]);
+    assert_eq!(
+      parse(&cmd).unwrap(),
+      Command::DescribeCommands(vec!["get", "set"])
+    );
+  }
+
+  #[test]
+  fn test_every_listed_command_parses() {
+    for name in crate::cmd::names() {
+      let unknown = matches!(
+        parse(&args(&[name])),
+        Err(RedisError::Parse(e)) if e.starts_with("unknown command")
+      );
+      assert!(!unknown, "{name} isn't parsed");
+    }
+  }
+
+  #[test]
+  fn test_hrandfield() {
+    let cmd = args(&["HRANDFIELD", "h"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::HRandField("h", None, false));
+
+    let cmd = args(&["HRANDFIELD", "h", "-3", "WITHVALUES"]);
+    assert_eq!(
+      parse(&cmd).unwrap(),
+      Command::HRandField("h", Some(-3), true)
+    );
+  }
+
+  #[test]
+  fn test_zrange_bounds() {
+    let cmd = args(&[
+      "ZRANGEBYSCORE",
+      "z",
+      "(1.5",
+      "+inf",
+      "WITHSCORES",
+      "LIMIT",
+      "1",
+      "-1",
+    ]);
+    assert_eq!(
+      parse(&cmd).unwrap(),
+      Command::ZRangeByScore(
+        "z",
+        ScoreBound::Exclusive(Score(1.5)),
+        ScoreBound::Inclusive(Score(f64::INFINITY)),
+        true,
+        Some((1, -1))
+      )
+    );
+
+    let cmd = args(&["ZRANGEBYLEX", "z", "-", "(c"]);
+    assert_eq!(
+      parse(&cmd).unwrap(),
+      Command::ZRangeByLex("z", LexBound::Min, LexBound::Exclusive(b"c"), None)
+    );
+
+    let cmd = args(&["ZRANGEBYSCORE", "z", "abc", "1"]);
+    assert!(parse(&cmd).is_err());
+    let cmd = args(&["ZRANGEBYLEX", "z", "c", "+"]);
+    assert!(parse(&cmd).is_err());
+  }
+
+  #[test]
+  fn test_waitaof() {
+    let cmd = args(&["WAITAOF", "1", "0", "100"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::WaitAof(1, 0, 100));
+  }
+
+  #[test]
+  fn test_bitpos() {
+    let cmd = args(&["BITPOS", "k", "1"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::BitPos("k", true, None));
+
+    let cmd = args(&["BITPOS", "k", "0", "2"]);
+    assert_eq!(
+      parse(&cmd).unwrap(),
+      Command::BitPos("k", false, Some((2, -1)))
+    );
+
+    let cmd = args(&["BITPOS", "k", "2"]);
+    assert!(parse(&cmd).is_err());
+  }
+
+  #[test]
+  fn test_incrbyfloat() {
+    let cmd = args(&["INCRBYFLOAT", "k", "2.0e2"]);
+    assert_eq!(
+      parse(&cmd).unwrap(),
+      Command::IncrByFloat("k", Score(200.0))
+    );
+    let cmd = args(&["INCR", "k"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::Incr("k"));
+  }
+
+  #[test]
+  fn test_set_options() {
+    let cmd = args(&["SET", "k", "v", "get", "NX"]);
+    let options = SetOptions {
+      condition: Some(SetCondition::Nx),
+      get: true,
+      keep_ttl: false,
+      expire_at: None,
+      ttl: None,
+    };
+    assert_eq!(parse(&cmd).unwrap(), Command::SetWith("k", b"v", options));
+
+    let cmd = args(&["SET", "k", "v", "KEEPTTL"]);
+    let options = SetOptions {
+      keep_ttl: true,
+      ..SetOptions::default()
+    };
+    assert_eq!(parse(&cmd).unwrap(), Command::SetWith("k", b"v", options));
+
+    let cmd = args(&["SET", "k", "v", "PXAT", "1700000000000"]);
+    let options = SetOptions {
+      expire_at: Some(1_700_000_000_000),
+      ..SetOptions::default()
+    };
+    assert_eq!(parse(&cmd).unwrap(), Command::SetWith("k", b"v", options));
+
+    let cmd = args(&["SET", "k", "v", "NX", "XX"]);
+    assert!(parse(&cmd).is_err());
+    let cmd = args(&["SET", "k", "v", "KEEPTTL", "PXAT", "1"]);
+    assert!(parse(&cmd).is_err());
+  }
+
+  #[test]
+  fn test_set_relative_expiry() {
+    let cmd = args(&["SET", "k", "v", "EX", "10"]);
+    let options = SetOptions {
+      ttl: Some(10_000),
+      ..SetOptions::default()
+    };
+    assert_eq!(parse(&cmd).unwrap(), Command::SetWith("k", b"v", options));
+
+    let cmd = args(&["SET", "k", "v", "NX", "PX", "500"]);
+    let options = SetOptions {
+      condition: Some(SetCondition::Nx),
+      ttl: Some(500),
+      ..SetOptions::default()
+    };
+    assert_eq!(parse(&cmd).unwrap(), Command::SetWith("k", b"v", options));
+
+    for bad in [
+      &["SET", "k", "v", "EX", "0"][..],
+      &["SET", "k", "v", "EX", "10", "PX", "500"],
+      &["SET", "k", "v", "PX", "500", "KEEPTTL"],
+      &["SET", "k", "v", "EX"],
+      &["SET", "k", "v", "EXPIRE", "10"],
+      &["SET", "k", "v", "NX", "10"],
+    ] {
+      assert!(parse(&args(bad)).is_err(), "{:?}", bad);
+    }
+  }
+
+  #[test]
+  fn test_append_setrange() {
+    let cmd = args(&["APPEND", "k", "abc"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::Append("k", b"abc"));
+
+    let cmd = args(&["SETRANGE", "k", "4", "hi"]);
+    assert_eq!(parse(&cmd).unwrap(), Command::SetRange("k", 4, b"hi"));
+
+    let cmd = args(&["SETRANGE", "k", "-1", "hi"]);
+    assert!(parse(&cmd).is_err());
   }
 }
@@ -0,0 +1,300 @@
+use crate::err::RedisError;
+use crate::value::{RedisValue, STREAM_WINDOW};
+
+use log::warn;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+/// The RESP version replies are encoded in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Protocol {
+  #[default]
+  Resp2,
+  Resp3,
+}
+
+pub async fn write_reply<W: AsyncWrite + Unpin + Send>(
+  w: &mut W,
+  reply: &Result<RedisValue, RedisError>,
+  protocol: Protocol,
+) -> io::Result<()> {
+  match reply {
+    Ok(value) => write_value(w, value, protocol).await,
+    Err(e) => w.write_all(encode_error(e).as_bytes()).await,
+  }
+}
+
+/// Streams `value` to `w` piece by piece, so a large reply is never
+/// materialized in memory a second time. Callers should hand in a
+/// buffered writer to coalesce the small writes.
+fn write_value<'a, W: AsyncWrite + Unpin + Send>(
+  w: &'a mut W,
+  value: &'a RedisValue,
+  protocol: Protocol,
+) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send + 'a>> {
+  Box::pin(async move {
+    match value {
+      RedisValue::Nested(values) => {
+        w.write_all(format!("*{}\r\n", values.len()).as_bytes())
+          .await?;
+        for v in values {
+          match v {
+            RedisValue::Nested(_) | RedisValue::Map(_) | RedisValue::Attributed(..) => {
+              write_value(w, v, protocol).await?
+            }
+            v => write_flat(w, v, protocol).await?,
+          }
+        }
+        Ok(())
+      }
+      RedisValue::Map(pairs) => {
+        let header = match protocol {
+          Protocol::Resp3 => format!("%{}\r\n", pairs.len()),
+          Protocol::Resp2 => format!("*{}\r\n", pairs.len() * 2),
+        };
+        w.write_all(header.as_bytes()).await?;
+        for (name, value) in pairs {
+          write_bulk(w, name.as_bytes()).await?;
+          write_value(w, value, protocol).await?;
+        }
+        Ok(())
+      }
+      RedisValue::Attributed(attributes, reply) => {
+        if protocol == Protocol::Resp3 {
+          w.write_all(format!("|{}\r\n", attributes.len()).as_bytes())
+            .await?;
+          for (name, value) in attributes {
+            write_bulk(w, name.as_bytes()).await?;
+            w.write_all(format!(":{value}\r\n").as_bytes()).await?;
+          }
+        }
+        write_value(w, reply, protocol).await
+      }
+      v => write_flat(w, v, protocol).await,
+    }
+  })
+}
+
+/// Writes any value but `Nested`, `Map` and `Attributed`, which need the
+/// boxed recursion.
+async fn write_flat<W: AsyncWrite + Unpin>(
+  w: &mut W,
+  value: &RedisValue,
+  protocol: Protocol,
+) -> io::Result<()> {
+  match value {
+    RedisValue::Ok => w.write_all(b"+OK\r\n").await,
+    RedisValue::NullBulk => w.write_all(b"$-1\r\n").await,
+    RedisValue::SimpleString(elem) => write_bulk(w, elem).await,
+    RedisValue::InlineString(elem) => write_bulk(w, elem.as_slice()).await,
+    RedisValue::Array(v) if v.is_empty() => w.write_all(b"*-1\r\n").await,
+    RedisValue::Array(v) => {
+      w.write_all(format!("*{}\r\n", v.len()).as_bytes()).await?;
+      for elem in v {
+        match elem {
+          Some(elem) => write_bulk(w, elem).await?,
+          None => w.write_all(b"$-1\r\n").await?,
+        }
+      }
+      Ok(())
+    }
+    RedisValue::Status(status) => w.write_all(format!("+{status}\r\n").as_bytes()).await,
+    RedisValue::Error(e) => w.write_all(encode_error(e).as_bytes()).await,
+    RedisValue::Nested(_) | RedisValue::Map(_) | RedisValue::Attributed(..) => {
+      unreachable!("nested values go through write_value")
+    }
+    RedisValue::Integer(v) => w.write_all(format!(":{v}\r\n").as_bytes()).await,
+    RedisValue::BulkString(_) => w.write_all(b"$-1\r\n").await,
+    RedisValue::Verbatim(format, text) if protocol == Protocol::Resp3 => {
+      let len = format.len() + 1 + text.len();
+      w.write_all(format!("={len}\r\n{format}:").as_bytes())
+        .await?;
+      w.write_all(text).await?;
+      w.write_all(b"\r\n").await
+    }
+    RedisValue::Verbatim(_, text) => write_bulk(w, text).await,
+    RedisValue::Encoded(reply) => w.write_all(reply).await,
+    RedisValue::Streamed(len, windows) => {
+      w.write_all(format!("*{len}\r\n").as_bytes()).await?;
+      let mut from = 0;
+      while from < *len {
+        let count = (*len - from).min(STREAM_WINDOW);
+        w.write_all(&windows.window(from, count).await).await?;
+        from += count;
+      }
+      Ok(())
+    }
+  }
+}
+
+async fn write_bulk<W: AsyncWrite + Unpin>(w: &mut W, elem: &[u8]) -> io::Result<()> {
+  w.write_all(format!("${}\r\n", elem.len()).as_bytes())
+    .await?;
+  w.write_all(elem).await?;
+  w.write_all(b"\r\n").await
+}
+
+fn encode_error(e: &RedisError) -> String {
+  match e {
+    RedisError::Type => format!("-WRONGTYPE {e}\r\n"),
+    RedisError::ReadOnly => format!("-READONLY {e}\r\n"),
+    RedisError::ExecAbort => format!("-EXECABORT {e}\r\n"),
+    RedisError::NoProto => format!("-NOPROTO {e}\r\n"),
+    RedisError::Parse(msg) => {
+      warn!("parse error: {msg}");
+      format!("-ERR {msg}\r\n")
+    }
+    e => format!("-ERR {e}\r\n"),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{write_reply, Protocol};
+  use crate::value::RedisValue;
+  use std::sync::Arc;
+
+  #[tokio::test]
+  async fn test_write_binary_bulk() {
+    let mut out = Vec::new();
+    let reply = Ok(RedisValue::SimpleString(Arc::new(vec![0xff, 0x00, 0x80])));
+    write_reply(&mut out, &reply, Protocol::Resp2)
+      .await
+      .unwrap();
+
+    assert_eq!(out, b"$3\r\n\xff\x00\x80\r\n");
+  }
+
+  #[tokio::test]
+  async fn test_write_large_array() {
+    let values = (0..10_000)
+      .map(|i| RedisValue::from(i.to_string().into_bytes()))
+      .collect::<Vec<_>>();
+    let mut expected = b"*10000\r\n".to_vec();
+    for i in 0..10_000 {
+      let elem = i.to_string();
+      expected.extend(format!("${}\r\n{}\r\n", elem.len(), elem).as_bytes());
+    }
+
+    let mut out = Vec::new();
+    write_reply(&mut out, &Ok(RedisValue::Nested(values)), Protocol::Resp2)
+      .await
+      .unwrap();
+    assert_eq!(out, expected);
+  }
+
+  #[tokio::test]
+  async fn test_write_nested_array() {
+    let reply = Ok(RedisValue::Nested(vec![
+      RedisValue::from("a"),
+      RedisValue::Nested(vec![RedisValue::Integer(1)]),
+      RedisValue::NullBulk,
+    ]));
+
+    let mut out = Vec::new();
+    write_reply(&mut out, &reply, Protocol::Resp2)
+      .await
+      .unwrap();
+    assert_eq!(out, b"*3\r\n$1\r\na\r\n*1\r\n:1\r\n$-1\r\n");
+  }
+
+  #[tokio::test]
+  async fn test_write_array_with_nil() {
+    let reply = Ok(RedisValue::Array(vec![
+      Some(b"a".to_vec()),
+      None,
+      Some(b"b".to_vec()),
+    ]));
+
+    let mut out = Vec::new();
+    write_reply(&mut out, &reply, Protocol::Resp2)
+      .await
+      .unwrap();
+    assert_eq!(out, b"*3\r\n$1\r\na\r\n$-1\r\n$1\r\nb\r\n");
+  }
+
+  #[tokio::test]
+  async fn test_write_verbatim() {
+    let reply = Ok(RedisValue::Verbatim("txt", b"# Server\r\n".to_vec()));
+
+    let mut out = Vec::new();
+    write_reply(&mut out, &reply, Protocol::Resp3)
+      .await
+      .unwrap();
+    assert_eq!(out, b"=14\r\ntxt:# Server\r\n\r\n");
+
+    let mut out = Vec::new();
+    write_reply(&mut out, &reply, Protocol::Resp2)
+      .await
+      .unwrap();
+    assert_eq!(out, b"$10\r\n# Server\r\n\r\n");
+  }
+
+  #[bench]
+  fn bench_write_large_array(b: &mut test::Bencher) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let values = (0..100_000)
+      .map(|i| RedisValue::from(i.to_string().into_bytes()))
+      .collect::<Vec<_>>();
+    let reply = Ok(RedisValue::Nested(values));
+
+    b.iter(|| {
+      let mut w = tokio::io::BufWriter::new(tokio::io::sink());
+      rt.block_on(write_reply(&mut w, &reply, Protocol::Resp2))
+        .unwrap()
+    });
+  }
+
+  #[bench]
+  fn bench_write_large_bulk(b: &mut test::Bencher) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let reply = Ok(RedisValue::SimpleString(Arc::new(vec![b'x'; 1024 * 1024])));
+
+    b.iter(|| {
+      rt.block_on(write_reply(&mut tokio::io::sink(), &reply, Protocol::Resp2))
+        .unwrap()
+    });
+  }
+
+  #[tokio::test]
+  async fn test_write_attributed() {
+    let reply = Ok(RedisValue::Attributed(
+      vec![("latency-us", 12_000)],
+      Box::new(RedisValue::Ok),
+    ));
+
+    let mut out = Vec::new();
+    write_reply(&mut out, &reply, Protocol::Resp3)
+      .await
+      .unwrap();
+    assert_eq!(out, b"|1\r\n$10\r\nlatency-us\r\n:12000\r\n+OK\r\n");
+
+    let mut out = Vec::new();
+    write_reply(&mut out, &reply, Protocol::Resp2)
+      .await
+      .unwrap();
+    assert_eq!(out, b"+OK\r\n");
+  }
+
+  #[tokio::test]
+  async fn test_write_map() {
+    let reply = Ok(RedisValue::Map(vec![
+      ("proto", RedisValue::Integer(3)),
+      ("modules", RedisValue::Nested(Vec::new())),
+    ]));
+
+    let mut out = Vec::new();
+    write_reply(&mut out, &reply, Protocol::Resp3)
+      .await
+      .unwrap();
+    assert_eq!(out, b"%2\r\n$5\r\nproto\r\n:3\r\n$7\r\nmodules\r\n*0\r\n");
+
+    let mut out = Vec::new();
+    write_reply(&mut out, &reply, Protocol::Resp2)
+      .await
+      .unwrap();
+    assert_eq!(out, b"*4\r\n$5\r\nproto\r\n:3\r\n$7\r\nmodules\r\n*0\r\n");
+  }
+}
@@ -8,6 +8,14 @@ pub enum RedisError {
   Parse(String),
   IO(String),
   Type,
+  ReadOnly,
+  /// EXEC of a transaction a command failed to queue in.
+  ExecAbort,
+  /// The client broke RESP framing, the connection can't be read further.
+  Protocol(String),
+  /// HELLO asked for a RESP version other than 2 and 3.
+  NoProto,
+  Other(String),
 }
 
 impl fmt::Display for RedisError {
@@ -16,8 +24,13 @@ impl fmt::Display for RedisError {
       RedisError::Type => {
         write!(f, "Operation against a key holding the wrong kind of value")
       }
+      RedisError::ReadOnly => write!(f, "You can't write against a read only replica."),
+      RedisError::ExecAbort => write!(f, "Transaction discarded because of previous errors."),
       RedisError::Parse(message) => write!(f, "{message}"),
       RedisError::IO(message) => write!(f, "{message}"),
+      RedisError::Protocol(message) => write!(f, "Protocol error: {message}"),
+      RedisError::NoProto => write!(f, "unsupported protocol version"),
+      RedisError::Other(message) => write!(f, "{message}"),
     }
   }
 }
@@ -0,0 +1,120 @@
+/// Matches `string` against a Redis-style glob `pattern`.
+///
+/// Supports `*`, `?`, `[abc]`, `[a-z]`, `[^abc]` and `\` to escape the
+/// next character.
+pub fn matches(pattern: &[u8], string: &[u8]) -> bool {
+  // Iterative with backtracking to the last `*` only, O(n·m) and no
+  // recursion whatever the pattern: it comes straight from clients.
+  let (mut p, mut s) = (0, 0);
+  let mut star = None;
+  while s < string.len() {
+    if pattern.get(p) == Some(&b'*') {
+      p += 1;
+      star = Some((p, s));
+      continue;
+    }
+    if let Some(next) = step(pattern, p, string[s]) {
+      p = next;
+      s += 1;
+      continue;
+    }
+    // let the last `*` swallow one more byte and retry from there
+    match star {
+      Some((after_star, from)) => {
+        p = after_star;
+        s = from + 1;
+        star = Some((after_star, s));
+      }
+      None => return false,
+    }
+  }
+  pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Matches `ch` against the pattern element at `p`, other than `*`,
+/// returns where the next one starts if it did.
+fn step(pattern: &[u8], p: usize, ch: u8) -> Option<usize> {
+  match &pattern[p..] {
+    [] => None,
+    [b'?', ..] => Some(p + 1),
+    [b'[', rest @ ..] => match class(rest, ch) {
+      Some((true, rest)) => Some(pattern.len() - rest.len()),
+      _ => None,
+    },
+    [b'\\', c, ..] => (*c == ch).then_some(p + 2),
+    [c, ..] => (*c == ch).then_some(p + 1),
+  }
+}
+
+/// Matches `ch` against the class starting right after `[`, returns whether
+/// it matched and the pattern after the closing `]`.
+fn class(mut pattern: &[u8], ch: u8) -> Option<(bool, &[u8])> {
+  let negate = pattern.first() == Some(&b'^');
+  if negate {
+    pattern = &pattern[1..];
+  }
+
+  let mut matched = false;
+  loop {
+    match pattern {
+      [] => return None,
+      [b']', rest @ ..] => return Some((matched != negate, rest)),
+      [b'\\', c, rest @ ..] => {
+        matched |= *c == ch;
+        pattern = rest;
+      }
+      [from, b'-', to, rest @ ..] if *to != b']' => {
+        let (lo, hi) = if from <= to { (from, to) } else { (to, from) };
+        matched |= (*lo..=*hi).contains(&ch);
+        pattern = rest;
+      }
+      [c, rest @ ..] => {
+        matched |= *c == ch;
+        pattern = rest;
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::matches;
+
+  #[test]
+  fn test_matches() {
+    assert!(matches(b"*", b"anything"));
+    assert!(matches(b"user:*", b"user:42"));
+    assert!(!matches(b"user:*", b"admin:42"));
+    assert!(matches(b"h?llo", b"hello"));
+    assert!(matches(b"h[a-e]llo", b"hello"));
+    assert!(!matches(b"h[^e]llo", b"hello"));
+  }
+
+  #[test]
+  fn test_edge_cases() {
+    assert!(matches(b"", b""));
+    assert!(!matches(b"", b"a"));
+    assert!(matches(b"*", b""));
+    assert!(!matches(b"?", b""));
+    assert!(matches(b"[a-c]", b"b"));
+    assert!(!matches(b"[a-c]", b"d"));
+    assert!(matches(b"[c-a]", b"b"));
+    assert!(matches(b"\\*", b"*"));
+    assert!(!matches(b"\\*", b"a"));
+    assert!(matches(b"\\?x", b"?x"));
+    assert!(matches(b"[\\]]", b"]"));
+    assert!(!matches(b"[abc", b"a"));
+    assert!(matches(b"a*b*c", b"aXbYbZc"));
+    assert!(!matches(b"a*b*c", b"aXbYbZ"));
+    assert!(matches(b"**?", b"x"));
+    assert!(matches(b"*\\", b"a\\"));
+  }
+
+  #[test]
+  fn test_pathological_patterns() {
+    // each once blew the stack or ran for minutes
+    let stars = vec![b'*'; 200_000];
+    assert!(matches(&stars, b"a"));
+    assert!(!matches(b"*a*a*a*a*a*a*a*a*a*b", &[b'a'; 60]));
+  }
+}
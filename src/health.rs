@@ -0,0 +1,26 @@
+use std::io;
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+
+use crate::journal::Writer;
+use crate::redis::Redis;
+
+/// Answers one HTTP request: 200 to `GET /health` while the server is
+/// healthy, 503 once it isn't and 404 to anything else.
+pub async fn serve<S: AsyncRead + AsyncWrite + Unpin, W: Writer>(
+  socket: S,
+  redis: &Redis<W>,
+) -> io::Result<()> {
+  let mut socket = BufReader::new(socket);
+  let mut request_line = String::new();
+  socket.read_line(&mut request_line).await?;
+
+  let status = match request_line.split_whitespace().collect::<Vec<_>>()[..] {
+    ["GET", "/health", ..] if redis.is_healthy() => "200 OK",
+    ["GET", "/health", ..] => "503 Service Unavailable",
+    _ => "404 Not Found",
+  };
+  let response = format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+  socket.write_all(response.as_bytes()).await?;
+  socket.shutdown().await
+}
@@ -1,12 +1,72 @@
-use crate::cmd::Command;
+use crate::cmd::{Command, Expiry, SetCondition};
+use crate::err::RedisError;
 
 use async_trait::async_trait;
-use log::info;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use log::{debug, info, warn};
 use tokio::{fs::File, sync::Mutex, io::AsyncWriteExt};
+use serde_json::json;
+use std::path::PathBuf;
+use std::str::FromStr;
+use tokio::fs::{self, OpenOptions};
 
 #[async_trait]
 pub trait Writer {
   async fn write<'a>(&self, cmd: &'a Command<'a>);
+
+  /// Makes everything written so far durable.
+  async fn flush(&self);
+
+  /// Flushes and fsyncs everything written so far to disk.
+  async fn fsync(&self);
+
+  /// Whether commands end up anywhere, WAITAOF has nothing to wait for
+  /// otherwise.
+  fn is_enabled(&self) -> bool {
+    true
+  }
+
+  /// Replaces everything written so far with `commands`, commands
+  /// written afterwards are appended to them.
+  async fn rewrite(&self, commands: Vec<Command<'_>>) -> Result<(), RedisError>;
+}
+
+/// When journal writes are fsynced, as appendfsync in Redis.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FsyncPolicy {
+  /// After every write.
+  Always,
+  /// Once a second in the background.
+  #[default]
+  EverySec,
+  /// Left to the OS, only WAITAOF forces one.
+  No,
+}
+
+impl FromStr for FsyncPolicy {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().as_str() {
+      "always" => Ok(FsyncPolicy::Always),
+      "everysec" => Ok(FsyncPolicy::EverySec),
+      "no" => Ok(FsyncPolicy::No),
+      _ => Err(format!(
+        "unknown fsync policy '{s}', expected always, everysec or no"
+      )),
+    }
+  }
+}
+
+async fn fsync_file(file: &Mutex<File>) {
+  let mut f = file.lock().await;
+  if let Err(e) = f.flush().await {
+    warn!("can't flush journal: {e}");
+  }
+  if let Err(e) = f.sync_data().await {
+    warn!("can't fsync journal: {e}");
+  }
 }
 
 pub struct Journal {
@@ -29,6 +89,23 @@ impl Writer for Simple {
     let mut f = self.file.lock().await;
     f.write_all(format!("attempt to simple log a command: {:?}\r\n", cmd).as_bytes()).await;
   }
+
+  async fn flush(&self) {
+    let mut f = self.file.lock().await;
+    if let Err(e) = f.flush().await {
+      warn!("can't flush journal: {e}");
+    }
+  }
+
+  async fn fsync(&self) {
+    fsync_file(&self.file).await
+  }
+
+  async fn rewrite(&self, _commands: Vec<Command<'_>>) -> Result<(), RedisError> {
+    Err(RedisError::Other(
+      "only the json journal can be rewritten".to_string(),
+    ))
+  }
 }
 
 pub struct Disabled;
@@ -36,6 +113,249 @@ pub struct Disabled;
 #[async_trait]
 impl Writer for Disabled {
   async fn write<'a>(&self, cmd: &'a Command<'a>) {
-    info!("attempt to log a command: {:?}", cmd);
+    debug!("attempt to log a command: {:?}", cmd);
+  }
+
+  async fn flush(&self) {}
+
+  async fn fsync(&self) {}
+
+  fn is_enabled(&self) -> bool {
+    false
+  }
+
+  async fn rewrite(&self, _commands: Vec<Command<'_>>) -> Result<(), RedisError> {
+    Ok(())
+  }
+}
+
+/// Writes every mutating command as a line of JSON, see `to_json`.
+pub struct Json {
+  file: Mutex<File>,
+  /// Where `file` lives, a rewrite replaces it.
+  path: PathBuf,
+}
+
+impl Json {
+  pub fn make(file: Mutex<File>, path: PathBuf) -> Json {
+    Json { file, path }
+  }
+}
+
+#[async_trait]
+impl Writer for Json {
+  async fn write<'a>(&self, cmd: &'a Command<'a>) {
+    let Some(line) = to_json(cmd) else { return };
+    let mut f = self.file.lock().await;
+    if let Err(e) = f.write_all(format!("{line}\n").as_bytes()).await {
+      warn!("can't write journal: {e}");
+    }
+  }
+
+  async fn flush(&self) {
+    let mut f = self.file.lock().await;
+    if let Err(e) = f.flush().await {
+      warn!("can't flush journal: {e}");
+    }
+  }
+
+  async fn fsync(&self) {
+    fsync_file(&self.file).await
+  }
+
+  /// Writes `commands` aside and renames the result over the journal.
+  /// Writes wait for the file meanwhile, so none land in the old one.
+  async fn rewrite(&self, commands: Vec<Command<'_>>) -> Result<(), RedisError> {
+    let mut out = String::new();
+    for cmd in &commands {
+      if let Some(line) = to_json(cmd) {
+        out.push_str(&line);
+        out.push('\n');
+      }
+    }
+
+    let mut f = self.file.lock().await;
+    let tmp_path = self.path.with_extension("tmp");
+    let mut tmp = File::create(&tmp_path).await?;
+    tmp.write_all(out.as_bytes()).await?;
+    tmp.sync_data().await?;
+    fs::rename(&tmp_path, &self.path).await?;
+    *f = OpenOptions::new().append(true).open(&self.path).await?;
+    info!(
+      "rewrote journal {} with {} commands",
+      self.path.display(),
+      commands.len()
+    );
+    Ok(())
+  }
+}
+
+/// A mutating command as one JSON object like
+/// `{"cmd":"SET","key":"k","value":"dg=="}`, `None` for other commands.
+/// Values are base64 since they may be binary, keys are plain strings.
+pub fn to_json(cmd: &Command) -> Option<String> {
+  let encode = |value: &[u8]| STANDARD.encode(value);
+  let encode_all = |values: &[&[u8]]| values.iter().map(|v| encode(v)).collect::<Vec<_>>();
+
+  let line = match cmd {
+    Command::Set(key, value) => json!({"cmd": "SET", "key": key, "value": encode(value)}),
+    Command::SetWith(key, value, options) => {
+      let mut line = json!({"cmd": "SET", "key": key, "value": encode(value)});
+      match options.condition {
+        Some(SetCondition::Nx) => line["nx"] = json!(true),
+        Some(SetCondition::Xx) => line["xx"] = json!(true),
+        None => {}
+      }
+      if options.keep_ttl {
+        line["keepttl"] = json!(true);
+      }
+      if let Some(at) = options.expire_at {
+        line["pxat"] = json!(at);
+      }
+      line
+    }
+    Command::Append(key, value) => json!({"cmd": "APPEND", "key": key, "value": encode(value)}),
+    Command::SetRange(key, offset, value) => {
+      json!({"cmd": "SETRANGE", "key": key, "offset": offset, "value": encode(value)})
+    }
+    Command::SetEx(key, value, ttl) => {
+      json!({"cmd": "SETEX", "key": key, "ttl": ttl, "value": encode(value)})
+    }
+    Command::GetEx(key, Some(Expiry::Ex(seconds))) => {
+      json!({"cmd": "GETEX", "key": key, "ex": seconds})
+    }
+    Command::GetEx(key, Some(Expiry::Px(millis))) => {
+      json!({"cmd": "GETEX", "key": key, "px": millis})
+    }
+    Command::GetEx(key, Some(Expiry::PxAt(at))) => {
+      json!({"cmd": "GETEX", "key": key, "pxat": at})
+    }
+    Command::GetEx(key, Some(Expiry::Persist)) => {
+      json!({"cmd": "GETEX", "key": key, "persist": true})
+    }
+    Command::Lpush(key, values) => {
+      json!({"cmd": "LPUSH", "key": key, "values": encode_all(values)})
+    }
+    Command::Rpush(key, values) => {
+      json!({"cmd": "RPUSH", "key": key, "values": encode_all(values)})
+    }
+    Command::LpushX(key, values) => {
+      json!({"cmd": "LPUSHX", "key": key, "values": encode_all(values)})
+    }
+    Command::RpushX(key, values) => {
+      json!({"cmd": "RPUSHX", "key": key, "values": encode_all(values)})
+    }
+    Command::Lpop(key, count) => {
+      json!({"cmd": "LPOP", "key": key, "count": count.unwrap_or(1)})
+    }
+    Command::Rpop(key, count) => {
+      json!({"cmd": "RPOP", "key": key, "count": count.unwrap_or(1)})
+    }
+    Command::Del(keys) => json!({"cmd": "DEL", "keys": keys}),
+    Command::Copy(source, destination, replace) => {
+      let mut line = json!({"cmd": "COPY", "keys": [source, destination]});
+      if *replace {
+        line["replace"] = json!(true);
+      }
+      line
+    }
+    Command::Incr(key) => json!({"cmd": "INCR", "key": key}),
+    Command::IncrByFloat(key, by) => {
+      json!({"cmd": "INCRBYFLOAT", "key": key, "increment": by.0.to_string()})
+    }
+    Command::Decr(key) => json!({"cmd": "DECR", "key": key}),
+    Command::Hset(key, pairs) => {
+      let values = pairs
+        .iter()
+        .flat_map(|(f, v)| [encode(f), encode(v)])
+        .collect::<Vec<_>>();
+      json!({"cmd": "HSET", "key": key, "values": values})
+    }
+    Command::HsetNx(key, field, value) => {
+      json!({"cmd": "HSETNX", "key": key, "values": [encode(field), encode(value)]})
+    }
+    Command::Sadd(key, members) => {
+      json!({"cmd": "SADD", "key": key, "values": encode_all(members)})
+    }
+    Command::Zadd(key, pairs) => {
+      let values = pairs
+        .iter()
+        .flat_map(|(score, m)| [encode(score.0.to_string().as_bytes()), encode(m)])
+        .collect::<Vec<_>>();
+      json!({"cmd": "ZADD", "key": key, "values": values})
+    }
+    Command::FlushAll => json!({"cmd": "FLUSHALL"}),
+    _ => return None,
+  };
+  Some(line.to_string())
+}
+
+/// The arguments of a command written by `to_json`, command name first.
+/// Fields go back in the order the commands expect them: key, keys, ttl,
+/// value, values, count and then the GETEX expiry.
+pub fn from_json(line: &str) -> Result<Vec<Vec<u8>>, RedisError> {
+  let bad = || RedisError::Parse(format!("invalid journal line: {line}"));
+  let json: serde_json::Value = serde_json::from_str(line).map_err(|_| bad())?;
+  let string = |v: &serde_json::Value| v.as_str().map(|s| s.as_bytes().to_vec()).ok_or_else(bad);
+  let number = |v: &serde_json::Value| {
+    v.as_u64()
+      .map(|n| n.to_string().into_bytes())
+      .ok_or_else(bad)
+  };
+  let decode = |v: &serde_json::Value| {
+    v.as_str()
+      .and_then(|s| STANDARD.decode(s).ok())
+      .ok_or_else(bad)
+  };
+
+  let mut args = vec![string(&json["cmd"])?];
+  if let Some(key) = json.get("key") {
+    args.push(string(key)?);
+  }
+  for key in json["keys"].as_array().into_iter().flatten() {
+    args.push(string(key)?);
+  }
+  if let Some(ttl) = json.get("ttl") {
+    args.push(number(ttl)?);
+  }
+  if let Some(offset) = json.get("offset") {
+    args.push(number(offset)?);
+  }
+  if let Some(increment) = json.get("increment") {
+    args.push(string(increment)?);
+  }
+  if let Some(value) = json.get("value") {
+    args.push(decode(value)?);
+  }
+  for value in json["values"].as_array().into_iter().flatten() {
+    args.push(decode(value)?);
+  }
+  if let Some(count) = json.get("count") {
+    args.push(number(count)?);
+  }
+  if let Some(seconds) = json.get("ex") {
+    args.extend([b"EX".to_vec(), number(seconds)?]);
+  }
+  if let Some(millis) = json.get("px") {
+    args.extend([b"PX".to_vec(), number(millis)?]);
+  }
+  if let Some(at) = json.get("pxat") {
+    args.extend([b"PXAT".to_vec(), number(at)?]);
+  }
+  if json.get("persist").is_some() {
+    args.push(b"PERSIST".to_vec());
+  }
+  if json.get("nx").is_some() {
+    args.push(b"NX".to_vec());
+  }
+  if json.get("xx").is_some() {
+    args.push(b"XX".to_vec());
+  }
+  if json.get("keepttl").is_some() {
+    args.push(b"KEEPTTL".to_vec());
+  }
+  if json.get("replace").is_some() {
+    args.push(b"REPLACE".to_vec());
   }
+  Ok(args)
 }
@@ -0,0 +1,66 @@
+/// Counter a key starts at, so new keys aren't the first to go.
+pub static INIT_VAL: u8 = 5;
+/// The higher, the more accesses it takes to bump the counter, 10
+/// saturating it after about a million hits.
+static LOG_FACTOR: f64 = 10.0;
+/// Minutes after which an idle counter is decremented by one.
+static DECAY_MINUTES: u64 = 1;
+
+/// Access frequency of a key as the allkeys-lfu policy tracks it, a
+/// logarithmic counter that decays while the key sits idle.
+#[derive(Clone, Copy, Debug)]
+pub struct Counter {
+  counter: u8,
+  /// Unix minutes of the last decrement.
+  decremented_at: u64,
+}
+
+impl Counter {
+  pub fn new(now_minutes: u64) -> Counter {
+    Counter {
+      counter: INIT_VAL,
+      decremented_at: now_minutes,
+    }
+  }
+
+  /// The counter with the decay since the last access applied.
+  pub fn value(&self, now_minutes: u64) -> u8 {
+    let periods = now_minutes.saturating_sub(self.decremented_at) / DECAY_MINUTES;
+    self
+      .counter
+      .saturating_sub(periods.min(u8::MAX as u64) as u8)
+  }
+
+  /// Records an access, bumping the counter with a probability that falls
+  /// as it grows. `random` is uniform in `[0, 1)`.
+  pub fn touch(&mut self, now_minutes: u64, random: f64) {
+    self.counter = self.value(now_minutes);
+    self.decremented_at = now_minutes;
+    if self.counter == u8::MAX {
+      return;
+    }
+    let base = self.counter.saturating_sub(INIT_VAL) as f64;
+    if random < 1.0 / (base * LOG_FACTOR + 1.0) {
+      self.counter += 1;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Counter, INIT_VAL};
+
+  #[test]
+  fn test_counter() {
+    let mut counter = Counter::new(0);
+    counter.touch(0, 0.99);
+    assert_eq!(counter.value(0), INIT_VAL + 1);
+    counter.touch(0, 0.5);
+    assert_eq!(counter.value(0), INIT_VAL + 1);
+    counter.touch(0, 0.01);
+    assert_eq!(counter.value(0), INIT_VAL + 2);
+
+    assert_eq!(counter.value(3), INIT_VAL - 1);
+    assert_eq!(counter.value(1_000), 0);
+  }
+}
@@ -0,0 +1,135 @@
+use std::collections::{vec_deque, VecDeque};
+use std::slice;
+
+pub static DEFAULT_PACKED_THRESHOLD: usize = 128;
+
+/// A Redis list. Small lists are kept packed in one contiguous `Vec` and
+/// converted to a `VecDeque` once they grow past the packed threshold, both
+/// giving O(1) indexed access.
+#[derive(Clone)]
+pub enum List {
+  Packed(Vec<Vec<u8>>),
+  Deque(VecDeque<Vec<u8>>),
+}
+
+pub enum Iter<'a> {
+  Packed(slice::Iter<'a, Vec<u8>>),
+  Deque(vec_deque::Iter<'a, Vec<u8>>),
+}
+
+impl<'a> Iterator for Iter<'a> {
+  type Item = &'a Vec<u8>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    match self {
+      Iter::Packed(it) => it.next(),
+      Iter::Deque(it) => it.next(),
+    }
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    match self {
+      Iter::Packed(it) => it.size_hint(),
+      Iter::Deque(it) => it.size_hint(),
+    }
+  }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+impl List {
+  pub fn new() -> List {
+    List::Packed(Vec::new())
+  }
+
+  pub fn len(&self) -> usize {
+    match self {
+      List::Packed(v) => v.len(),
+      List::Deque(dq) => dq.len(),
+    }
+  }
+
+  pub fn iter(&self) -> Iter<'_> {
+    match self {
+      List::Packed(v) => Iter::Packed(v.iter()),
+      List::Deque(dq) => Iter::Deque(dq.iter()),
+    }
+  }
+
+  pub fn push(&mut self, value: Vec<u8>, front: bool, threshold: usize) {
+    match self {
+      List::Packed(v) if front => v.insert(0, value),
+      List::Packed(v) => v.push(value),
+      List::Deque(dq) if front => dq.push_front(value),
+      List::Deque(dq) => dq.push_back(value),
+    }
+    self.convert(threshold);
+  }
+
+  pub fn pop(&mut self, front: bool, threshold: usize) -> Option<Vec<u8>> {
+    let value = match self {
+      List::Packed(v) if v.is_empty() => None,
+      List::Packed(v) if front => Some(v.remove(0)),
+      List::Packed(v) => v.pop(),
+      List::Deque(dq) if front => dq.pop_front(),
+      List::Deque(dq) => dq.pop_back(),
+    };
+    self.convert(threshold);
+    value
+  }
+
+  /// Element at `index`, negative indices counting from the tail.
+  pub fn get(&self, index: i64) -> Option<&Vec<u8>> {
+    let index = if index < 0 {
+      self.len() as i64 + index
+    } else {
+      index
+    };
+    let index = usize::try_from(index).ok()?;
+    match self {
+      List::Packed(v) => v.get(index),
+      List::Deque(dq) => dq.get(index),
+    }
+  }
+
+  /// Elements between `start` and `stop` inclusive, negative indices
+  /// counting from the tail as in LRANGE.
+  pub fn range(&self, start: i64, stop: i64) -> Vec<Vec<u8>> {
+    self.range_iter(start, stop).cloned().collect()
+  }
+
+  /// `range` without copying the elements out.
+  pub fn range_iter(&self, start: i64, stop: i64) -> impl ExactSizeIterator<Item = &Vec<u8>> {
+    let len = self.len() as i64;
+    let start = if start < 0 {
+      (len + start).max(0)
+    } else {
+      start
+    };
+    let stop = if stop < 0 {
+      len + stop
+    } else {
+      stop.min(len - 1)
+    };
+    let count = match start > stop || start >= len {
+      true => 0,
+      false => (stop - start + 1) as usize,
+    };
+
+    self.iter().skip(start as usize).take(count)
+  }
+
+  /// Packs lists shrunk to half the threshold and unpacks ones grown past
+  /// it, the gap avoids converting back and forth around the boundary.
+  fn convert(&mut self, threshold: usize) {
+    match self {
+      List::Packed(v) if v.len() > threshold => {
+        *self = List::Deque(v.drain(..).collect());
+      }
+      List::Deque(dq) if dq.len() <= threshold / 2 => {
+        *self = List::Packed(dq.drain(..).collect());
+      }
+      _ => {}
+    }
+  }
+}
@@ -1,35 +1,64 @@
 #![feature(let_chains)]
+#![feature(test)]
 #![allow(unused)]
 
+#[cfg(test)]
+extern crate test;
+
 mod cmd;
+mod encoder;
 mod err;
+mod glob;
+mod health;
 mod journal;
+mod lfu;
+mod list;
+#[cfg(test)]
+mod peak_alloc;
+mod pubsub;
 mod redis;
+mod slowlog;
+mod sync;
+mod tls;
 mod value;
+mod zset;
 
 use cmd::parser::parse;
+use cmd::Command;
+use encoder::Protocol;
 
-use log::warn;
-use redis::Redis;
-use tokio::sync::Mutex;
-use std::borrow::Cow;
+use log::{error, info, warn};
+use redis::{Config, Redis};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::{broadcast, Mutex, Notify};
+use tokio::task::{AbortHandle, JoinSet};
+use tokio_rustls::TlsAcceptor;
 
 use value::RedisValue;
 
+#[cfg(test)]
+#[global_allocator]
+static ALLOCATOR: peak_alloc::PeakAlloc = peak_alloc::PeakAlloc;
+
+use std::collections::HashSet;
 use std::env;
 use std::error::Error;
+use std::future::Future;
+use std::io;
 use std::marker::Send;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use journal::{Disabled, Simple, Writer};
+use journal::{Disabled, Json, Simple, Writer};
 
 use tokio::fs::File;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
-use tokio::io::{AsyncRead, BufReader};
-use tokio::net::tcp::{ReadHalf, WriteHalf};
-use tokio::net::{TcpListener, TcpStream};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncRead, BufReader, BufWriter};
+use tokio::io::{AsyncWrite, ReadHalf, WriteHalf};
+use tokio::net::{TcpListener, TcpSocket, TcpStream, UnixListener};
 
 use simple_logger::SimpleLogger;
+use socket2::{SockRef, TcpKeepalive};
 
 use err::RedisError;
 
@@ -37,161 +66,1622 @@ use clap::Parser;
 
 #[derive(clap::Parser)]
 struct Cli {
-  /// addr to listen on
+  /// addr to listen on, ignored when --bind is given
   addr: Option<String>,
   /// Persistence level
   journal: Option<String>,
   /// The path to the file to read
   journal_path: Option<std::path::PathBuf>,
+  /// How journaled commands are written: simple, or json for one JSON
+  /// object per line, replayed on startup
+  #[arg(long, default_value = "simple")]
+  journal_format: String,
+  /// When the journal is fsynced: always, everysec or no
+  #[arg(long, default_value = "everysec")]
+  journal_fsync: String,
+  /// Reject commands that modify the dataset
+  #[arg(long)]
+  read_only: bool,
+  /// Snapshot written by SAVE and loaded on startup
+  #[arg(long)]
+  dbfilename: Option<std::path::PathBuf>,
+  /// Let the SHUTDOWN command stop the server
+  #[arg(long)]
+  allow_shutdown: bool,
+  /// Port used with the --bind addresses, 0 disables TCP
+  #[arg(long)]
+  port: Option<u16>,
+  /// Addresses to listen on, each gets its own listener
+  #[arg(long, num_args = 1..)]
+  bind: Vec<String>,
+  /// Commands answered with "unknown command", like FLUSHALL or DEBUG
+  #[arg(long, num_args = 1..)]
+  disable_command: Vec<String>,
+  /// Also listen on this Unix domain socket
+  #[arg(long)]
+  unixsocket: Option<std::path::PathBuf>,
+  /// Port accepting TLS connections on the --bind addresses
+  #[arg(long, requires_all = ["tls_cert", "tls_key"])]
+  tls_port: Option<u16>,
+  /// Port answering HTTP GET /health on the --bind addresses
+  #[arg(long)]
+  health_port: Option<u16>,
+  /// PEM certificate chain presented to TLS clients
+  #[arg(long)]
+  tls_cert: Option<std::path::PathBuf>,
+  /// PEM private key of the TLS certificate
+  #[arg(long)]
+  tls_key: Option<std::path::PathBuf>,
+  /// Seconds the snapshot and journal may take to replay before startup
+  /// is aborted
+  #[arg(long)]
+  load_timeout: Option<u64>,
+  /// Log commands taking at least this many microseconds
+  #[arg(long, default_value_t = slowlog::DEFAULT_SLOWER_THAN_MICROS)]
+  slowlog_log_slower_than: u64,
+  /// Number of slow commands kept
+  #[arg(long, default_value_t = slowlog::DEFAULT_MAX_LEN)]
+  slowlog_max_len: usize,
+  /// Keys the dataset has room for before it first rehashes
+  #[arg(long, default_value_t = redis::DEFAULT_INITIAL_CAPACITY)]
+  initial_capacity: usize,
+  /// noeviction, or allkeys-lfu to track how often every key is accessed
+  #[arg(long, default_value = "noeviction")]
+  maxmemory_policy: String,
+  /// Bytes an inline command or a multibulk header line may take before
+  /// the client is disconnected
+  #[arg(long, default_value_t = redis::DEFAULT_MAX_INLINE_LEN)]
+  proto_inline_max_size: usize,
+  /// off, error, warn, info, debug or trace; every command is logged at
+  /// debug
+  #[arg(long, default_value = "info")]
+  log_level: String,
+  /// How commands are logged at debug level: text, or json with the
+  /// command name and keys
+  #[arg(long, default_value = "text")]
+  command_log_format: String,
+  /// Don't run the background task removing expired keys, they are then
+  /// only removed once accessed
+  #[arg(long)]
+  no_active_expire: bool,
+  /// Most expired keys removed before the background task lets other
+  /// clients in
+  #[arg(long, default_value_t = redis::DEFAULT_ACTIVE_EXPIRE_BATCH)]
+  active_expire_batch: usize,
+  /// Milliseconds between passes of the background task removing keys
+  #[arg(long, default_value_t = redis::DEFAULT_ACTIVE_EXPIRE_INTERVAL_MS)]
+  active_expire_interval: u64,
+  /// Leave Nagle's algorithm on for client sockets, trading small-reply
+  /// latency for fewer packets
+  #[arg(long)]
+  no_tcp_nodelay: bool,
+  /// Seconds a client connection may idle before TCP keepalive probes are
+  /// sent, 0 turns keepalive off
+  #[arg(long, default_value_t = 0)]
+  tcp_keepalive: u64,
+  /// Connections the kernel queues on each TCP listener before they are
+  /// accepted
+  #[arg(long, default_value_t = DEFAULT_TCP_BACKLOG)]
+  tcp_backlog: u32,
+}
+
+/// A bound socket accepting client connections.
+enum Listener {
+  Tcp(TcpListener),
+  Unix(UnixListener),
+  Tls(TcpListener, TlsAcceptor),
+  /// Plain HTTP answering health checks.
+  Health(TcpListener),
+}
+
+static DEFAULT_PORT: u16 = 6380;
+/// Same as Redis' tcp-backlog default.
+static DEFAULT_TCP_BACKLOG: u32 = 511;
+/// Pause after a failed accept, doubled while failures repeat.
+static ACCEPT_BACKOFF_MIN: Duration = Duration::from_millis(5);
+static ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(1);
+
+/// The addresses to listen on: every `bind` address on `port`, or `addr`
+/// as given when there are none. Port 0 turns TCP off, like in Redis.
+fn listen_addrs(addr: Option<String>, bind: &[String], port: Option<u16>) -> Vec<String> {
+  if port == Some(0) {
+    return Vec::new();
+  }
+  let port = port.unwrap_or(DEFAULT_PORT);
+  if bind.is_empty() {
+    return vec![addr.unwrap_or_else(|| format!("0.0.0.0:{port}"))];
+  }
+  bind
+    .iter()
+    .map(|host| {
+      if host.contains(':') {
+        format!("[{host}]:{port}")
+      } else {
+        format!("{host}:{port}")
+      }
+    })
+    .collect()
+}
+
+/// Binds `addr` with a listen queue of `backlog` connections, trying each
+/// address it resolves to in turn.
+async fn bind(addr: &str, backlog: u32) -> io::Result<TcpListener> {
+  let mut last_error = None;
+  for addr in tokio::net::lookup_host(addr).await? {
+    let socket = if addr.is_ipv4() {
+      TcpSocket::new_v4()?
+    } else {
+      TcpSocket::new_v6()?
+    };
+    socket.set_reuseaddr(true)?;
+    match socket.bind(addr).and_then(|_| socket.listen(backlog)) {
+      Ok(listener) => return Ok(listener),
+      Err(e) => last_error = Some(e),
+    }
+  }
+  Err(last_error.unwrap_or_else(|| {
+    io::Error::new(
+      io::ErrorKind::InvalidInput,
+      "could not resolve to any address",
+    )
+  }))
+}
+
+/// Binds every address it can, failing only if none of them could be bound.
+async fn bind_all(addrs: &[String], backlog: u32) -> Result<Vec<TcpListener>, Box<dyn Error>> {
+  let mut listeners = Vec::new();
+  for addr in addrs {
+    match bind(addr, backlog).await {
+      Ok(listener) => {
+        info!("listening on {}", addr);
+        listeners.push(listener);
+      }
+      Err(e) => warn!("can't bind {}: {}", addr, e),
+    }
+  }
+  if listeners.is_empty() && !addrs.is_empty() {
+    return Err(format!("could not bind any of {}", addrs.join(", ")).into());
+  }
+  Ok(listeners)
 }
 
-async fn start_with_no_journal(listener: &TcpListener) -> Result<(), Box<dyn Error>> {
-  let redis = Arc::new(Redis::new(Disabled {}).await);
-  start(redis, listener).await
+async fn start_with_no_journal(
+  listeners: Vec<Listener>,
+  config: Config,
+) -> Result<(), Box<dyn Error>> {
+  let redis = Arc::new(Redis::with_config(Disabled {}, config).await);
+  start(redis, listeners).await
 }
 
 async fn start_with_simple_journaling(
-  listener: &TcpListener,
-  file: Mutex<File>
+  listeners: Vec<Listener>,
+  file: Mutex<File>,
+  config: Config,
 ) -> Result<(), Box<dyn Error>> {
-  let redis = Arc::new(Redis::new(Simple::make(file)).await);
-  start(redis, listener).await
+  let redis = Arc::new(Redis::with_config(Simple::make(file), config).await);
+  start(redis, listeners).await
+}
+
+/// Replays the JSON journal at `path` and keeps appending to it. Replayed
+/// commands aren't journaled again, so an unterminated last line left by a
+/// crash is terminated if it parses and cut off if not, for new commands to
+/// start on a line of their own.
+async fn start_with_json_journaling(
+  listeners: Vec<Listener>,
+  path: &std::path::Path,
+  config: Config,
+) -> Result<(), Box<dyn Error>> {
+  let journal = match tokio::fs::read_to_string(path).await {
+    Ok(journal) => journal,
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+    Err(e) => return Err(e.into()),
+  };
+  let mut file = tokio::fs::OpenOptions::new()
+    .create(true)
+    .append(true)
+    .open(path)
+    .await?;
+  let tail = journal.rsplit('\n').next().unwrap_or_default();
+  if journal::from_json(tail).is_ok() {
+    file.write_all(b"\n").await?;
+  } else if !tail.is_empty() {
+    file.set_len((journal.len() - tail.len()) as u64).await?;
+  }
+  let file = Mutex::new(file);
+  let redis = Arc::new(Redis::with_config(Json::make(file, path.to_path_buf()), config).await);
+  let count = redis.replay_json(&journal).await?;
+  info!("replayed {} commands from {}", count, path.display());
+  start(redis, listeners).await
 }
 
 async fn start<W: Writer + Send + Sync + 'static>(
   redis: Arc<Redis<W>>,
-  listener: &TcpListener,
+  listeners: Vec<Listener>,
 ) -> Result<(), Box<dyn Error>> {
+  redis.load().await?;
+  let fsync = redis.clone();
+  tokio::spawn(async move { fsync.fsync_every_second().await });
+
+  let mut accept_loops = JoinSet::new();
+  for listener in listeners {
+    accept_loops.spawn(accept_loop(redis.clone(), listener));
+  }
+
+  // dropping the set aborts the accept loops
+  redis.shutdown_requested().await;
+  Ok(())
+}
+
+async fn accept_loop<W: Writer + Send + Sync + 'static>(redis: Arc<Redis<W>>, listener: Listener) {
   loop {
-    let (mut socket, _) = listener.accept().await.unwrap();
     let r = redis.clone();
-    tokio::spawn(async move { Session::new(&mut socket, r).run().await });
+    match &listener {
+      Listener::Tcp(listener) => {
+        let (socket, addr) = accept_retrying(|| listener.accept()).await;
+        configure_tcp(&socket, &r);
+        let addr = addr.to_string();
+        tokio::spawn(isolate(addr.clone(), async move {
+          Session::new(socket, addr, r).run().await
+        }));
+      }
+      Listener::Tls(listener, acceptor) => {
+        let (socket, addr) = accept_retrying(|| listener.accept()).await;
+        configure_tcp(&socket, &r);
+        let acceptor = acceptor.clone();
+        tokio::spawn(async move {
+          match acceptor.accept(socket).await {
+            Ok(stream) => {
+              let session = async move { Session::new(stream, addr.to_string(), r).run().await };
+              isolate(addr.to_string(), session).await
+            }
+            Err(e) => warn!("TLS handshake with {} failed: {}", addr, e),
+          }
+        });
+      }
+      Listener::Health(listener) => {
+        let (socket, addr) = accept_retrying(|| listener.accept()).await;
+        tokio::spawn(async move {
+          if let Err(e) = health::serve(socket, &r).await {
+            warn!("health check from {} failed: {}", addr, e);
+          }
+        });
+      }
+      Listener::Unix(listener) => {
+        let (socket, _) = accept_retrying(|| listener.accept()).await;
+        // Redis reports unix clients by the socket path and port 0
+        let addr = listener
+          .local_addr()
+          .ok()
+          .and_then(|addr| {
+            addr
+              .as_pathname()
+              .map(|path| format!("{}:0", path.display()))
+          })
+          .unwrap_or_default();
+        tokio::spawn(isolate(addr.clone(), async move {
+          Session::new(socket, addr, r).run().await
+        }));
+      }
+    }
   }
 }
 
+/// Waits for the next connection. A failed accept is logged and retried
+/// rather than ending the loop, after a pause since errors like running
+/// out of file descriptors last until some connection closes.
+async fn accept_retrying<T, F>(mut accept: impl FnMut() -> F) -> T
+where
+  F: Future<Output = io::Result<T>>,
+{
+  let mut backoff = ACCEPT_BACKOFF_MIN;
+  loop {
+    match accept().await {
+      Ok(accepted) => return accepted,
+      Err(e) => {
+        warn!("accept failed, retrying in {:?}: {}", backoff, e);
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(ACCEPT_BACKOFF_MAX);
+      }
+    }
+  }
+}
+
+/// Applies the TCP_NODELAY and keepalive settings to an accepted client
+/// socket. Failing to is logged, the connection is served regardless.
+fn configure_tcp<W: Writer + Send>(socket: &TcpStream, redis: &Redis<W>) {
+  let configured = socket.set_nodelay(redis.tcp_nodelay()).and_then(|_| {
+    redis.tcp_keepalive().map_or(Ok(()), |idle| {
+      let keepalive = TcpKeepalive::new().with_time(idle).with_interval(idle);
+      SockRef::from(socket).set_tcp_keepalive(&keepalive)
+    })
+  });
+  if let Err(e) = configured {
+    warn!("can't configure client socket: {}", e);
+  }
+}
+
+/// Runs the session of the client at `addr` in a task of its own, so a
+/// panic in it is logged and closes only that connection. Dropping the
+/// session on unwind still unregisters the client.
+async fn isolate(addr: String, session: impl Future<Output = ()> + Send + 'static) {
+  if let Err(e) = tokio::spawn(session).await {
+    error!("session of {} died, connection closed: {}", addr, e);
+  }
+}
+
+/// Binds the Unix domain socket at `path`, replacing a stale socket file
+/// left by a previous run.
+fn bind_unix(path: &std::path::Path) -> Result<Listener, Box<dyn Error>> {
+  if path.exists() {
+    std::fs::remove_file(path)?;
+  }
+  let listener = UnixListener::bind(path)?;
+  info!("listening on {}", path.display());
+  Ok(Listener::Unix(listener))
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-  SimpleLogger::new().init()?;
-
   let args = Cli::parse();
-  let addr = args.addr.unwrap_or_else(|| "0.0.0.0:6380".to_string());
+  SimpleLogger::new()
+    .with_level(args.log_level.parse()?)
+    .init()?;
+
+  let addrs = listen_addrs(args.addr, &args.bind, args.port);
 
-  let listener = TcpListener::bind(&addr).await?;
+  let mut listeners = bind_all(&addrs, args.tcp_backlog)
+    .await?
+    .into_iter()
+    .map(Listener::Tcp)
+    .collect::<Vec<_>>();
+  if let Some(path) = &args.unixsocket {
+    listeners.push(bind_unix(path)?);
+  }
+  if let (Some(port), Some(cert), Some(key)) = (args.tls_port, &args.tls_cert, &args.tls_key) {
+    let acceptor = tls::acceptor(cert, key)?;
+    for listener in bind_all(
+      &listen_addrs(None, &args.bind, Some(port)),
+      args.tcp_backlog,
+    )
+    .await?
+    {
+      listeners.push(Listener::Tls(listener, acceptor.clone()));
+    }
+  }
+  if let Some(port) = args.health_port {
+    for listener in bind_all(
+      &listen_addrs(None, &args.bind, Some(port)),
+      args.tcp_backlog,
+    )
+    .await?
+    {
+      listeners.push(Listener::Health(listener));
+    }
+  }
+  if listeners.is_empty() {
+    return Err("nothing to listen on, TCP is off and there is no --unixsocket".into());
+  }
   let journal = args.journal.unwrap_or("disabled".to_string());
+  let config = Config {
+    read_only: args.read_only,
+    snapshot_path: args.dbfilename,
+    allow_shutdown: args.allow_shutdown,
+    slowlog_slower_than_micros: args.slowlog_log_slower_than,
+    slowlog_max_len: args.slowlog_max_len,
+    disabled_commands: args
+      .disable_command
+      .iter()
+      .map(|c| c.to_lowercase())
+      .collect(),
+    load_timeout: args.load_timeout.map(Duration::from_secs),
+    rng_seed: None,
+    fsync: args.journal_fsync.parse()?,
+    initial_capacity: args.initial_capacity,
+    maxmemory_policy: args.maxmemory_policy.parse()?,
+    max_inline_len: args.proto_inline_max_size,
+    command_log_format: args.command_log_format.parse()?,
+    active_expire: !args.no_active_expire,
+    active_expire_batch: args.active_expire_batch,
+    active_expire_interval: Duration::from_millis(args.active_expire_interval),
+    tcp_nodelay: !args.no_tcp_nodelay,
+    tcp_keepalive: (args.tcp_keepalive > 0).then(|| Duration::from_secs(args.tcp_keepalive)),
+  };
 
   if journal == "disabled" {
-    start_with_no_journal(&listener).await
+    start_with_no_journal(listeners, config).await
+  } else if args.journal_format == "json" {
+    let path = args.journal_path.unwrap_or_else(|| "./log".into());
+    start_with_json_journaling(listeners, &path, config).await
   } else {
     let path = "./log";
     let mut file = File::create(&path).await?;
     let file = Mutex::new(file);
-    start_with_simple_journaling(&listener, file).await
+    start_with_simple_journaling(listeners, file, config).await
   }
 }
 
-struct Session<'a, W: Writer> {
-  write: WriteHalf<'a>,
-  read: BufReader<ReadHalf<'a>>,
+/// A client connection over any byte stream, TCP or Unix.
+struct Session<S, W: Writer> {
+  id: u64,
+  write: BufWriter<WriteHalf<S>>,
+  read: BufReader<ReadHalf<S>>,
   redis: Arc<Redis<W>>,
+  /// Notified by CLIENT KILL from another session.
+  kill: Arc<Notify>,
+  /// Pub/sub messages for this client, written out between commands.
+  inbox: UnboundedReceiver<RedisValue>,
+  inbox_sender: UnboundedSender<RedisValue>,
+  channels: HashSet<Vec<u8>>,
+  patterns: HashSet<Vec<u8>>,
+  /// Set by QUIT, the connection is closed once the reply is out.
+  quitting: bool,
+  /// Arguments of the commands queued since MULTI, run by EXEC.
+  transaction: Option<Vec<Vec<Vec<u8>>>>,
+  /// Set once a command fails to queue, EXEC then runs nothing.
+  dirty: bool,
+  /// Set by MONITOR to the task streaming commands to the session, which
+  /// then only listens until RESET or QUIT.
+  monitor: Option<AbortHandle>,
+  /// How replies are encoded, RESP2 unless the client negotiated RESP3.
+  protocol: Protocol,
+}
+
+/// Same limit as Redis' default proto-max-bulk-len.
+const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+/// Reads one line into `r`, failing once `max_len` bytes went by without a
+/// line break so a client can't make it buffer without end.
+async fn read_line_limited<T: AsyncRead + Unpin>(
+  read: &mut BufReader<T>,
+  r: &mut String,
+  max_len: usize,
+) -> Result<usize, RedisError> {
+  let limit = (max_len as u64).saturating_add(1);
+  let n = (&mut *read).take(limit).read_line(r).await?;
+  if n > max_len && !r.ends_with('\n') {
+    return Err(RedisError::Protocol("too big inline request".to_string()));
+  }
+  Ok(n)
 }
 
-async fn read_cmd<'a, T: AsyncRead + Unpin>(read: &mut BufReader<T>) -> Result<String, RedisError> {
+/// Reads one command frame as its decoded arguments, the name first, `None`
+/// once the stream ends before a new frame started. Blank lines between
+/// frames are skipped and inline commands are split on whitespace.
+///
+/// Multibulk frames are read by their declared lengths, a frame that breaks
+/// the framing is reported as `RedisError::Protocol` as the rest of the
+/// stream can't be trusted. So is an inline command or a multibulk header
+/// line longer than `max_inline_len`.
+async fn read_cmd<'a, T: AsyncRead + Unpin>(
+  read: &mut BufReader<T>,
+  max_inline_len: usize,
+) -> Result<Option<Vec<Vec<u8>>>, RedisError> {
   let mut r = String::new();
-  read.read_line(&mut r).await?;
+  loop {
+    if read_line_limited(read, &mut r, max_inline_len).await? == 0 {
+      return Ok(None);
+    }
+    if !r.trim().is_empty() {
+      break;
+    }
+    r.clear();
+  }
 
-  let mut cmd = String::new();
-  if r.chars().nth(0) == Some('*') {
-    let rest = &r.trim()[1..];
-    let mut cmd_parts_count = rest.parse::<usize>()?;
+  if !r.starts_with('*') {
+    let args = r.split_whitespace().map(|arg| arg.as_bytes().to_vec());
+    return Ok(Some(args.collect()));
+  }
 
+  let cmd_parts_count = r.trim_end()[1..]
+    .parse::<usize>()
+    .map_err(|_| RedisError::Protocol("invalid multibulk length".to_string()))?;
+
+  let mut args = Vec::new();
+  for _ in 0..cmd_parts_count {
     r.clear();
-    while cmd_parts_count > 0 {
-      read.read_line(&mut r).await?;
-      if r.chars().nth(0) != Some('$') {
-        cmd_parts_count -= 1;
-      }
-      cmd.push_str(r.as_str());
-      r.clear();
+    if read_line_limited(read, &mut r, max_inline_len).await? == 0 {
+      return Err(RedisError::Protocol("unexpected end of stream".to_string()));
     }
-    cmd = cmd.to_string();
-  } else {
-    cmd = r.to_string();
+    let len = r
+      .strip_prefix('$')
+      .and_then(|len| len.trim_end().parse::<usize>().ok())
+      .ok_or_else(|| RedisError::Protocol(format!("expected '$', got '{}'", r.trim_end())))?;
+    if len > MAX_BULK_LEN {
+      return Err(RedisError::Protocol("invalid bulk length".to_string()));
+    }
+
+    let mut arg = vec![0; len + 2];
+    read.read_exact(&mut arg).await?;
+    if !arg.ends_with(b"\r\n") {
+      return Err(RedisError::Protocol("bulk length mismatch".to_string()));
+    }
+    arg.truncate(len);
+    args.push(arg);
   }
-  Ok(cmd)
+  Ok(Some(args))
+}
+
+/// Name of the command `args` run, lowercased.
+fn command_name(args: &[Vec<u8>]) -> String {
+  let name = args.first().map_or(&[][..], Vec::as_slice);
+  String::from_utf8_lossy(name).to_lowercase()
 }
 
-impl<'a, W: Writer + Send> Session<'a, W> {
-  pub fn new(socket: &'a mut TcpStream, redis: Arc<Redis<W>>) -> Session<'a, W> {
-    let (read_half, write) = socket.split();
+impl<S: AsyncRead + AsyncWrite + Send, W: Writer + Send + Sync + 'static> Session<S, W> {
+  pub fn new(socket: S, addr: String, redis: Arc<Redis<W>>) -> Session<S, W> {
+    let (id, kill) = redis.register_client(addr);
+
+    let (read_half, write) = tokio::io::split(socket);
     let read = BufReader::new(read_half);
+    let write = BufWriter::new(write);
+    let (inbox_sender, inbox) = unbounded_channel();
+
+    Session {
+      id,
+      read,
+      write,
+      redis,
+      kill,
+      inbox,
+      inbox_sender,
+      channels: HashSet::new(),
+      patterns: HashSet::new(),
+      quitting: false,
+      transaction: None,
+      dirty: false,
+      monitor: None,
+      protocol: Protocol::default(),
+    }
+  }
+
+  /// In subscriber mode once subscribed to any channel or pattern.
+  fn is_subscribed(&self) -> bool {
+    !self.channels.is_empty() || !self.patterns.is_empty()
+  }
+
+  /// Subscribes to or, with `subscribe` unset, unsubscribes from `names`,
+  /// which are patterns if `pattern` is set. Unsubscribing from nothing
+  /// unsubscribes from everything.
+  ///
+  /// Every name gets its own confirmation frame, all but the last are
+  /// written here and the last is returned as the reply.
+  async fn subscription(
+    &mut self,
+    names: &[&[u8]],
+    pattern: bool,
+    subscribe: bool,
+  ) -> Result<RedisValue, RedisError> {
+    let kind = match (pattern, subscribe) {
+      (false, true) => "subscribe",
+      (false, false) => "unsubscribe",
+      (true, true) => "psubscribe",
+      (true, false) => "punsubscribe",
+    };
+    let (subscribed, others) = if pattern {
+      (&mut self.patterns, self.channels.len())
+    } else {
+      (&mut self.channels, self.patterns.len())
+    };
+    let names = match names {
+      [] if !subscribe => subscribed.iter().cloned().collect(),
+      names => names.iter().map(|n| n.to_vec()).collect::<Vec<_>>(),
+    };
+
+    let pubsub = self.redis.pubsub();
+    let mut frames = Vec::new();
+    for name in names {
+      if subscribe {
+        pubsub.subscribe(&name, pattern, self.id, self.inbox_sender.clone());
+        subscribed.insert(name.clone());
+      } else {
+        pubsub.unsubscribe(&name, pattern, self.id);
+        subscribed.remove(&name);
+      }
+      frames.push(RedisValue::Nested(vec![
+        RedisValue::from(kind),
+        RedisValue::from(name),
+        RedisValue::from(others + subscribed.len()),
+      ]));
+    }
 
-    Session { read, write, redis }
+    let last = frames.pop().unwrap_or_else(|| {
+      RedisValue::Nested(vec![
+        RedisValue::from(kind),
+        RedisValue::NullBulk,
+        RedisValue::from(others + subscribed.len()),
+      ])
+    });
+    for frame in frames {
+      encoder::write_reply(&mut self.write, &Ok(frame), self.protocol).await?;
+    }
+    Ok(last)
+  }
+
+  /// Reads and executes the next command, `None` once the client is gone
+  /// and `Some(None)` for input that gets no reply.
+  async fn handle_cmd(&mut self) -> Option<Option<Result<RedisValue, RedisError>>> {
+    let args = match read_cmd(&mut self.read, self.redis.max_inline_len()).await {
+      Ok(Some(args)) => args,
+      Ok(None) => return None,
+      Err(e) => return Some(Some(Err(e))),
+    };
+    let command = parse(&args);
+    // a monitor only listens, everything but QUIT and RESET is dropped
+    if self.monitor.is_some() && !matches!(command, Ok(Command::Quit | Command::Reset)) {
+      return Some(None);
+    }
+    match command {
+      Ok(command) => {
+        let started = Instant::now();
+        let reply = self.dispatch(command, &args).await;
+        Some(Some(self.with_latency(reply, started.elapsed())))
+      }
+      Err(e) => {
+        self.dirty |= self.transaction.is_some();
+        Some(Some(Err(e)))
+      }
+    }
+  }
+
+  /// Prefixes a reply that took over the slowlog threshold with its latency
+  /// for RESP3 clients, RESP2 replies are left alone.
+  fn with_latency(
+    &self,
+    reply: Result<RedisValue, RedisError>,
+    elapsed: Duration,
+  ) -> Result<RedisValue, RedisError> {
+    match reply {
+      Ok(value) if self.protocol == Protocol::Resp3 && self.redis.is_slow(elapsed) => {
+        Ok(RedisValue::Attributed(
+          vec![("latency-us", elapsed.as_micros() as i64)],
+          Box::new(value),
+        ))
+      }
+      reply => reply,
+    }
   }
 
-  async fn handle_cmd(&mut self) -> Result<RedisValue, RedisError> {
-    let cmd = read_cmd(&mut self.read).await?;
+  /// Runs a parsed command, `args` are its arguments for queueing and error
+  /// messages.
+  ///
+  /// EXEC runs the queued commands one after another but doesn't keep other
+  /// clients from running theirs in between.
+  async fn dispatch(
+    &mut self,
+    command: Command<'_>,
+    args: &[Vec<u8>],
+  ) -> Result<RedisValue, RedisError> {
+    let name = command_name(args);
+    if self.redis.is_disabled(&name) {
+      self.dirty |= self.transaction.is_some();
+      return Err(RedisError::Other(format!("unknown command '{name}'")));
+    }
 
-    if cmd.trim().is_empty() {
-      return Ok(RedisValue::Nothing);
+    let subscriber_command = matches!(
+      command,
+      Command::Subscribe(_)
+        | Command::Unsubscribe(_)
+        | Command::Psubscribe(_)
+        | Command::Punsubscribe(_)
+        | Command::Ping(_)
+        | Command::Quit
+        | Command::Reset
+    );
+    if self.is_subscribed() && !subscriber_command {
+      return Err(RedisError::Other(format!(
+        "Can't execute '{}': only (P)SUBSCRIBE / (P)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+        name
+      )));
     }
 
-    let command = parse(cmd.as_str())?;
+    let transaction_command = matches!(
+      command,
+      Command::Multi | Command::Exec | Command::Discard | Command::Reset | Command::Quit
+    );
+    match self.transaction.as_mut() {
+      Some(queued) if !transaction_command => {
+        queued.push(args.to_vec());
+        return Ok(RedisValue::Status("QUEUED".into()));
+      }
+      _ => {}
+    }
 
-    self.redis.exec(&command).await
-    // do something else with the command?
+    if command != Command::Monitor {
+      self.redis.feed_monitors(self.id, args);
+    }
+    match command {
+      Command::ClientId => Ok(RedisValue::Integer(self.id as i64)),
+      Command::Monitor => {
+        let mut lines = self.redis.monitor();
+        let inbox = self.inbox_sender.clone();
+        let forward = tokio::spawn(async move {
+          loop {
+            match lines.recv().await {
+              Ok(line) => {
+                if inbox.send(RedisValue::Status(line.into())).is_err() {
+                  break;
+                }
+              }
+              Err(broadcast::error::RecvError::Lagged(missed)) => {
+                warn!("monitor fell behind, {} commands not shown", missed);
+              }
+              Err(broadcast::error::RecvError::Closed) => break,
+            }
+          }
+        });
+        self.monitor = Some(forward.abort_handle());
+        Ok(RedisValue::Ok)
+      }
+      Command::BgRewriteAof => {
+        let redis = self.redis.clone();
+        tokio::spawn(async move {
+          if let Err(e) = redis.rewrite_journal().await {
+            warn!("journal rewrite failed: {e}");
+          }
+        });
+        Ok(RedisValue::Status(
+          "Background append only file rewriting started".into(),
+        ))
+      }
+      Command::Quit => {
+        self.quitting = true;
+        Ok(RedisValue::Ok)
+      }
+      Command::Subscribe(channels) => self.subscription(&channels, false, true).await,
+      Command::Unsubscribe(channels) => self.subscription(&channels, false, false).await,
+      Command::Psubscribe(patterns) => self.subscription(&patterns, true, true).await,
+      Command::Punsubscribe(patterns) => self.subscription(&patterns, true, false).await,
+      Command::Multi if self.transaction.is_some() => Err(RedisError::Other(
+        "MULTI calls can not be nested".to_string(),
+      )),
+      Command::Multi => {
+        self.transaction = Some(Vec::new());
+        self.dirty = false;
+        Ok(RedisValue::Ok)
+      }
+      Command::Exec => match self.transaction.take() {
+        None => Err(RedisError::Other("EXEC without MULTI".to_string())),
+        Some(_) if self.dirty => Err(RedisError::ExecAbort),
+        Some(queued) => {
+          let mut replies = Vec::with_capacity(queued.len());
+          for args in queued {
+            let reply = match parse(&args) {
+              Ok(command) => Box::pin(self.dispatch(command, &args)).await,
+              Err(e) => Err(e),
+            };
+            replies.push(reply.unwrap_or_else(RedisValue::Error));
+          }
+          Ok(RedisValue::Nested(replies))
+        }
+      },
+      Command::Discard => match self.transaction.take() {
+        None => Err(RedisError::Other("DISCARD without MULTI".to_string())),
+        Some(_) => Ok(RedisValue::Ok),
+      },
+      Command::Reset => {
+        self.transaction = None;
+        if let Some(monitor) = self.monitor.take() {
+          monitor.abort();
+        }
+        self.protocol = Protocol::Resp2;
+        let pubsub = self.redis.pubsub();
+        for channel in self.channels.drain() {
+          pubsub.unsubscribe(&channel, false, self.id);
+        }
+        for pattern in self.patterns.drain() {
+          pubsub.unsubscribe(&pattern, true, self.id);
+        }
+        Ok(RedisValue::Status("RESET".into()))
+      }
+      Command::Hello(version) => {
+        self.protocol = match version {
+          None => self.protocol,
+          Some(2) => Protocol::Resp2,
+          Some(3) => Protocol::Resp3,
+          Some(_) => return Err(RedisError::NoProto),
+        };
+        let proto = match self.protocol {
+          Protocol::Resp2 => 2,
+          Protocol::Resp3 => 3,
+        };
+        let role = if self.redis.is_replica().await {
+          "replica"
+        } else {
+          "master"
+        };
+        Ok(RedisValue::Map(vec![
+          ("server", RedisValue::from("redis")),
+          ("version", RedisValue::from(env!("CARGO_PKG_VERSION"))),
+          ("proto", RedisValue::Integer(proto)),
+          ("id", RedisValue::Integer(self.id as i64)),
+          ("mode", RedisValue::from("standalone")),
+          ("role", RedisValue::from(role)),
+          ("modules", RedisValue::Nested(Vec::new())),
+        ]))
+      }
+      command => self.redis.exec(&command).await,
+    }
   }
 
   pub async fn run(&mut self) {
+    let kill = self.kill.clone();
     loop {
-      let output = self.handle_cmd().await;
-
-      let raw_output: Cow<'static, str> = match output {
-        Ok(RedisValue::Ok) => "+OK\r\n".into(),
-        Ok(RedisValue::EmptyString) => "$-1\r\n".into(),
-        Ok(RedisValue::SimpleString(elem)) => {
-          let mut s = String::new();
-          s.push_str(format!("${}\r\n", elem.len()).as_str());
-          for ch in &*elem {
-            s.push(*ch as char);
+      // wait for input without consuming any, reading a frame can't be
+      // interrupted by a message half way through
+      if self.read.buffer().is_empty() {
+        tokio::select! {
+          _ = kill.notified() => {
+            info!("client id={} killed", self.id);
+            break;
           }
-          s.push_str("\r\n");
-          s.into()
-        }
-        Ok(RedisValue::Nothing) => "\0".into(), // to close connection if it's
-        Ok(RedisValue::Array(v)) if v.is_empty() => "*-1\r\n".into(),
-        Ok(RedisValue::Array(v)) => {
-          let mut s = String::new();
-          s.push_str(format!("*{}\r\n", v.len()).as_str());
-          v.iter().for_each(|elem| {
-            s.push_str(format!("${}\r\n", elem.len()).as_str());
-            s.push_str(elem);
-            s.push_str("\r\n");
-          });
-          s.into()
+          Some(message) = self.inbox.recv() => {
+            encoder::write_reply(&mut self.write, &Ok(message), self.protocol)
+              .await
+              .expect("can't write response");
+            self.write.flush().await.expect("can't write response");
+            continue;
+          }
+          _ = self.read.fill_buf() => {}
         }
-        Ok(RedisValue::Integer(v)) => format!(":{v}\r\n").into(),
-        Ok(RedisValue::BulkString(_)) => "$-1\r\n".into(),
-        Err(e @ RedisError::Type) => format!("-WRONGTYPE {e}\r\n").into(),
-        Err(RedisError::Parse(msg)) => {
-          warn!("parse error: {msg}");
-          format!("-ERR {msg}\r\n").into()
+      }
+
+      let output = tokio::select! {
+        _ = kill.notified() => {
+          info!("client id={} killed", self.id);
+          break;
         }
-        Err(e) => format!("-ERR {e}\r\n").into(),
+        output = self.handle_cmd() => match output {
+          Some(Some(output)) => output,
+          Some(None) => continue,
+          None => break,
+        },
       };
 
-      self
-        .write
-        .write_all(raw_output.as_bytes())
+      encoder::write_reply(&mut self.write, &output, self.protocol)
         .await
         .expect("can't write response");
+
+      // like Redis, reply to a protocol error and drop the connection, there
+      // is no telling where the next frame starts
+      if let Err(RedisError::Protocol(_)) = output {
+        let _ = self.write.flush().await;
+        break;
+      }
+      if self.quitting {
+        let _ = self.write.shutdown().await;
+        break;
+      }
+
+      // pipelined commands still buffered get their replies coalesced,
+      // everything is flushed before blocking on the socket again
+      if self.read.buffer().is_empty() {
+        self.write.flush().await.expect("can't write response");
+      }
     }
   }
 }
 
+impl<S, W: Writer> Drop for Session<S, W> {
+  fn drop(&mut self) {
+    let pubsub = self.redis.pubsub();
+    for channel in &self.channels {
+      pubsub.unsubscribe(channel, false, self.id);
+    }
+    for pattern in &self.patterns {
+      pubsub.unsubscribe(pattern, true, self.id);
+    }
+    self.redis.unregister_client(self.id);
+  }
+}
+
 #[cfg(test)]
 mod tests {
-  use crate::read_cmd;
-  use tokio::io::BufReader;
+  use crate::cmd::Command;
+  use crate::journal::Disabled;
+  use crate::redis::{Config, Redis, DEFAULT_MAX_INLINE_LEN};
+  use crate::{
+    accept_retrying, bind_all, bind_unix, configure_tcp, listen_addrs, read_cmd, start, Listener,
+  };
+  use std::net::SocketAddr;
+  use std::sync::Arc;
+  use std::time::Duration;
+  use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+  use tokio::net::{TcpListener, TcpStream, UnixStream};
+
+  /// Encodes a command as a RESP multibulk, the way clients send it.
+  fn resp(args: &[&str]) -> Vec<u8> {
+    let mut out = format!("*{}\r\n", args.len());
+    for arg in args {
+      out.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+    }
+    out.into_bytes()
+  }
+
+  async fn spawn_server() -> SocketAddr {
+    spawn_server_with_config(Config::default()).await
+  }
+
+  async fn spawn_server_with_config(config: Config) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let redis = Arc::new(Redis::with_config(Disabled {}, config).await);
+    tokio::spawn(async move {
+      let _ = start(redis, vec![Listener::Tcp(listener)]).await;
+    });
+    addr
+  }
+
+  #[test]
+  fn test_listen_addrs() {
+    assert_eq!(listen_addrs(None, &[], None), vec!["0.0.0.0:6380"]);
+    assert!(listen_addrs(None, &[], Some(0)).is_empty());
+    assert_eq!(
+      listen_addrs(Some("127.0.0.1:7000".to_string()), &[], Some(1)),
+      vec!["127.0.0.1:7000"]
+    );
+    let bind = vec!["127.0.0.1".to_string(), "::1".to_string()];
+    assert_eq!(
+      listen_addrs(Some("ignored".to_string()), &bind, Some(7000)),
+      vec!["127.0.0.1:7000", "[::1]:7000"]
+    );
+  }
+
+  #[tokio::test]
+  async fn test_multiple_listeners() {
+    let addrs = vec![
+      "127.0.0.1:0".to_string(),
+      "127.0.0.1:0".to_string(),
+      "256.0.0.1:0".to_string(),
+    ];
+    let listeners = bind_all(&addrs, 16).await.unwrap();
+    assert_eq!(listeners.len(), 2);
+    let bound = listeners
+      .iter()
+      .map(|l| l.local_addr().unwrap())
+      .collect::<Vec<_>>();
+
+    let redis = Arc::new(Redis::new(Disabled {}).await);
+    tokio::spawn(async move {
+      let _ = start(redis, listeners.into_iter().map(Listener::Tcp).collect()).await;
+    });
+
+    for addr in bound {
+      let mut client = TcpStream::connect(addr).await.unwrap();
+      client.write_all(&resp(&["PING"])).await.unwrap();
+      let mut reply = [0; 7];
+      client.read_exact(&mut reply).await.unwrap();
+      assert_eq!(&reply, b"+PONG\r\n");
+    }
+
+    assert!(bind_all(&addrs[2..], 16).await.is_err());
+  }
+
+  #[tokio::test]
+  async fn test_tls() {
+    use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName};
+    use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+    use tokio_rustls::TlsConnector;
+
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let dir = std::env::temp_dir();
+    let cert_path = dir.join(format!("reddis-{}.crt", std::process::id()));
+    let key_path = dir.join(format!("reddis-{}.key", std::process::id()));
+    std::fs::write(&cert_path, cert.cert.pem()).unwrap();
+    std::fs::write(&key_path, cert.key_pair.serialize_pem()).unwrap();
+    let acceptor = crate::tls::acceptor(&cert_path, &key_path).unwrap();
+    std::fs::remove_file(&cert_path).unwrap();
+    std::fs::remove_file(&key_path).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let redis = Arc::new(Redis::new(Disabled {}).await);
+    tokio::spawn(async move {
+      let _ = start(redis, vec![Listener::Tls(listener, acceptor)]).await;
+    });
+
+    let mut roots = RootCertStore::empty();
+    roots
+      .add(CertificateDer::from(cert.cert.der().to_vec()))
+      .unwrap();
+    let config = ClientConfig::builder()
+      .with_root_certificates(roots)
+      .with_no_client_auth();
+    let connector = TlsConnector::from(Arc::new(config));
+    let socket = TcpStream::connect(addr).await.unwrap();
+    let name = ServerName::try_from("localhost").unwrap();
+    let mut client = connector.connect(name, socket).await.unwrap();
+
+    client.write_all(&resp(&["PING"])).await.unwrap();
+    let mut reply = [0; 7];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, b"+PONG\r\n");
+  }
+
+  #[tokio::test]
+  async fn test_unix_socket() {
+    let path = std::env::temp_dir().join(format!("reddis-{}.sock", std::process::id()));
+    let listener = bind_unix(&path).unwrap();
+    let redis = Arc::new(Redis::new(Disabled {}).await);
+    tokio::spawn(async move {
+      let _ = start(redis, vec![listener]).await;
+    });
+
+    let mut client = UnixStream::connect(&path).await.unwrap();
+    client.write_all(&resp(&["PING"])).await.unwrap();
+    let mut reply = [0; 7];
+    client.read_exact(&mut reply).await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(&reply, b"+PONG\r\n");
+  }
+
+  #[tokio::test]
+  async fn test_ping() {
+    let mut client = TcpStream::connect(spawn_server().await).await.unwrap();
+    client.write_all(&resp(&["PING"])).await.unwrap();
+    client.write_all(&resp(&["PING", "hello"])).await.unwrap();
+
+    let expected = b"+PONG\r\n$5\r\nhello\r\n";
+    let mut reply = vec![0; expected.len()];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply, expected);
+  }
+
+  #[tokio::test]
+  async fn test_inline_ping() {
+    let mut client = TcpStream::connect(spawn_server().await).await.unwrap();
+    for _ in 0..2 {
+      client.write_all(b"PING\r\n").await.unwrap();
+      let mut reply = vec![0; 7];
+      client.read_exact(&mut reply).await.unwrap();
+      assert_eq!(reply, b"+PONG\r\n");
+    }
+  }
+
+  #[tokio::test]
+  async fn test_get_empty_value() {
+    let mut client = TcpStream::connect(spawn_server().await).await.unwrap();
+    client
+      .write_all(&resp(&["SET", "empty", ""]))
+      .await
+      .unwrap();
+    client.write_all(&resp(&["GET", "empty"])).await.unwrap();
+    client.write_all(&resp(&["GET", "missing"])).await.unwrap();
+
+    let expected = b"+OK\r\n$0\r\n\r\n$-1\r\n";
+    let mut reply = vec![0; expected.len()];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply, expected);
+  }
+
+  #[tokio::test]
+  async fn test_binary_value_round_trips() {
+    let mut client = TcpStream::connect(spawn_server().await).await.unwrap();
+    client
+      .write_all(b"*3\r\n$3\r\nSET\r\n$3\r\nbin\r\n$4\r\n\xff\x00\r\n\r\n")
+      .await
+      .unwrap();
+    client.write_all(&resp(&["GET", "bin"])).await.unwrap();
+
+    let expected = b"+OK\r\n$4\r\n\xff\x00\r\n\r\n";
+    let mut reply = vec![0; expected.len()];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply, expected);
+  }
+
+  #[tokio::test]
+  async fn test_del_big_list() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let redis = Arc::new(Redis::new(Disabled {}).await);
+    let values = (0..500_000).map(|i| i.to_string()).collect::<Vec<_>>();
+    let values = values.iter().map(|v| v.as_bytes()).collect();
+    redis.exec(&Command::Rpush("big", values)).await.unwrap();
+    tokio::spawn(async move {
+      let _ = start(redis, vec![Listener::Tcp(listener)]).await;
+    });
+
+    let mut deleting = TcpStream::connect(addr).await.unwrap();
+    let mut pinging = TcpStream::connect(addr).await.unwrap();
+    deleting
+      .write_all(&resp(&["DEL", "big", "big", "missing"]))
+      .await
+      .unwrap();
+    pinging.write_all(&resp(&["PING"])).await.unwrap();
+
+    let mut reply = [0; 7];
+    tokio::time::timeout(Duration::from_secs(1), pinging.read_exact(&mut reply))
+      .await
+      .unwrap()
+      .unwrap();
+    assert_eq!(&reply, b"+PONG\r\n");
+    let mut reply = [0; 4];
+    deleting.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, b":1\r\n");
+  }
+
+  #[tokio::test]
+  async fn test_health() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let redis = Arc::new(Redis::new(Disabled {}).await);
+    tokio::spawn(async move {
+      let _ = start(redis, vec![Listener::Health(listener)]).await;
+    });
+
+    let mut client = TcpStream::connect(addr).await.unwrap();
+    client
+      .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n")
+      .await
+      .unwrap();
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).await.unwrap();
+    assert!(response.starts_with(b"HTTP/1.1 200 OK\r\n"));
+
+    let mut client = TcpStream::connect(addr).await.unwrap();
+    client
+      .write_all(b"GET /other HTTP/1.1\r\n\r\n")
+      .await
+      .unwrap();
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).await.unwrap();
+    assert!(response.starts_with(b"HTTP/1.1 404 Not Found\r\n"));
+  }
+
+  #[tokio::test]
+  async fn test_pipelined_replies() {
+    let mut client = TcpStream::connect(spawn_server().await).await.unwrap();
+    client.write_all(b"PING\r\nPING\r\nPING\r\n").await.unwrap();
+
+    let expected = b"+PONG\r\n".repeat(3);
+    let mut replies = vec![0; expected.len()];
+    client.read_exact(&mut replies).await.unwrap();
+    assert_eq!(replies, expected);
+  }
+
+  #[tokio::test]
+  async fn test_client_kill() {
+    let addr = spawn_server().await;
+    let mut victim = TcpStream::connect(addr).await.unwrap();
+    let mut killer = TcpStream::connect(addr).await.unwrap();
+
+    victim.write_all(&resp(&["CLIENT", "ID"])).await.unwrap();
+    let mut reply = [0; 64];
+    let n = victim.read(&mut reply).await.unwrap();
+    let id = std::str::from_utf8(&reply[1..n - 2]).unwrap().to_string();
+
+    let kill = resp(&["CLIENT", "KILL", "ID", &id]);
+    killer.write_all(&kill).await.unwrap();
+    let n = killer.read(&mut reply).await.unwrap();
+    assert_eq!(&reply[..n], b":1\r\n");
+
+    assert_eq!(victim.read(&mut reply).await.unwrap(), 0);
+  }
+
+  #[tokio::test]
+  async fn test_session_panic_is_isolated() {
+    let addr = spawn_server().await;
+    let mut other = TcpStream::connect(addr).await.unwrap();
+    let mut victim = TcpStream::connect(addr).await.unwrap();
+    // DEBUG PANIC only exists in test builds
+    victim.write_all(&resp(&["DEBUG", "PANIC"])).await.unwrap();
+    let mut reply = Vec::new();
+    victim.read_to_end(&mut reply).await.unwrap();
+    assert!(reply.is_empty());
+
+    other
+      .write_all(&resp(&["SET", "key", "value"]))
+      .await
+      .unwrap();
+    other.write_all(&resp(&["GET", "key"])).await.unwrap();
+    let expected = b"+OK\r\n$5\r\nvalue\r\n";
+    let mut reply = vec![0; expected.len()];
+    other.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply, expected);
+
+    let mut fresh = TcpStream::connect(addr).await.unwrap();
+    fresh.write_all(&resp(&["PING"])).await.unwrap();
+    let mut reply = [0; 7];
+    fresh.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, b"+PONG\r\n");
+  }
+
+  #[tokio::test]
+  async fn test_protocol_error_closes_connection() {
+    let mut client = TcpStream::connect(spawn_server().await).await.unwrap();
+    client.write_all(b"*1\r\n+PING\r\n").await.unwrap();
+    client.write_all(&resp(&["PING"])).await.unwrap();
+
+    let mut replies = Vec::new();
+    client.read_to_end(&mut replies).await.unwrap();
+    assert_eq!(
+      replies,
+      b"-ERR Protocol error: expected '$', got '+PING'\r\n"
+    );
+  }
+
+  #[tokio::test]
+  async fn test_too_big_inline_request() {
+    let config = Config {
+      max_inline_len: 1024,
+      ..Config::default()
+    };
+    let mut client = TcpStream::connect(spawn_server_with_config(config).await)
+      .await
+      .unwrap();
+    client.write_all(&[b'a'; 4096]).await.unwrap();
+
+    let mut replies = Vec::new();
+    client.read_to_end(&mut replies).await.unwrap();
+    assert_eq!(replies, b"-ERR Protocol error: too big inline request\r\n");
+  }
+
+  #[tokio::test]
+  async fn test_slow_reader_holds_no_lock() {
+    let addr = spawn_server().await;
+    let mut slow = TcpStream::connect(addr).await.unwrap();
+    let big = "x".repeat(1 << 20);
+    slow.write_all(&resp(&["SET", "big", &big])).await.unwrap();
+    // far more than the socket buffers hold, the session blocks writing
+    for _ in 0..64 {
+      slow.write_all(&resp(&["GET", "big"])).await.unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = TcpStream::connect(addr).await.unwrap();
+    client.write_all(&resp(&["SET", "k", "v"])).await.unwrap();
+    let mut reply = [0; 5];
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut reply))
+      .await
+      .expect("SET blocked behind a slow reader")
+      .unwrap();
+    assert_eq!(&reply, b"+OK\r\n");
+  }
+
+  #[tokio::test]
+  async fn test_accept_retrying() {
+    let mut attempts = 0;
+    let accepted = accept_retrying(|| {
+      attempts += 1;
+      let attempt = attempts;
+      async move {
+        if attempt < 3 {
+          Err(std::io::Error::other("Too many open files"))
+        } else {
+          Ok(attempt)
+        }
+      }
+    })
+    .await;
+    assert_eq!(accepted, 3);
+  }
+
+  #[tokio::test]
+  async fn test_configure_tcp() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let _client = TcpStream::connect(listener.local_addr().unwrap())
+      .await
+      .unwrap();
+    let (socket, _) = listener.accept().await.unwrap();
+    assert!(!socket.nodelay().unwrap());
+
+    let config = Config {
+      tcp_keepalive: Some(Duration::from_secs(60)),
+      ..Config::default()
+    };
+    configure_tcp(&socket, &Redis::with_config(Disabled {}, config).await);
+    assert!(socket.nodelay().unwrap());
+    assert!(socket2::SockRef::from(&socket).keepalive().unwrap());
+  }
+
+  #[tokio::test]
+  async fn test_read_cmd_binary_safe_lengths() {
+    let test_input = b"*2\r\n$3\r\nGET\r\n$4\r\n$a\nb\r\n" as &[u8];
+    let mut buf = BufReader::new(test_input);
+
+    assert_eq!(
+      read_cmd(&mut buf, DEFAULT_MAX_INLINE_LEN)
+        .await
+        .unwrap()
+        .unwrap(),
+      vec![b"GET".to_vec(), b"$a\nb".to_vec()]
+    );
+    assert_eq!(
+      read_cmd(&mut buf, DEFAULT_MAX_INLINE_LEN).await.unwrap(),
+      None
+    );
+  }
+
+  #[tokio::test]
+  async fn test_subscribe() {
+    let addr = spawn_server().await;
+    let mut subscriber = TcpStream::connect(addr).await.unwrap();
+    let mut publisher = TcpStream::connect(addr).await.unwrap();
+
+    subscriber
+      .write_all(&resp(&["SUBSCRIBE", "news", "sport"]))
+      .await
+      .unwrap();
+    let expected = b"*3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n\
+                     *3\r\n$9\r\nsubscribe\r\n$5\r\nsport\r\n:2\r\n";
+    let mut reply = vec![0; expected.len()];
+    subscriber.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply, expected);
+
+    publisher
+      .write_all(&resp(&["PUBLISH", "news", "hello"]))
+      .await
+      .unwrap();
+    let mut reply = [0; 4];
+    publisher.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, b":1\r\n");
+
+    let expected = b"*3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$5\r\nhello\r\n";
+    let mut reply = vec![0; expected.len()];
+    subscriber.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply, expected);
+  }
+
+  #[tokio::test]
+  async fn test_publish_after_subscriber_disconnects() {
+    let addr = spawn_server().await;
+    let mut staying = TcpStream::connect(addr).await.unwrap();
+    let mut leaving = TcpStream::connect(addr).await.unwrap();
+    let mut publisher = TcpStream::connect(addr).await.unwrap();
+
+    let expected = b"*3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n";
+    for subscriber in [&mut staying, &mut leaving] {
+      subscriber
+        .write_all(&resp(&["SUBSCRIBE", "news"]))
+        .await
+        .unwrap();
+      let mut reply = vec![0; expected.len()];
+      subscriber.read_exact(&mut reply).await.unwrap();
+      assert_eq!(reply, expected);
+    }
+    drop(leaving);
+
+    // The leaving session unsubscribes once it reads EOF, publish until then.
+    let mut published = 0;
+    loop {
+      publisher
+        .write_all(&resp(&["PUBLISH", "news", "hello"]))
+        .await
+        .unwrap();
+      published += 1;
+      let mut reply = [0; 4];
+      publisher.read_exact(&mut reply).await.unwrap();
+      if &reply == b":1\r\n" {
+        break;
+      }
+      assert_eq!(&reply, b":2\r\n");
+      assert!(published < 100, "subscriber never pruned");
+      tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    let expected = b"*3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$5\r\nhello\r\n";
+    for _ in 0..published {
+      let mut reply = vec![0; expected.len()];
+      staying.read_exact(&mut reply).await.unwrap();
+      assert_eq!(reply, expected);
+    }
+  }
+
+  #[tokio::test]
+  async fn test_subscriber_mode_rejects_commands() {
+    let mut client = TcpStream::connect(spawn_server().await).await.unwrap();
+    client
+      .write_all(&resp(&["SUBSCRIBE", "news"]))
+      .await
+      .unwrap();
+    let mut reply = vec![0; 33];
+    client.read_exact(&mut reply).await.unwrap();
+
+    client.write_all(&resp(&["GET", "key"])).await.unwrap();
+    let expected = b"-ERR Can't execute 'get': only (P)SUBSCRIBE / (P)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context\r\n";
+    let mut reply = vec![0; expected.len()];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply, expected);
+
+    client.write_all(&resp(&["UNSUBSCRIBE"])).await.unwrap();
+    let expected = b"*3\r\n$11\r\nunsubscribe\r\n$4\r\nnews\r\n:0\r\n";
+    let mut reply = vec![0; expected.len()];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply, expected);
+
+    client.write_all(&resp(&["GET", "key"])).await.unwrap();
+    let mut reply = [0; 5];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, b"$-1\r\n");
+  }
+
+  #[tokio::test]
+  async fn test_quit() {
+    let mut client = TcpStream::connect(spawn_server().await).await.unwrap();
+    client.write_all(&resp(&["QUIT"])).await.unwrap();
+    client.write_all(&resp(&["PING"])).await.unwrap();
+
+    let mut replies = Vec::new();
+    client.read_to_end(&mut replies).await.unwrap();
+    assert_eq!(replies, b"+OK\r\n");
+  }
+
+  #[tokio::test]
+  async fn test_multi_exec() {
+    let mut client = TcpStream::connect(spawn_server().await).await.unwrap();
+    client.write_all(&resp(&["MULTI"])).await.unwrap();
+    client.write_all(&resp(&["SET", "key", "1"])).await.unwrap();
+    client.write_all(&resp(&["INCR", "key"])).await.unwrap();
+    client.write_all(&resp(&["EXEC"])).await.unwrap();
+
+    let expected = b"+OK\r\n+QUEUED\r\n+QUEUED\r\n*2\r\n+OK\r\n:2\r\n";
+    let mut reply = vec![0; expected.len()];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply, expected);
+  }
+
+  #[tokio::test]
+  async fn test_exec_abort() {
+    let mut client = TcpStream::connect(spawn_server().await).await.unwrap();
+    client.write_all(&resp(&["MULTI"])).await.unwrap();
+    client.write_all(&resp(&["SET", "key", "1"])).await.unwrap();
+    client
+      .write_all(&resp(&["NOSUCHCOMMAND", "key"]))
+      .await
+      .unwrap();
+    client.write_all(&resp(&["EXEC"])).await.unwrap();
+    // the aborted transaction is over, SET never ran
+    client.write_all(&resp(&["GET", "key"])).await.unwrap();
+
+    let mut reply = vec![0; b"+OK\r\n+QUEUED\r\n".len()];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply, b"+OK\r\n+QUEUED\r\n");
+    let mut client = BufReader::new(client);
+    let mut line = String::new();
+    client.read_line(&mut line).await.unwrap();
+    assert!(line.starts_with("-ERR "), "{line}");
+
+    let expected = b"-EXECABORT Transaction discarded because of previous errors.\r\n$-1\r\n";
+    let mut reply = vec![0; expected.len()];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply, expected);
+  }
+
+  #[tokio::test]
+  async fn test_wrongtype() {
+    let mut client = TcpStream::connect(spawn_server().await).await.unwrap();
+    client
+      .write_all(&resp(&["RPUSH", "list", "a"]))
+      .await
+      .unwrap();
+    client.write_all(&resp(&["GET", "list"])).await.unwrap();
+    client
+      .write_all(&resp(&["HGET", "list", "a"]))
+      .await
+      .unwrap();
+
+    let wrongtype = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+    let expected = format!("+OK\r\n{wrongtype}{wrongtype}");
+    let mut reply = vec![0; expected.len()];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply, expected.as_bytes());
+  }
+
+  #[tokio::test]
+  async fn test_reset() {
+    let mut client = TcpStream::connect(spawn_server().await).await.unwrap();
+    client.write_all(&resp(&["MULTI"])).await.unwrap();
+    client.write_all(&resp(&["RESET"])).await.unwrap();
+    client.write_all(&resp(&["EXEC"])).await.unwrap();
+
+    let expected = b"+OK\r\n+RESET\r\n-ERR EXEC without MULTI\r\n";
+    let mut reply = vec![0; expected.len()];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply, expected);
+
+    // RESET also leaves RESP3 and MONITOR behind
+    client.write_all(&resp(&["HELLO", "3"])).await.unwrap();
+    read_until(&mut client, b"$7\r\nmodules\r\n*0\r\n").await;
+    client.write_all(&resp(&["MONITOR"])).await.unwrap();
+    client.write_all(&resp(&["RESET"])).await.unwrap();
+    let reply = read_until(&mut client, b"+RESET\r\n").await;
+    assert_eq!(reply, b"+OK\r\n+RESET\r\n");
+
+    let mut other = TcpStream::connect(client.peer_addr().unwrap())
+      .await
+      .unwrap();
+    other.write_all(&resp(&["SET", "k", "v"])).await.unwrap();
+    read_until(&mut other, b"+OK\r\n").await;
+    client.write_all(&resp(&["INFO", "server"])).await.unwrap();
+    let reply = read_until(&mut client, b"\r\n").await;
+    assert!(
+      reply.starts_with(b"$"),
+      "{:?}",
+      String::from_utf8_lossy(&reply)
+    );
+  }
+
+  /// Reads from `client` until what it read ends with `suffix`.
+  async fn read_until(client: &mut TcpStream, suffix: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    while !out.ends_with(suffix) {
+      let mut buf = [0; 4096];
+      let n = tokio::time::timeout(Duration::from_secs(5), client.read(&mut buf))
+        .await
+        .expect("no reply")
+        .unwrap();
+      assert!(
+        n > 0,
+        "connection closed after {:?}",
+        String::from_utf8_lossy(&out)
+      );
+      out.extend(&buf[..n]);
+    }
+    out
+  }
+
+  #[tokio::test]
+  async fn test_hello() {
+    // every command slow, replies carry their latency under RESP3
+    let config = Config {
+      slowlog_slower_than_micros: 0,
+      ..Config::default()
+    };
+    let mut client = TcpStream::connect(spawn_server_with_config(config).await)
+      .await
+      .unwrap();
+
+    client.write_all(&resp(&["HELLO", "4"])).await.unwrap();
+    let reply = read_until(&mut client, b"\r\n").await;
+    assert_eq!(reply, b"-NOPROTO unsupported protocol version\r\n");
+
+    client.write_all(&resp(&["HELLO", "3"])).await.unwrap();
+    let reply = read_until(&mut client, b"$7\r\nmodules\r\n*0\r\n").await;
+    // switched before replying, so the reply already carries its latency
+    assert!(reply.starts_with(b"|1\r\n$10\r\nlatency-us\r\n:"));
+    let map = reply.iter().position(|&b| b == b'%').expect("no map frame");
+    assert!(reply[map..].starts_with(b"%7\r\n$6\r\nserver\r\n$5\r\nredis\r\n"));
+    assert!(reply.windows(15).any(|w| w == b"$5\r\nproto\r\n:3\r\n"));
+
+    client.write_all(&resp(&["SET", "k", "v"])).await.unwrap();
+    let reply = read_until(&mut client, b"+OK\r\n").await;
+    assert!(reply.starts_with(b"|1\r\n$10\r\nlatency-us\r\n:"));
+
+    client.write_all(&resp(&["INFO", "server"])).await.unwrap();
+    client.write_all(&resp(&["PING"])).await.unwrap();
+    let reply = read_until(&mut client, b"+PONG\r\n").await;
+    let info = reply
+      .iter()
+      .position(|&b| b == b'=')
+      .expect("no verbatim frame");
+    let txt = reply[info..].iter().position(|&b| b == b'\r').unwrap() + info;
+    assert_eq!(&reply[txt..txt + 6], b"\r\ntxt:");
+
+    client.write_all(&resp(&["HELLO", "2"])).await.unwrap();
+    let reply = read_until(&mut client, b"$7\r\nmodules\r\n*0\r\n").await;
+    assert!(reply.starts_with(b"*14\r\n$6\r\nserver\r\n"));
+
+    // back on RESP2, no attribute and a plain bulk string
+    client.write_all(&resp(&["SET", "k", "v"])).await.unwrap();
+    assert_eq!(read_until(&mut client, b"+OK\r\n").await, b"+OK\r\n");
+  }
+
+  #[tokio::test]
+  async fn test_disabled_command() {
+    let config = Config {
+      disabled_commands: ["flushall".to_string()].into(),
+      ..Config::default()
+    };
+    let mut client = TcpStream::connect(spawn_server_with_config(config).await)
+      .await
+      .unwrap();
+    client.write_all(&resp(&["FLUSHALL"])).await.unwrap();
+    client.write_all(&resp(&["GET", "key"])).await.unwrap();
+
+    let expected = b"-ERR unknown command 'flushall'\r\n$-1\r\n";
+    let mut reply = vec![0; expected.len()];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply, expected);
+  }
 
   #[tokio::test]
   async fn test_read_cmd() {
@@ -201,8 +1691,100 @@ mod tests {
     let mut buf = BufReader::new(test_input);
 
     assert_eq!(
-      read_cmd(&mut buf).await.unwrap(),
-      "$5\r\nLPUSH\r\n$3\r\naaa\r\n$1\r\n1\r\n$1\r\n2\r\n$1\r\n3\r\n$1\r\n4\r\n$1\r\n5\r\n"
+      read_cmd(&mut buf, DEFAULT_MAX_INLINE_LEN)
+        .await
+        .unwrap()
+        .unwrap(),
+      ["LPUSH", "aaa", "1", "2", "3", "4", "5"].map(|arg| arg.as_bytes().to_vec())
     )
   }
+
+  #[tokio::test]
+  async fn test_read_cmd_args() {
+    let test_input = b"SET  key value\r\n*2\r\n$3\r\nGET\r\n$2\r\n\xff\x00\r\n" as &[u8];
+    let mut buf = BufReader::new(test_input);
+
+    assert_eq!(
+      read_cmd(&mut buf, DEFAULT_MAX_INLINE_LEN)
+        .await
+        .unwrap()
+        .unwrap(),
+      vec![b"SET".to_vec(), b"key".to_vec(), b"value".to_vec()]
+    );
+    assert_eq!(
+      read_cmd(&mut buf, DEFAULT_MAX_INLINE_LEN)
+        .await
+        .unwrap()
+        .unwrap(),
+      vec![b"GET".to_vec(), vec![0xff, 0x00]]
+    );
+  }
+
+  #[tokio::test]
+  async fn test_read_cmd_blank_lines() {
+    let test_input = b"\r\n  \r\nPING\r\n\r\n" as &[u8];
+    let mut buf = BufReader::new(test_input);
+
+    assert_eq!(
+      read_cmd(&mut buf, DEFAULT_MAX_INLINE_LEN)
+        .await
+        .unwrap()
+        .unwrap(),
+      vec![b"PING".to_vec()]
+    );
+    assert_eq!(
+      read_cmd(&mut buf, DEFAULT_MAX_INLINE_LEN).await.unwrap(),
+      None
+    );
+  }
+
+  #[tokio::test]
+  async fn test_blank_line_and_eof() {
+    let mut client = TcpStream::connect(spawn_server().await).await.unwrap();
+    client.write_all(b"\r\n").await.unwrap();
+    client.write_all(&resp(&["PING"])).await.unwrap();
+
+    let mut reply = [0; 7];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, b"+PONG\r\n");
+
+    client.shutdown().await.unwrap();
+    let mut rest = Vec::new();
+    client.read_to_end(&mut rest).await.unwrap();
+    assert!(rest.is_empty());
+  }
+
+  #[tokio::test]
+  async fn test_monitor() {
+    let addr = spawn_server().await;
+    let mut monitor = BufReader::new(TcpStream::connect(addr).await.unwrap());
+    let mut client = TcpStream::connect(addr).await.unwrap();
+
+    monitor
+      .get_mut()
+      .write_all(&resp(&["MONITOR"]))
+      .await
+      .unwrap();
+    let mut line = String::new();
+    monitor.read_line(&mut line).await.unwrap();
+    assert_eq!(line, "+OK\r\n");
+    // ignored, no reply comes before the SET below
+    monitor.get_mut().write_all(&resp(&["PING"])).await.unwrap();
+
+    client
+      .write_all(&resp(&["SET", "k", "a\"b"]))
+      .await
+      .unwrap();
+    let mut reply = [0; 5];
+    client.read_exact(&mut reply).await.unwrap();
+
+    line.clear();
+    monitor.read_line(&mut line).await.unwrap();
+    let local = client.local_addr().unwrap();
+    assert!(line.starts_with('+'), "{line}");
+    assert!(
+      line.ends_with(&format!("[0 {local}] \"SET\" \"k\" \"a\\\"b\"\r\n")),
+      "{line}"
+    );
+  }
 }
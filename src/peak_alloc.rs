@@ -0,0 +1,47 @@
+//! The test build's allocator, counting the bytes each thread has allocated
+//! so a test can tell how much memory something took at its peak.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+pub struct PeakAlloc;
+
+thread_local! {
+  static CURRENT: Cell<isize> = const { Cell::new(0) };
+  static PEAK: Cell<isize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for PeakAlloc {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    let ptr = unsafe { System.alloc(layout) };
+    if !ptr.is_null() {
+      grow(layout.size() as isize);
+    }
+    ptr
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    unsafe { System.dealloc(ptr, layout) };
+    grow(-(layout.size() as isize));
+  }
+}
+
+fn grow(by: isize) {
+  // thread locals are gone while a thread shuts down
+  let _ = CURRENT.try_with(|current| {
+    let now = current.get() + by;
+    current.set(now);
+    let _ = PEAK.try_with(|peak| peak.set(peak.get().max(now)));
+  });
+}
+
+/// Runs `f`, returns its result and the most it had allocated at once on
+/// this thread on top of what was allocated before. Memory freed on other
+/// threads isn't seen, so `f` should stay on this one.
+pub fn peak<T>(f: impl FnOnce() -> T) -> (T, usize) {
+  let base = CURRENT.with(Cell::get);
+  PEAK.with(|peak| peak.set(base));
+  let result = f();
+  let peak = PEAK.with(Cell::get);
+  (result, (peak - base).max(0) as usize)
+}
@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::glob;
+use crate::sync;
+use crate::value::RedisValue;
+
+type Subscribers = HashMap<u64, UnboundedSender<RedisValue>>;
+
+/// Channel and pattern subscriptions of every session, keyed by client id.
+/// Messages are pushed to the session's inbox which writes them out.
+#[derive(Default)]
+pub struct PubSub {
+  channels: Mutex<HashMap<Vec<u8>, Subscribers>>,
+  patterns: Mutex<HashMap<Vec<u8>, Subscribers>>,
+}
+
+impl PubSub {
+  fn registry(&self, pattern: bool) -> &Mutex<HashMap<Vec<u8>, Subscribers>> {
+    if pattern {
+      &self.patterns
+    } else {
+      &self.channels
+    }
+  }
+
+  pub fn subscribe(
+    &self,
+    channel: &[u8],
+    pattern: bool,
+    id: u64,
+    inbox: UnboundedSender<RedisValue>,
+  ) {
+    let mut registry = sync::lock(self.registry(pattern));
+    registry
+      .entry(channel.to_vec())
+      .or_default()
+      .insert(id, inbox);
+  }
+
+  pub fn unsubscribe(&self, channel: &[u8], pattern: bool, id: u64) {
+    let mut registry = sync::lock(self.registry(pattern));
+    if let Some(subscribers) = registry.get_mut(channel) {
+      subscribers.remove(&id);
+      if subscribers.is_empty() {
+        registry.remove(channel);
+      }
+    }
+  }
+
+  /// Sends `message` to the subscribers of `channel` and of every pattern
+  /// matching it, returns how many received it. Inboxes whose session is gone
+  /// are pruned on the way so they no longer count.
+  pub fn publish(&self, channel: &[u8], message: &[u8]) -> usize {
+    let bulk = |b: &[u8]| RedisValue::from(b.to_vec());
+    let mut received = 0;
+
+    let mut channels = sync::lock(&self.channels);
+    if let Some(subscribers) = channels.get_mut(channel) {
+      received += deliver(subscribers, || {
+        RedisValue::Nested(vec![
+          RedisValue::from("message"),
+          bulk(channel),
+          bulk(message),
+        ])
+      });
+      if subscribers.is_empty() {
+        channels.remove(channel);
+      }
+    }
+    drop(channels);
+
+    let mut patterns = sync::lock(&self.patterns);
+    patterns.retain(|pattern, subscribers| {
+      if glob::matches(pattern, channel) {
+        received += deliver(subscribers, || {
+          RedisValue::Nested(vec![
+            RedisValue::from("pmessage"),
+            bulk(pattern),
+            bulk(channel),
+            bulk(message),
+          ])
+        });
+      }
+      !subscribers.is_empty()
+    });
+    received
+  }
+
+  /// Channels with at least one subscriber, those matching `pattern` if
+  /// given. Pattern subscriptions don't count.
+  pub fn channels(&self, pattern: Option<&[u8]>) -> Vec<Vec<u8>> {
+    sync::lock(&self.channels)
+      .iter()
+      .filter(|(channel, subscribers)| {
+        live(subscribers) > 0 && pattern.is_none_or(|p| glob::matches(p, channel))
+      })
+      .map(|(channel, _)| channel.clone())
+      .collect()
+  }
+
+  /// Subscriber count of each of `channels`, pattern subscribers left out.
+  pub fn numsub(&self, channels: &[&[u8]]) -> Vec<usize> {
+    let registry = sync::lock(&self.channels);
+    channels
+      .iter()
+      .map(|channel| registry.get(*channel).map_or(0, live))
+      .collect()
+  }
+
+  /// Number of patterns subscribed to, by any client.
+  pub fn numpat(&self) -> usize {
+    sync::lock(&self.patterns)
+      .values()
+      .filter(|subscribers| live(subscribers) > 0)
+      .count()
+  }
+}
+
+/// Subscribers whose session is still around to receive messages.
+fn live(subscribers: &Subscribers) -> usize {
+  subscribers
+    .values()
+    .filter(|inbox| !inbox.is_closed())
+    .count()
+}
+
+/// Sends a `frame` to each of `subscribers`, dropping the ones whose inbox is
+/// closed, returns how many it was sent to.
+fn deliver(subscribers: &mut Subscribers, frame: impl Fn() -> RedisValue) -> usize {
+  subscribers.retain(|_, inbox| inbox.send(frame()).is_ok());
+  subscribers.len()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::PubSub;
+  use crate::sync;
+  use tokio::sync::mpsc::unbounded_channel;
+
+  #[test]
+  fn test_publish() {
+    let pubsub = PubSub::default();
+    let (tx, mut rx) = unbounded_channel();
+    pubsub.subscribe(b"news", false, 1, tx.clone());
+    pubsub.subscribe(b"n*", true, 1, tx);
+
+    assert_eq!(pubsub.publish(b"news", b"hello"), 2);
+    assert_eq!(pubsub.publish(b"other", b"hello"), 0);
+    assert!(rx.try_recv().is_ok());
+    assert!(rx.try_recv().is_ok());
+
+    pubsub.unsubscribe(b"news", false, 1);
+    pubsub.unsubscribe(b"n*", true, 1);
+    assert_eq!(pubsub.publish(b"news", b"hello"), 0);
+  }
+
+  #[test]
+  fn test_publish_prunes_closed_inboxes() {
+    let pubsub = PubSub::default();
+    let (alive, mut alive_rx) = unbounded_channel();
+    let (gone, gone_rx) = unbounded_channel();
+    pubsub.subscribe(b"news", false, 1, alive);
+    pubsub.subscribe(b"news", false, 2, gone.clone());
+    pubsub.subscribe(b"n*", true, 2, gone);
+    drop(gone_rx);
+
+    assert_eq!(pubsub.publish(b"news", b"hello"), 1);
+    assert!(alive_rx.try_recv().is_ok());
+    assert_eq!(sync::lock(&pubsub.channels)[b"news".as_slice()].len(), 1);
+    assert!(sync::lock(&pubsub.patterns).is_empty());
+  }
+
+  #[test]
+  fn test_introspection() {
+    let pubsub = PubSub::default();
+    let (first, _first_rx) = unbounded_channel();
+    let (second, _second_rx) = unbounded_channel();
+    pubsub.subscribe(b"news", false, 1, first.clone());
+    pubsub.subscribe(b"news", false, 2, second.clone());
+    pubsub.subscribe(b"sport", false, 2, second.clone());
+    pubsub.subscribe(b"n*", true, 1, first);
+    pubsub.subscribe(b"n*", true, 2, second);
+
+    let mut channels = pubsub.channels(None);
+    channels.sort();
+    assert_eq!(channels, vec![b"news".to_vec(), b"sport".to_vec()]);
+    assert_eq!(pubsub.channels(Some(b"s*")), vec![b"sport".to_vec()]);
+    assert_eq!(pubsub.numsub(&[b"news", b"sport", b"other"]), vec![2, 1, 0]);
+    assert_eq!(pubsub.numpat(), 1);
+  }
+}
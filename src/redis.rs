@@ -1,305 +1,5171 @@
-use crate::cmd::Command;
+use crate::cmd::parser::parse;
+use crate::cmd::{
+  self, Command, Expiry, KillFilter, ScanOptions, SetCondition, SetOptions, SortOptions,
+};
 use crate::err::RedisError;
-use crate::journal::{Journal, Writer};
-use crate::value::RedisValue;
+use crate::glob;
+use crate::journal::{self, FsyncPolicy, Journal, Writer};
+use crate::lfu;
+use crate::list::{List, DEFAULT_PACKED_THRESHOLD};
+use crate::pubsub::PubSub;
+use crate::read_cmd;
+use crate::slowlog::{self, Slowlog};
+use crate::sync;
+use crate::value::{Inline, RedisValue, Windows, STREAM_WINDOW};
+use crate::zset::{LexBound, Score, ScoreBound, SortedSet};
 
-use log::info;
+use async_trait::async_trait;
+use log::{debug, info, log_enabled, warn, Level};
+use rand::rngs::StdRng;
+use rand::seq::{IteratorRandom, SliceRandom};
+use rand::{Rng, SeedableRng};
 use std::borrow::Cow;
-use std::cmp::Reverse;
-use std::collections::{BinaryHeap, HashMap, LinkedList};
-use std::ops::Add;
-use std::sync::Arc;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use tokio::sync::RwLock;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::mem::size_of;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::fs::{self, File};
+use tokio::io::BufReader;
+use tokio::sync::{broadcast, watch, Notify, RwLock, RwLockReadGuard};
+use tokio::task::JoinHandle;
 
-static INITIAL_CAPACITY: usize = 256;
+pub static DEFAULT_INITIAL_CAPACITY: usize = 256;
+/// Same as Redis' default proto-inline-max-size.
+pub static DEFAULT_MAX_INLINE_LEN: usize = 64 * 1024;
+/// Expired keys the cleaner removes before it lets go of the write lock.
+pub static DEFAULT_ACTIVE_EXPIRE_BATCH: usize = 200;
+/// Milliseconds between cleaner passes.
+pub static DEFAULT_ACTIVE_EXPIRE_INTERVAL_MS: u64 = 1000;
+static DEFAULT_SCAN_COUNT: usize = 10;
+/// SCANs in progress whose snapshot is kept, the oldest is dropped past it.
+static MAX_SCAN_SNAPSHOTS: usize = 64;
+/// Largest keyspace SCAN snapshots, bigger ones are paged through live.
+static MAX_SCAN_SNAPSHOT_KEYS: usize = 10_000;
+/// Low bits of a SCAN cursor holding the offset, the rest the snapshot id.
+static SCAN_OFFSET_BITS: u32 = 40;
+/// Longest string APPEND and SETRANGE may build, as proto-max-bulk-len.
+static MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+/// Lines a slow MONITOR session may fall behind by before missing some.
+static MONITOR_BACKLOG: usize = 1024;
+/// DEL frees values with more elements than this on a blocking thread.
+static LAZYFREE_THRESHOLD: usize = 64;
+/// Commands replayed between two progress lines while loading.
+static LOAD_PROGRESS_EVERY: usize = 10_000;
 
+type Hash = HashMap<Vec<u8>, Vec<u8>>;
+
+#[derive(Clone)]
 enum Value {
   Raw(Arc<Vec<u8>>),
-  List(LinkedList<Vec<u8>>),
+  /// A string of at most `INLINE_LEN` bytes, GET copies it instead of
+  /// sharing an `Arc`.
+  Inline(Inline),
+  /// A string holding an integer, rendered in decimal when read.
+  Int(i64),
+  List(List),
+  Hash(Hash),
+  Set(HashSet<Vec<u8>>),
+  ZSet(SortedSet),
 }
 
-struct SharedData {
-  dict: HashMap<String, Value>,
-  ttl_heap: BinaryHeap<Reverse<(u64, String)>>,
+impl Value {
+  /// A string value, stored as `Value::Int` if it is the canonical decimal
+  /// form of an i64 so that "007" still reads back as "007".
+  fn string(value: &[u8]) -> Value {
+    let int = std::str::from_utf8(value)
+      .ok()
+      .and_then(|s| s.parse::<i64>().ok())
+      .filter(|n| n.to_string().as_bytes() == value);
+    match int {
+      Some(n) => Value::Int(n),
+      None => match Inline::new(value) {
+        Some(inline) => Value::Inline(inline),
+        None => Value::Raw(Arc::new(value.to_vec())),
+      },
+    }
+  }
+
+  /// Strong count of a string value, above 1 while a GET reply still holds
+  /// it. Other values, inline strings included, are never shared.
+  fn refcount(&self) -> usize {
+    match self {
+      Value::Raw(data) => Arc::strong_count(data),
+      _ => 1,
+    }
+  }
+
+  /// Name of the type, as SCAN TYPE takes it.
+  fn type_name(&self) -> &'static str {
+    match self {
+      Value::Raw(_) | Value::Inline(_) | Value::Int(_) => "string",
+      Value::List(_) => "list",
+      Value::Hash(_) => "hash",
+      Value::Set(_) => "set",
+      Value::ZSet(_) => "zset",
+    }
+  }
+
+  /// Name of the internal representation, as reported by OBJECT ENCODING.
+  fn encoding(&self) -> &'static str {
+    match self {
+      Value::Int(_) => "int",
+      // strings short enough to be inline, as embstr objects are in Redis
+      Value::Inline(_) => "embstr",
+      Value::Raw(_) => "raw",
+      Value::List(List::Packed(_)) => "listpack",
+      Value::List(List::Deque(_)) => "quicklist",
+      Value::Hash(_) | Value::Set(_) => "hashtable",
+      Value::ZSet(_) => "skiplist",
+    }
+  }
+
+  /// Bytes of a string value, `None` for other types.
+  fn as_string(&self) -> Option<Arc<Vec<u8>>> {
+    match self {
+      Value::Raw(data) => Some(Arc::clone(data)),
+      Value::Inline(data) => Some(Arc::new(data.as_slice().to_vec())),
+      Value::Int(n) => Some(Arc::new(n.to_string().into_bytes())),
+      _ => None,
+    }
+  }
+
+  /// The bytes of a string value to modify in place, zero-padded to at least
+  /// `len`, integers and inline strings being turned into raw ones first.
+  /// `None` for other types.
+  ///
+  /// A buffer nobody else holds grows in place, so repeated APPENDs cost
+  /// amortized O(1) per byte. One still shared with a GET reply waiting to be
+  /// written is copied, once, into a buffer with room to spare, and later
+  /// appends grow that copy in place.
+  fn raw_mut(&mut self, len: usize) -> Option<&mut Vec<u8>> {
+    if let Value::Int(_) | Value::Inline(_) = self {
+      *self = Value::Raw(self.as_string()?);
+    }
+    let Value::Raw(data) = self else {
+      return None;
+    };
+    if Arc::get_mut(data).is_none() {
+      let mut copy = Vec::with_capacity(len.max(data.len() * 2));
+      copy.extend_from_slice(data);
+      *data = Arc::new(copy);
+    }
+    let data = Arc::make_mut(data);
+    if data.len() < len {
+      data.resize(len, 0);
+    }
+    Some(data)
+  }
+
+  /// A string value as a GET reply, inline strings are copied rather than
+  /// wrapped in a new `Arc`.
+  fn as_reply(&self) -> Option<RedisValue> {
+    match self {
+      Value::Inline(data) => Some(RedisValue::InlineString(*data)),
+      value => value.as_string().map(RedisValue::SimpleString),
+    }
+  }
+
+  /// Number of elements freed along with the value, 1 for strings.
+  fn elements(&self) -> usize {
+    match self {
+      Value::Raw(_) | Value::Inline(_) | Value::Int(_) => 1,
+      Value::List(ll) => ll.len(),
+      Value::Hash(hash) => hash.len(),
+      Value::Set(set) => set.len(),
+      Value::ZSet(zset) => zset.len(),
+    }
+  }
+
+  /// Approximate number of bytes held by the value, including the
+  /// allocation headers of its elements.
+  fn mem_size(&self) -> usize {
+    let elem = size_of::<Vec<u8>>();
+    size_of::<Value>()
+      + match self {
+        Value::Raw(data) => data.len(),
+        Value::Int(_) | Value::Inline(_) => 0,
+        Value::List(ll) => ll.iter().map(|v| elem + v.len()).sum(),
+        Value::Hash(hash) => hash.iter().map(|(k, v)| 2 * elem + k.len() + v.len()).sum(),
+        Value::Set(set) => set.iter().map(|m| elem + m.len()).sum(),
+        Value::ZSet(zset) => zset
+          .iter()
+          .map(|(m, _)| 2 * (elem + m.len() + size_of::<f64>()))
+          .sum(),
+      }
+  }
 }
 
-pub struct Redis<W: Writer> {
-  shared_data: Arc<RwLock<SharedData>>,
-  journal: W,
+struct SharedData {
+  dict: HashMap<String, Value>,
+  /// Expiry deadline (unix millis) of every volatile key.
+  expires: HashMap<String, u64>,
+  /// The entries of `expires` ordered by deadline for the cleaner, kept in
+  /// step with it so every volatile key has exactly one.
+  ttl_heap: BTreeSet<(u64, String)>,
+  /// Access frequency of every key under allkeys-lfu, locked on its own so
+  /// reads can bump it.
+  lfu: Mutex<HashMap<String, lfu::Counter>>,
 }
 
-impl<W: Writer + Send> Redis<W> {
-  pub async fn new(writer: W) -> Redis<W> {
-    let shared_data = RwLock::new(SharedData {
-      dict: HashMap::with_capacity(INITIAL_CAPACITY),
-      ttl_heap: BinaryHeap::new(),
-    });
-    let arc = Arc::new(shared_data);
-    spawn_ttl_heap_cleaner(arc.clone()).await;
+impl SharedData {
+  fn is_expired(&self, key: &str) -> bool {
+    matches!(self.expires.get(key), Some(&at) if at <= now_millis())
+  }
 
-    Redis {
-      shared_data: arc,
-      journal: writer,
+  /// Removes `key` if its deadline has passed, returns whether it did.
+  fn expire_if_needed(&mut self, key: &str) -> bool {
+    if !self.is_expired(key) {
+      return false;
     }
+    debug!("lazily expiring key={}", key);
+    self.remove(key);
+    true
   }
 
-  pub async fn exec<'a>(&self, cmd: &'a Command<'a>) -> Result<RedisValue, RedisError> {
-    match cmd {
-      Command::Set(key, value) => {
-        self.set(key, value).await;
-        Ok(RedisValue::Ok)
-      }
-      Command::Get(key) => match self.get(key).await? {
-        Option::None => Ok(RedisValue::EmptyString),
-        Option::Some(v) => Ok(RedisValue::SimpleString(v)),
-      },
-      c @ Command::SetEx(key, value, ttl) => {
-        self.journal.write(c).await;
-        self.setex(key, value, *ttl).await;
-        Ok(RedisValue::Ok)
-      }
-      Command::Ping => Ok(RedisValue::from("PONG")),
-      Command::CommandDocs => Ok(RedisValue::BulkString(Vec::new())),
-      Command::DbSize => Ok(RedisValue::Integer(self.keys_count().await as i64)),
-      Command::Config => Ok(RedisValue::BulkString(Vec::new())),
-      c @ Command::Lpush(key, value) => {
-        self.journal.write(c).await;
-        self.push(key, value, true, true).await?;
-        Ok(RedisValue::Ok)
-      }
-      c @ Command::Rpush(key, value) => {
-        self.journal.write(c).await;
-        self.push(key, value, true, false).await?;
-        Ok(RedisValue::Ok)
-      }
-      c @ Command::LpushX(key, value) => {
-        self.journal.write(c).await;
-        self.push(key, value, false, true).await?;
-        Ok(RedisValue::Ok)
-      }
-      c @ Command::RpushX(key, value) => {
-        self.journal.write(c).await;
-        self.push(key, value, false, false).await?;
-        Ok(RedisValue::Ok)
-      }
-      c @ Command::Lpop(key, times) => {
-        info!("lpop: {}!", key);
-        self.journal.write(c).await;
-        let v = self.pop(key, *times, true).await?;
-        Ok(RedisValue::from(v))
-      }
-      c @ Command::Rpop(key, times) => {
-        info!("rpop: {}!", key);
-        self.journal.write(c).await;
-        let v = self.pop(key, *times, false).await?;
-        Ok(RedisValue::from(v))
-      }
-      c @ Command::Del(keys) => {
-        info!("delete {:?}!", keys);
-        self.journal.write(c).await;
-        let del_keys_count: usize = self.delete(&keys).await;
+  /// Sets the deadline of `key` to `at`, replacing the one it had.
+  fn set_deadline(&mut self, key: &str, at: u64) {
+    self.clear_deadline(key);
+    self.expires.insert(key.to_string(), at);
+    self.ttl_heap.insert((at, key.to_string()));
+  }
 
-        Ok(RedisValue::Integer(del_keys_count as i64))
-      }
-      c @ Command::Incr(key) => {
-        self.journal.write(c).await;
-        Ok(RedisValue::Integer(self.incr(key).await?))
-      }
+  fn clear_deadline(&mut self, key: &str) {
+    if let Some(at) = self.expires.remove(key) {
+      self.ttl_heap.remove(&(at, key.to_string()));
     }
   }
 
-  async fn set(&self, key: &str, value: &[u8]) {
-    self
-      .shared_data
-      .write()
-      .await
-      .dict
-      .insert(key.to_string(), Value::Raw(Arc::new(value.to_vec())));
+  /// Removes at most `max` keys whose deadline is `now` or earlier, returns
+  /// how many it did.
+  fn reap_expired(&mut self, now: u64, max: usize) -> usize {
+    let mut reaped = 0;
+    while reaped < max {
+      let key = match self.ttl_heap.first() {
+        Some((at, key)) if *at <= now => key.clone(),
+        _ => break,
+      };
+      debug!("deleting stale key={}", key);
+      self.remove(&key);
+      reaped += 1;
+    }
+    reaped
   }
 
-  async fn setex(&self, key: &str, value: &[u8], ttl: usize) {
-    let s_data = &mut self.shared_data.write().await;
+  fn remove(&mut self, key: &str) -> Option<Value> {
+    self.clear_deadline(key);
+    sync::get_mut(&mut self.lfu).remove(key);
+    self.dict.remove(key)
+  }
 
-    s_data
-      .dict
-      .insert(key.to_string(), Value::Raw(Arc::new(value.to_vec())));
+  /// The arguments of one command per live key that recreates it, what
+  /// SAVE and journal rewrites write out.
+  fn commands(&self) -> Vec<Vec<Vec<u8>>> {
+    let now = now_millis();
+    let mut commands = Vec::with_capacity(self.dict.len());
+    for (key, value) in self.dict.iter() {
+      let deadline = match self.expires.get(key) {
+        Some(&at) if at <= now => continue,
+        Some(&at) => Some(at.to_string()),
+        None => None,
+      };
+      let key = key.as_bytes().to_vec();
+      let args = match (value, deadline) {
+        (Value::Raw(_) | Value::Inline(_) | Value::Int(_), Some(at)) => {
+          let data = value.as_string().unwrap();
+          vec![
+            b"SET".to_vec(),
+            key,
+            data.to_vec(),
+            b"PXAT".to_vec(),
+            at.into_bytes(),
+          ]
+        }
+        (Value::Raw(_) | Value::Inline(_) | Value::Int(_), None) => {
+          let data = value.as_string().unwrap();
+          vec![b"SET".to_vec(), key, data.to_vec()]
+        }
+        (Value::List(ll), _) => {
+          let mut args = vec![b"RPUSH".to_vec(), key];
+          args.extend(ll.iter().cloned());
+          args
+        }
+        (Value::Hash(hash), _) => {
+          let mut args = vec![b"HSET".to_vec(), key];
+          for (field, value) in hash {
+            args.push(field.clone());
+            args.push(value.clone());
+          }
+          args
+        }
+        (Value::Set(set), _) => {
+          let mut args = vec![b"SADD".to_vec(), key];
+          args.extend(set.iter().cloned());
+          args
+        }
+        (Value::ZSet(zset), _) => {
+          let mut args = vec![b"ZADD".to_vec(), key];
+          for (member, score) in zset.iter() {
+            args.push(score.to_string().into_bytes());
+            args.push(member.to_vec());
+          }
+          args
+        }
+      };
+      commands.push(args);
+    }
+    commands
+  }
 
-    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-    let ttl_value = now.add(Duration::from_secs(ttl as u64));
+  /// The list at `key`, `None` if it is missing and a type error if it
+  /// holds anything else.
+  fn list(&self, key: &str) -> Result<Option<&List>, RedisError> {
+    match self.dict.get(key) {
+      Some(Value::List(ll)) => Ok(Some(ll)),
+      Some(_) => Err(RedisError::Type),
+      None => Ok(None),
+    }
+  }
 
-    s_data
-      .ttl_heap
-      .push(Reverse((ttl_value.as_secs(), key.to_string())));
+  fn list_mut(&mut self, key: &str) -> Result<Option<&mut List>, RedisError> {
+    match self.dict.get_mut(key) {
+      Some(Value::List(ll)) => Ok(Some(ll)),
+      Some(_) => Err(RedisError::Type),
+      None => Ok(None),
+    }
+  }
 
-    info!(
-      "pushed 1 elem into ttl_heap, ttl_heap_len={}",
-      s_data.ttl_heap.len()
-    );
+  fn hash(&self, key: &str) -> Result<Option<&Hash>, RedisError> {
+    match self.dict.get(key) {
+      Some(Value::Hash(hash)) => Ok(Some(hash)),
+      Some(_) => Err(RedisError::Type),
+      None => Ok(None),
+    }
   }
 
-  async fn get(&self, key: &str) -> Result<Option<Arc<Vec<u8>>>, RedisError> {
-    let read_from = self.shared_data.read().await;
-    let value_opt = read_from.dict.get(key);
+  fn set(&self, key: &str) -> Result<Option<&HashSet<Vec<u8>>>, RedisError> {
+    match self.dict.get(key) {
+      Some(Value::Set(set)) => Ok(Some(set)),
+      Some(_) => Err(RedisError::Type),
+      None => Ok(None),
+    }
+  }
 
-    match value_opt {
-      Some(Value::Raw(data)) => Ok(Some(Arc::clone(data))),
-      Some(_) => Result::Err(RedisError::Type),
+  fn zset(&self, key: &str) -> Result<Option<&SortedSet>, RedisError> {
+    match self.dict.get(key) {
+      Some(Value::ZSet(zset)) => Ok(Some(zset)),
+      Some(_) => Err(RedisError::Type),
       None => Ok(None),
     }
   }
+}
 
-  async fn push(
-    &self,
-    key: &str,
-    values: &Vec<&[u8]>,
-    allow_creation: bool,
-    front: bool,
-  ) -> Result<usize, RedisError> {
-    let mut write_from = self.shared_data.write().await;
+/// The integer held by a string value that INCR can add to.
+fn parse_counter(value: &[u8]) -> Result<i64, RedisError> {
+  match String::from_utf8_lossy(value) {
+    Cow::Borrowed(v) => v.parse::<i64>().or(Err(RedisError::Type)),
+    _ => Err(RedisError::Type),
+  }
+}
 
-    match write_from.dict.get_mut(key) {
-      Some(&mut Value::List(ref mut ll)) => {
-        values.iter().for_each(|v| ll.push_front(v.to_vec()));
-        Ok(ll.len())
-      }
-      Some(_) => Result::Err(RedisError::Type),
-      None if !allow_creation => Ok(values.len()),
-      None => {
-        let mut ll = LinkedList::new();
+fn parse_float(value: &[u8]) -> Result<f64, RedisError> {
+  let invalid = || RedisError::Other("value is not a valid float".to_string());
+  let value = std::str::from_utf8(value).map_err(|_| invalid())?;
+  match value.parse::<f64>() {
+    Ok(n) if n.is_finite() => Ok(n),
+    _ => Err(invalid()),
+  }
+}
 
-        values.iter().for_each(|v| {
-          if front {
-            ll.push_front(v.to_vec());
-          } else {
-            ll.push_back(v.to_vec());
-          }
-        });
-        write_from.dict.insert(key.to_string(), Value::List(ll));
+/// `n` in plain decimal notation with no exponent and no trailing zeros,
+/// the shortest digits that read back as `n`.
+fn format_float(n: f64) -> String {
+  if n == 0.0 {
+    // no negative zero
+    return "0".to_string();
+  }
+  n.to_string()
+}
 
-        Ok(values.len())
+/// `a + b` the way INCRBYFLOAT stores it. Redis adds in long double and
+/// prints 17 decimals with the trailing zeros cut, so `0.1 + 0.2` is `0.3`
+/// there. Here both are taken at their shortest digits and added exactly,
+/// then cut the same way; only sums past the 19 or so significant digits
+/// long double keeps come out differently.
+fn add_floats(a: f64, b: f64) -> String {
+  // the digits of |n|, `scale` of them after the point
+  let digits = |n: f64| {
+    let text = format_float(n.abs());
+    let (int, frac) = text.split_once('.').unwrap_or((&text, ""));
+    let digits = int.bytes().chain(frac.bytes()).map(|d| d - b'0');
+    (digits.collect::<Vec<_>>(), frac.len())
+  };
+  let ((mut x, x_scale), (mut y, y_scale)) = (digits(a), digits(b));
+  let scale = x_scale.max(y_scale);
+  x.resize(x.len() + scale - x_scale, 0);
+  y.resize(y.len() + scale - y_scale, 0);
+  let len = x.len().max(y.len()) + 1;
+  x.splice(0..0, std::iter::repeat_n(0, len - x.len()));
+  y.splice(0..0, std::iter::repeat_n(0, len - y.len()));
+
+  // the larger magnitude first, its sign is the sum's
+  let (mut negative, same_sign) = (a < 0.0, (a < 0.0) == (b < 0.0));
+  if !same_sign && x < y {
+    std::mem::swap(&mut x, &mut y);
+    negative = b < 0.0;
+  }
+  let mut carry = 0;
+  for i in (0..len).rev() {
+    let d = match same_sign {
+      true => x[i] as i8 + y[i] as i8 + carry,
+      false => x[i] as i8 - y[i] as i8 + carry,
+    };
+    x[i] = d.rem_euclid(10) as u8;
+    carry = d.div_euclid(10);
+  }
+
+  // %.17Lf
+  let mut scale = scale;
+  if scale > 17 {
+    let round_up = x[len - scale + 17] >= 5;
+    x.truncate(len - scale + 17);
+    scale = 17;
+    let mut i = x.len();
+    while round_up && i > 0 {
+      i -= 1;
+      x[i] = (x[i] + 1) % 10;
+      if x[i] != 0 {
+        break;
       }
     }
   }
+  let text = x.iter().map(|d| (b'0' + d) as char).collect::<String>();
+  let (int, frac) = text.split_at(text.len() - scale);
+  let int = int.trim_start_matches('0');
+  let frac = frac.trim_end_matches('0');
+  if int.is_empty() && frac.is_empty() {
+    return "0".to_string();
+  }
+  let sign = if negative { "-" } else { "" };
+  let int = if int.is_empty() { "0" } else { int };
+  match frac.is_empty() {
+    true => format!("{sign}{int}"),
+    false => format!("{sign}{int}.{frac}"),
+  }
+}
 
-  async fn pop(
-    &self,
-    key: &str,
-    mut times: usize,
-    front: bool,
-  ) -> Result<Vec<Vec<u8>>, RedisError> {
-    match self.shared_data.write().await.dict.get_mut(key) {
-      None => Ok(Vec::new()),
-      Some(&mut Value::List(ref mut ll)) => {
-        let mut r = Vec::new();
-        while times > 0 && let Some(v) = if front { ll.pop_front() } else { ll.pop_back() } {
-                    times -= 1;
-                    r.push(v);
-                }
-        Ok(r)
+fn encode_bulk(out: &mut Vec<u8>, element: &[u8]) {
+  out.extend(format!("${}\r\n", element.len()).as_bytes());
+  out.extend(element);
+  out.extend(b"\r\n");
+}
+
+/// The elements of a long LRANGE from `start` on, each window read under
+/// its own read lock. Elements pushed or popped meanwhile show up in later
+/// windows, and ones that are gone by then are sent as nils so the length
+/// written up front still holds.
+struct ListWindows {
+  data: Arc<RwLock<SharedData>>,
+  key: String,
+  start: usize,
+}
+
+impl std::fmt::Debug for ListWindows {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("ListWindows")
+      .field("key", &self.key)
+      .field("start", &self.start)
+      .finish_non_exhaustive()
+  }
+}
+
+#[async_trait]
+impl Windows for ListWindows {
+  async fn window(&self, from: usize, count: usize) -> Vec<u8> {
+    let read_from = self.data.read().await;
+    let mut out = Vec::new();
+    let mut written = 0;
+    if let Ok(Some(list)) = read_from.list(&self.key) {
+      let first = (self.start + from) as i64;
+      for element in list.range_iter(first, first + count as i64 - 1) {
+        encode_bulk(&mut out, element);
+        written += 1;
       }
-      Some(_) => Result::Err(RedisError::Type),
     }
+    for _ in written..count {
+      out.extend(b"$-1\r\n");
+    }
+    out
   }
+}
 
-  async fn delete(&self, keys: &[&str]) -> usize {
-    let mut write_handle = self.shared_data.write().await;
-    let mut count = 0;
-    for key in keys {
-      if write_handle.dict.contains_key(*key) {
-        count += 1;
-        write_handle.dict.remove(*key);
-      }
+/// A ZRANGEBYSCORE or ZRANGEBYLEX reply, cut to the LIMIT `window`.
+fn range_reply<'a>(
+  range: impl Iterator<Item = (&'a [u8], f64)>,
+  with_scores: bool,
+  window: Option<(usize, i64)>,
+) -> RedisValue {
+  let (offset, count) = window.unwrap_or((0, -1));
+  let count = usize::try_from(count).unwrap_or(usize::MAX);
+  let mut out = Vec::new();
+  for (member, score) in range.skip(offset).take(count) {
+    out.push(RedisValue::from(member.to_vec()));
+    if with_scores {
+      out.push(RedisValue::from(score.to_string().into_bytes()));
     }
-    count
   }
+  RedisValue::Nested(out)
+}
 
-  async fn keys_count(&self) -> usize {
-    let read_handle = self.shared_data.read().await;
-    read_handle.dict.len()
+/// The LPOP or RPOP reply: a single element or nil bulk without a `count`,
+/// an array of at most `count` elements or nil array with one.
+fn pop_reply(popped: Option<Vec<Vec<u8>>>, count: Option<usize>) -> RedisValue {
+  match (popped, count) {
+    (None, None) => RedisValue::NullBulk,
+    // an empty `Array` is written as the nil array
+    (None, Some(_)) => RedisValue::Array(Vec::new()),
+    (Some(mut popped), None) => popped.pop().map_or(RedisValue::NullBulk, RedisValue::from),
+    (Some(popped), Some(_)) => {
+      RedisValue::Nested(popped.into_iter().map(RedisValue::from).collect())
+    }
   }
+}
 
-  async fn incr(&self, key: &str) -> Result<i64, RedisError> {
-    let mut write_handle = self.shared_data.write().await;
-    match write_handle.dict.get(key) {
-      Some(Value::Raw(v)) => {
-        let v: Result<i64, RedisError> = match String::from_utf8_lossy(v) {
-          Cow::Borrowed(v) => v.parse::<i64>().or(Err(RedisError::Type)),
-          _ => Err(RedisError::Type),
-        };
+/// Counts commands replayed at startup, logging every
+/// `LOAD_PROGRESS_EVERY` of them and enforcing the load timeout.
+struct LoadProgress<'a> {
+  source: &'a str,
+  started: Instant,
+  timeout: Option<Duration>,
+  count: usize,
+}
 
-        let new_value = v? + 1;
-        write_handle.dict.insert(
-          key.to_owned(),
-          Value::Raw(Arc::new(new_value.to_string().as_bytes().to_vec())),
-        );
-        Ok(new_value)
-      }
-      None => {
-        write_handle.dict.insert(
-          key.to_owned(),
-          Value::Raw(Arc::new("1".as_bytes().to_vec())),
-        );
-        Ok(1)
-      }
-      Some(_) => Result::Err(RedisError::Type),
+impl<'a> LoadProgress<'a> {
+  fn new(source: &'a str, timeout: Option<Duration>) -> LoadProgress<'a> {
+    LoadProgress {
+      source,
+      started: Instant::now(),
+      timeout,
+      count: 0,
+    }
+  }
+
+  fn tick(&mut self) -> Result<(), RedisError> {
+    self.count += 1;
+    if self.count.is_multiple_of(LOAD_PROGRESS_EVERY) {
+      info!("loaded {} commands from {} so far", self.count, self.source);
+    }
+    match self.timeout {
+      Some(timeout) if self.started.elapsed() > timeout => Err(RedisError::Other(format!(
+        "loading {} took longer than the {}s load timeout",
+        self.source,
+        timeout.as_secs_f64()
+      ))),
+      _ => Ok(()),
     }
   }
 }
 
-async fn spawn_ttl_heap_cleaner(shared_data: Arc<RwLock<SharedData>>) {
-  tokio::spawn(async move {
-    let mut interval = tokio::time::interval(Duration::from_secs(1));
+/// `arg` quoted the way MONITOR shows it, non-printable bytes escaped.
+fn repr(arg: &[u8]) -> String {
+  let mut quoted = String::from("\"");
+  for &byte in arg {
+    match byte {
+      b'\\' => quoted.push_str("\\\\"),
+      b'"' => quoted.push_str("\\\""),
+      b'\n' => quoted.push_str("\\n"),
+      b'\r' => quoted.push_str("\\r"),
+      b'\t' => quoted.push_str("\\t"),
+      0x20..=0x7e => quoted.push(byte as char),
+      _ => quoted.push_str(&format!("\\x{byte:02x}")),
+    }
+  }
+  quoted.push('"');
+  quoted
+}
 
-    loop {
-      interval.tick().await;
+fn now_millis() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap()
+    .as_millis() as u64
+}
 
-      let s_data = &mut shared_data.write().await;
+/// Startup settings of the server.
+pub struct Config {
+  pub read_only: bool,
+  /// Where SAVE writes the snapshot, loaded back on startup.
+  pub snapshot_path: Option<PathBuf>,
+  /// SHUTDOWN is refused unless set, so it can't stop the server by accident.
+  pub allow_shutdown: bool,
+  /// Commands running at least this long end up in the slowlog.
+  pub slowlog_slower_than_micros: u64,
+  pub slowlog_max_len: usize,
+  /// Lowercased names of commands answered as if they didn't exist.
+  pub disabled_commands: HashSet<String>,
+  /// Startup fails if replaying the snapshot or journal takes longer.
+  pub load_timeout: Option<Duration>,
+  /// Seeds the generator behind random replies like HRANDFIELD, so tests
+  /// can reproduce them.
+  pub rng_seed: Option<u64>,
+  /// When the journal is fsynced.
+  pub fsync: FsyncPolicy,
+  /// Keys the dict has room for before its first rehash, worth raising
+  /// when a big journal or snapshot is loaded on startup.
+  pub initial_capacity: usize,
+  pub maxmemory_policy: MaxMemoryPolicy,
+  pub command_log_format: CommandLogFormat,
+  /// Longest inline command or multibulk header line read from a client.
+  pub max_inline_len: usize,
+  /// Runs the background task removing keys past their deadline. Without
+  /// it keys only expire lazily, when they are accessed, and the memory of
+  /// ones never touched again isn't reclaimed.
+  pub active_expire: bool,
+  /// Most keys the cleaner removes under one write lock, a pass with more
+  /// to do takes the lock again once other clients had their turn.
+  pub active_expire_batch: usize,
+  /// Time between cleaner passes.
+  pub active_expire_interval: Duration,
+  /// Sets TCP_NODELAY on accepted sockets so small replies aren't held
+  /// back by Nagle's algorithm.
+  pub tcp_nodelay: bool,
+  /// Idle time after which TCP keepalive probes are sent, and the interval
+  /// between them. Keepalive is off when unset.
+  pub tcp_keepalive: Option<Duration>,
+}
 
-      if s_data.ttl_heap.is_empty() {
-        continue;
-      };
+/// What to do once memory runs out. Nothing is evicted yet, the policy
+/// only decides what gets tracked.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MaxMemoryPolicy {
+  #[default]
+  NoEviction,
+  /// Tracks how often every key is accessed, see OBJECT FREQ.
+  AllKeysLfu,
+}
 
-      let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
+impl FromStr for MaxMemoryPolicy {
+  type Err = String;
 
-      while let Some(Reverse((w, key))) = s_data.ttl_heap.pop() {
-        if w >= now {
-          s_data.ttl_heap.push(Reverse((w, key)));
-          break;
-        }
-        info!("deleting stale key={}", key);
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().as_str() {
+      "noeviction" => Ok(MaxMemoryPolicy::NoEviction),
+      "allkeys-lfu" => Ok(MaxMemoryPolicy::AllKeysLfu),
+      _ => Err(format!(
+        "unsupported maxmemory policy '{s}', expected noeviction or allkeys-lfu"
+      )),
+    }
+  }
+}
 
-        s_data.dict.remove(&key).unwrap();
-      }
+/// How every command is logged at debug level.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CommandLogFormat {
+  #[default]
+  Text,
+  /// `{"command":"get","keys":["k"]}`, for log processors.
+  Json,
+}
+
+impl FromStr for CommandLogFormat {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().as_str() {
+      "text" => Ok(CommandLogFormat::Text),
+      "json" => Ok(CommandLogFormat::Json),
+      _ => Err(format!(
+        "unknown command log format '{s}', expected text or json"
+      )),
     }
-  });
+  }
 }
 
-#[cfg(test)]
-mod tests {
-  use std::sync::Arc;
+impl Default for Config {
+  fn default() -> Self {
+    Config {
+      read_only: false,
+      snapshot_path: None,
+      allow_shutdown: false,
+      slowlog_slower_than_micros: slowlog::DEFAULT_SLOWER_THAN_MICROS,
+      slowlog_max_len: slowlog::DEFAULT_MAX_LEN,
+      disabled_commands: HashSet::new(),
+      load_timeout: None,
+      rng_seed: None,
+      fsync: FsyncPolicy::default(),
+      initial_capacity: DEFAULT_INITIAL_CAPACITY,
+      maxmemory_policy: MaxMemoryPolicy::default(),
+      command_log_format: CommandLogFormat::default(),
+      active_expire: true,
+      active_expire_batch: DEFAULT_ACTIVE_EXPIRE_BATCH,
+      active_expire_interval: Duration::from_millis(DEFAULT_ACTIVE_EXPIRE_INTERVAL_MS),
+      max_inline_len: DEFAULT_MAX_INLINE_LEN,
+      tcp_nodelay: true,
+      tcp_keepalive: None,
+    }
+  }
+}
 
-  use crate::journal::Disabled;
+/// Where a command being applied comes from.
+#[derive(Clone, Copy, Debug, Default)]
+struct ExecContext {
+  /// Replayed from the snapshot or journal, or sent by a primary. Already
+  /// durable, so it isn't journaled again.
+  from_replication: bool,
+}
 
-  #[tokio::test]
-  async fn test_redis_set() {
-    let redis = super::Redis::new(Disabled {}).await;
-    for i in 0..100 {
-      redis
-        .set(
-          format!("key_{}", i).as_str(),
-          format!("value_{}", i).as_bytes(),
-        )
-        .await
+impl ExecContext {
+  const REPLAY: ExecContext = ExecContext {
+    from_replication: true,
+  };
+}
+
+/// Key names of the SCANs in progress by id, as they were when each started.
+#[derive(Default)]
+struct ScanSnapshots {
+  last_id: u64,
+  snapshots: BTreeMap<u64, Vec<String>>,
+}
+
+impl ScanSnapshots {
+  /// Keeps `keys` for a new scan, dropping the oldest one if there are too
+  /// many, returns its id.
+  fn start(&mut self, keys: Vec<String>) -> u64 {
+    self.last_id += 1;
+    self.snapshots.insert(self.last_id, keys);
+    if self.snapshots.len() > MAX_SCAN_SNAPSHOTS {
+      self.snapshots.pop_first();
     }
-    for i in 0..100 {
-      let key = format!("key_{}", i);
-      let value = Arc::new(format!("value_{}", i).as_bytes().to_vec());
+    self.last_id
+  }
+}
 
-      assert_eq!(redis.get(&key).await.unwrap(), Some(value));
+/// Throughput counters reported by INFO.
+#[derive(Default)]
+struct Stats {
+  commands_processed: AtomicU64,
+  /// Commands processed during the last sampling interval.
+  ops_per_sec: AtomicU64,
+}
+
+/// Estimated memory use reported by MEMORY STATS.
+struct MemoryStats {
+  keys: usize,
+  /// Keys and values, as added up by `Value::mem_size`.
+  dataset_bytes: usize,
+  /// Dict slots, expiry bookkeeping and the ttl heap.
+  overhead_bytes: usize,
+}
+
+/// A connected session as seen by CLIENT commands.
+struct Client {
+  addr: String,
+  kill: Arc<Notify>,
+}
+
+pub struct Redis<W: Writer> {
+  /// The whole keyspace behind one lock. Multi-key commands take it once
+  /// rather than a lock per key, so no order of keys can deadlock them.
+  shared_data: Arc<RwLock<SharedData>>,
+  journal: W,
+  config: Config,
+  /// Primary (host, port) while running as a replica.
+  replica_of: RwLock<Option<(String, u16)>>,
+  /// Mutating commands are rejected with READONLY while set.
+  read_only: AtomicBool,
+  /// Lists longer than this are converted from packed to linked.
+  list_packed_threshold: AtomicUsize,
+  clients: Mutex<HashMap<u64, Client>>,
+  next_client_id: AtomicU64,
+  /// Notified once SHUTDOWN has saved and the server should exit.
+  shutdown: Notify,
+  /// Unix seconds of the last successful SAVE, the start time before that.
+  last_save: AtomicU64,
+  slowlog: Slowlog,
+  stats: Arc<Stats>,
+  /// Whether the ttl heap cleaner runs, lazy expiration always does.
+  active_expire: Arc<AtomicBool>,
+  /// `None` with active expiry turned off in the config.
+  ttl_cleaner: Option<JoinHandle<()>>,
+  pubsub: PubSub,
+  rng: Mutex<StdRng>,
+  /// Journal writes so far and how many of them the last fsync covered,
+  /// WAITAOF waits for the latter to catch up.
+  journal_written: AtomicU64,
+  journal_synced: watch::Sender<u64>,
+  /// Every command run, as MONITOR sessions show it.
+  monitors: broadcast::Sender<String>,
+  /// Held shared by mutating commands from journaling to applying them
+  /// and exclusively by journal rewrites, which see both or neither.
+  rewrite_lock: RwLock<()>,
+  scans: Mutex<ScanSnapshots>,
+}
+
+impl<W: Writer> Redis<W> {
+  /// Registers a new session, returns its id and the handle notified
+  /// when it gets killed.
+  pub fn register_client(&self, addr: String) -> (u64, Arc<Notify>) {
+    let id = self.next_client_id.fetch_add(1, Ordering::Relaxed);
+    let kill = Arc::new(Notify::new());
+    let client = Client {
+      addr,
+      kill: kill.clone(),
+    };
+    sync::lock(&self.clients).insert(id, client);
+    (id, kill)
+  }
+
+  pub fn pubsub(&self) -> &PubSub {
+    &self.pubsub
+  }
+
+  /// Whether the background tasks are still running, checked by the health
+  /// endpoint.
+  pub fn is_healthy(&self) -> bool {
+    !matches!(&self.ttl_cleaner, Some(cleaner) if cleaner.is_finished())
+  }
+
+  pub fn unregister_client(&self, id: u64) {
+    sync::lock(&self.clients).remove(&id);
+  }
+
+  /// Lines for a new MONITOR session, one per command run from now on.
+  pub fn monitor(&self) -> broadcast::Receiver<String> {
+    self.monitors.subscribe()
+  }
+
+  /// Shows the command `args`, run by client `id`, to the MONITOR sessions.
+  pub fn feed_monitors(&self, id: u64, args: &[Vec<u8>]) {
+    if self.monitors.receiver_count() == 0 {
+      return;
+    }
+    let addr = match sync::lock(&self.clients).get(&id) {
+      Some(client) => client.addr.clone(),
+      None => return,
+    };
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let args = args.iter().map(|arg| repr(arg)).collect::<Vec<_>>();
+    let line = format!(
+      "{}.{:06} [0 {}] {}",
+      now.as_secs(),
+      now.subsec_micros(),
+      addr,
+      args.join(" ")
+    );
+    // fails only once the last monitor is gone
+    let _ = self.monitors.send(line);
+  }
+}
+
+impl<W: Writer + Send> Redis<W> {
+  pub async fn new(writer: W) -> Redis<W> {
+    Redis::with_config(writer, Config::default()).await
+  }
+
+  pub async fn with_config(writer: W, config: Config) -> Redis<W> {
+    let shared_data = RwLock::new(SharedData {
+      dict: HashMap::with_capacity(config.initial_capacity),
+      expires: HashMap::new(),
+      ttl_heap: BTreeSet::new(),
+      lfu: Mutex::new(HashMap::new()),
+    });
+    let arc = Arc::new(shared_data);
+    let active_expire = Arc::new(AtomicBool::new(config.active_expire));
+    let ttl_cleaner = config.active_expire.then(|| {
+      spawn_ttl_heap_cleaner(
+        arc.clone(),
+        active_expire.clone(),
+        config.active_expire_batch,
+        config.active_expire_interval,
+      )
+    });
+    let stats = Arc::new(Stats::default());
+    spawn_ops_sampler(stats.clone());
+
+    Redis {
+      shared_data: arc,
+      journal: writer,
+      replica_of: RwLock::new(None),
+      read_only: AtomicBool::new(config.read_only),
+      list_packed_threshold: AtomicUsize::new(DEFAULT_PACKED_THRESHOLD),
+      clients: Mutex::new(HashMap::new()),
+      next_client_id: AtomicU64::new(1),
+      shutdown: Notify::new(),
+      last_save: AtomicU64::new(now_millis() / 1000),
+      slowlog: Slowlog::new(config.slowlog_slower_than_micros, config.slowlog_max_len),
+      stats,
+      active_expire,
+      ttl_cleaner,
+      pubsub: PubSub::default(),
+      rng: Mutex::new(match config.rng_seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+      }),
+      journal_written: AtomicU64::new(0),
+      journal_synced: watch::Sender::new(0),
+      monitors: broadcast::channel(MONITOR_BACKLOG).0,
+      rewrite_lock: RwLock::new(()),
+      scans: Mutex::new(ScanSnapshots::default()),
+      config,
+    }
+  }
+
+  pub fn set_read_only(&self, read_only: bool) {
+    self.read_only.store(read_only, Ordering::Relaxed);
+  }
+
+  pub async fn is_replica(&self) -> bool {
+    self.replica_of.read().await.is_some()
+  }
+
+  fn is_read_only(&self) -> bool {
+    self.read_only.load(Ordering::Relaxed)
+  }
+
+  /// Whether a command that ran for `duration` would make the slowlog.
+  pub fn is_slow(&self, duration: Duration) -> bool {
+    self.slowlog.is_slow(duration)
+  }
+
+  pub fn max_inline_len(&self) -> usize {
+    self.config.max_inline_len
+  }
+
+  pub fn tcp_nodelay(&self) -> bool {
+    self.config.tcp_nodelay
+  }
+
+  pub fn tcp_keepalive(&self) -> Option<Duration> {
+    self.config.tcp_keepalive
+  }
+
+  /// Whether `name`, lowercased, was turned off with --disable-command.
+  pub fn is_disabled(&self, name: &str) -> bool {
+    self.config.disabled_commands.contains(name)
+  }
+
+  /// Runs `cmd` for a client. Every lock is released by the time it
+  /// returns, the reply owns or shares its data, so a client slow to read
+  /// the reply holds up no one else while it is written out.
+  pub async fn exec<'a>(&self, cmd: &'a Command<'a>) -> Result<RedisValue, RedisError> {
+    if cmd.is_mutating() && self.is_read_only() {
+      return Err(RedisError::ReadOnly);
+    }
+
+    self
+      .stats
+      .commands_processed
+      .fetch_add(1, Ordering::Relaxed);
+    self.log_command(cmd);
+    let started = Instant::now();
+    let rewrite_guard = match cmd.is_mutating() {
+      true => Some(self.rewrite_lock.read().await),
+      false => None,
+    };
+    let result = self.apply(cmd, ExecContext::default()).await;
+    drop(rewrite_guard);
+    if self.config.maxmemory_policy == MaxMemoryPolicy::AllKeysLfu {
+      self.touch(&cmd.accessed_keys()).await;
+    }
+    let timestamp = now_millis() / 1000;
+    self
+      .slowlog
+      .record(timestamp, started.elapsed(), || format!("{cmd:?}"));
+    result
+  }
+
+  /// Logs `cmd` at debug level, as its Debug form or as a JSON object with
+  /// its name and keys.
+  fn log_command(&self, cmd: &Command) {
+    if !log_enabled!(Level::Debug) {
+      return;
+    }
+    match self.config.command_log_format {
+      CommandLogFormat::Text => debug!("{:?}", cmd),
+      CommandLogFormat::Json => {
+        let name = format!("{cmd:?}");
+        let name = name.split(['(', ' ']).next().unwrap_or_default();
+        let line = serde_json::json!({
+          "command": name.to_lowercase(),
+          "keys": cmd.accessed_keys(),
+        });
+        debug!("{}", line)
+      }
+    }
+  }
+
+  /// Runs `cmd` regardless of read-only mode, which only guards clients.
+  async fn apply<'a>(
+    &self,
+    cmd: &'a Command<'a>,
+    ctx: ExecContext,
+  ) -> Result<RedisValue, RedisError> {
+    let absolute = cmd.with_deadline(now_millis());
+    let cmd = absolute.as_ref().unwrap_or(cmd);
+    let result = self.run(cmd).await;
+    // failed commands changed nothing, replaying them would only fail again
+    if result.is_ok() && cmd.is_mutating() {
+      self.journal_write(ctx, cmd).await;
+    }
+    result
+  }
+
+  async fn run<'a>(&self, cmd: &'a Command<'a>) -> Result<RedisValue, RedisError> {
+    match cmd {
+      Command::Set(key, value) => {
+        self.set(key, value).await;
+        Ok(RedisValue::Ok)
+      }
+      Command::SetWith(key, value, options) => self.set_with(key, value, options).await,
+      Command::Get(key) => self.get_reply(key).await,
+      Command::MGet(keys) => Ok(RedisValue::Nested(
+        self
+          .mget(keys)
+          .await
+          .into_iter()
+          .map(|v| match v {
+            Option::None => RedisValue::NullBulk,
+            Option::Some(v) => RedisValue::SimpleString(v),
+          })
+          .collect(),
+      )),
+      Command::Append(key, value) => Ok(RedisValue::from(self.append(key, value).await?)),
+      Command::SetRange(key, offset, value) => {
+        Ok(RedisValue::from(self.setrange(key, *offset, value).await?))
+      }
+      Command::BitPos(key, bit, range) => {
+        Ok(RedisValue::Integer(self.bitpos(key, *bit, *range).await?))
+      }
+      Command::GetEx(key, expiry) => match self.getex(key, expiry.as_ref()).await? {
+        Option::None => Ok(RedisValue::NullBulk),
+        Option::Some(v) => Ok(RedisValue::SimpleString(v)),
+      },
+      Command::SetEx(key, value, ttl) => {
+        self.setex(key, value, *ttl).await;
+        Ok(RedisValue::Ok)
+      }
+      Command::Ping(None) => Ok(RedisValue::Status("PONG".into())),
+      Command::Ping(Some(message)) => Ok(RedisValue::from(message.to_vec())),
+      Command::CommandDocs => Ok(RedisValue::BulkString(Vec::new())),
+      Command::GetKeys(args) => {
+        let keys = cmd::keys(args)?;
+        Ok(RedisValue::Array(
+          keys.iter().map(|k| Some(k.as_bytes().to_vec())).collect(),
+        ))
+      }
+      Command::ListCommands => Ok(RedisValue::Array(
+        cmd::names().map(|n| Some(n.as_bytes().to_vec())).collect(),
+      )),
+      Command::CountCommands => Ok(RedisValue::from(cmd::names().count())),
+      Command::DescribeCommands(names) => Ok(RedisValue::Nested(
+        names.iter().map(|name| cmd::info(name)).collect(),
+      )),
+      Command::Info(section) => Ok(RedisValue::Verbatim(
+        "txt",
+        self.info(*section).into_bytes(),
+      )),
+      Command::DbSize => Ok(RedisValue::Integer(self.keys_count().await as i64)),
+      Command::Config => Ok(RedisValue::BulkString(Vec::new())),
+      Command::Lpush(key, value) => {
+        self.push(key, value, true, true).await?;
+        Ok(RedisValue::Ok)
+      }
+      Command::Rpush(key, value) => {
+        self.push(key, value, true, false).await?;
+        Ok(RedisValue::Ok)
+      }
+      Command::LpushX(key, value) => {
+        self.push(key, value, false, true).await?;
+        Ok(RedisValue::Ok)
+      }
+      Command::RpushX(key, value) => {
+        self.push(key, value, false, false).await?;
+        Ok(RedisValue::Ok)
+      }
+      Command::Lpop(key, count) => {
+        let popped = self.pop(key, count.unwrap_or(1), true).await?;
+        Ok(pop_reply(popped, *count))
+      }
+      Command::Rpop(key, count) => {
+        let popped = self.pop(key, count.unwrap_or(1), false).await?;
+        Ok(pop_reply(popped, *count))
+      }
+      Command::Copy(source, destination, replace) => {
+        let copied = self.copy(source, destination, *replace).await?;
+        Ok(RedisValue::Integer(i64::from(copied)))
+      }
+      Command::Del(keys) => {
+        let del_keys_count: usize = self.delete(&keys).await;
+
+        Ok(RedisValue::Integer(del_keys_count as i64))
+      }
+      Command::FlushAll => {
+        let mut write_handle = self.shared_data.write().await;
+        write_handle.dict.clear();
+        write_handle.expires.clear();
+        write_handle.ttl_heap.clear();
+        sync::get_mut(&mut write_handle.lfu).clear();
+        Ok(RedisValue::Ok)
+      }
+      Command::Incr(key) => Ok(RedisValue::Integer(self.incr_by(key, 1).await?)),
+      Command::IncrByFloat(key, by) => {
+        let value = self.incr_by_float(key, by.0).await?;
+        Ok(RedisValue::from(value.into_bytes()))
+      }
+      Command::Decr(key) => Ok(RedisValue::Integer(self.incr_by(key, -1).await?)),
+      Command::Hset(key, pairs) => Ok(RedisValue::from(self.hset(key, pairs, true).await?)),
+      Command::HsetNx(key, field, value) => {
+        let added = self.hset(key, &[(*field, *value)], false).await?;
+        Ok(RedisValue::from(added))
+      }
+      Command::Hget(key, field) => match self.hmget(key, &[*field]).await?.pop().flatten() {
+        Option::None => Ok(RedisValue::NullBulk),
+        Option::Some(v) => Ok(RedisValue::SimpleString(Arc::new(v))),
+      },
+      Command::HRandField(key, None, _) => match self.hrandfield(key, 1, false).await?.pop() {
+        Some(field) => Ok(RedisValue::from(field)),
+        None => Ok(RedisValue::NullBulk),
+      },
+      Command::HRandField(key, Some(count), with_values) => {
+        let fields = self.hrandfield(key, *count, *with_values).await?;
+        Ok(RedisValue::Nested(
+          fields.into_iter().map(RedisValue::from).collect(),
+        ))
+      }
+      Command::Hmget(key, fields) => {
+        let values = self.hmget(key, fields).await?;
+        Ok(RedisValue::Nested(
+          values
+            .into_iter()
+            .map(|v| match v {
+              Option::None => RedisValue::NullBulk,
+              Option::Some(v) => RedisValue::SimpleString(Arc::new(v)),
+            })
+            .collect(),
+        ))
+      }
+      Command::Hlen(key) => Ok(RedisValue::from(self.hlen(key).await?)),
+      // `Nested` rather than `Array`, a missing hash is the empty array `*0`
+      Command::Hkeys(key) => Ok(RedisValue::Nested(
+        self
+          .hfields(key, true)
+          .await?
+          .into_iter()
+          .map(RedisValue::from)
+          .collect(),
+      )),
+      Command::Hvals(key) => Ok(RedisValue::Nested(
+        self
+          .hfields(key, false)
+          .await?
+          .into_iter()
+          .map(RedisValue::from)
+          .collect(),
+      )),
+      Command::Hscan(key, cursor, options) => {
+        let (cursor, batch) = self.hscan(key, *cursor, options).await?;
+        Ok(RedisValue::Nested(vec![
+          RedisValue::from(cursor.to_string().into_bytes()),
+          RedisValue::Nested(batch.into_iter().map(RedisValue::from).collect()),
+        ]))
+      }
+      Command::Sscan(key, cursor, options) => {
+        let (cursor, batch) = self.sscan(key, *cursor, options).await?;
+        Ok(RedisValue::Nested(vec![
+          RedisValue::from(cursor.to_string().into_bytes()),
+          RedisValue::Nested(batch.into_iter().map(RedisValue::from).collect()),
+        ]))
+      }
+      Command::Zscan(key, cursor, options) => {
+        let (cursor, batch) = self.zscan(key, *cursor, options).await?;
+        Ok(RedisValue::Nested(vec![
+          RedisValue::from(cursor.to_string().into_bytes()),
+          RedisValue::Nested(batch.into_iter().map(RedisValue::from).collect()),
+        ]))
+      }
+      Command::Keys(pattern) => Ok(RedisValue::Nested(
+        self
+          .keys(pattern)
+          .await
+          .into_iter()
+          .map(RedisValue::from)
+          .collect(),
+      )),
+      Command::Scan(cursor, options) => {
+        let (cursor, batch) = self.scan(*cursor, options).await?;
+        Ok(RedisValue::Nested(vec![
+          RedisValue::from(cursor.to_string().into_bytes()),
+          RedisValue::Nested(batch.into_iter().map(RedisValue::from).collect()),
+        ]))
+      }
+      Command::Sadd(key, members) => Ok(RedisValue::from(self.sadd(key, members).await?)),
+      Command::Zadd(key, pairs) => Ok(RedisValue::from(self.zadd(key, pairs).await?)),
+      Command::ZRangeByScore(key, min, max, with_scores, window) => {
+        let read_from = self.read_live(key).await;
+        Ok(match read_from.zset(key)? {
+          Some(zset) => range_reply(zset.range_by_score(*min, *max), *with_scores, *window),
+          None => RedisValue::Nested(Vec::new()),
+        })
+      }
+      Command::ZRangeByLex(key, min, max, window) => {
+        let read_from = self.read_live(key).await;
+        Ok(match read_from.zset(key)? {
+          Some(zset) => range_reply(zset.range_by_lex(*min, *max), false, *window),
+          None => RedisValue::Nested(Vec::new()),
+        })
+      }
+      Command::SInterCard(keys, limit) => {
+        Ok(RedisValue::from(self.sintercard(keys, *limit).await?))
+      }
+      Command::Lolwut => Ok(RedisValue::SimpleString(Arc::new(
+        format!("Reddis ver. {}\n", env!("CARGO_PKG_VERSION")).into_bytes(),
+      ))),
+      Command::Llen(key) => Ok(RedisValue::from(self.llen(key).await?)),
+      Command::Lindex(key, index) => match self.lindex(key, *index).await? {
+        Option::None => Ok(RedisValue::NullBulk),
+        Option::Some(v) => Ok(RedisValue::from(v)),
+      },
+      Command::Lrange(key, start, stop) => self.lrange_reply(key, *start, *stop).await,
+      Command::Sort(key, options) => {
+        let values = self.sort(key, options).await?;
+        Ok(RedisValue::Nested(
+          values.into_iter().map(RedisValue::from).collect(),
+        ))
+      }
+      Command::Debug(subcommand, args) => self.debug(subcommand, args).await,
+      Command::ClientKill(filter) => Ok(RedisValue::from(self.kill_clients(filter))),
+      // nothing is ever evicted
+      Command::ClientNoEvict(_) => Ok(RedisValue::Ok),
+      // answered by the session, which knows its own id
+      Command::ClientId => Ok(RedisValue::Integer(0)),
+      Command::Subscribe(_)
+      | Command::Unsubscribe(_)
+      | Command::Psubscribe(_)
+      | Command::Punsubscribe(_)
+      | Command::Monitor
+      | Command::BgRewriteAof
+      | Command::Multi
+      | Command::Exec
+      | Command::Discard
+      | Command::Reset
+      | Command::Hello(_) => Err(RedisError::Other(
+        "only available to connected clients".to_string(),
+      )),
+      // the session closes the connection after replying
+      Command::Quit => Ok(RedisValue::Ok),
+      Command::Publish(channel, message) => {
+        Ok(RedisValue::from(self.pubsub.publish(channel, message)))
+      }
+      Command::PubSubChannels(pattern) => Ok(RedisValue::Nested(
+        self
+          .pubsub
+          .channels(*pattern)
+          .into_iter()
+          .map(RedisValue::from)
+          .collect(),
+      )),
+      Command::PubSubNumSub(channels) => {
+        let mut reply = Vec::new();
+        for (channel, count) in channels.iter().zip(self.pubsub.numsub(channels)) {
+          reply.push(RedisValue::from(channel.to_vec()));
+          reply.push(RedisValue::from(count));
+        }
+        Ok(RedisValue::Nested(reply))
+      }
+      Command::PubSubNumPat => Ok(RedisValue::from(self.pubsub.numpat())),
+      Command::WaitAof(numlocal, _, timeout) => self.waitaof(*numlocal, *timeout).await,
+      Command::Save => {
+        self.save().await?;
+        Ok(RedisValue::Ok)
+      }
+      Command::SlowlogGet(count) => {
+        let entries = self.slowlog.get(count.unwrap_or(10), |e| {
+          RedisValue::Nested(vec![
+            RedisValue::Integer(e.id as i64),
+            RedisValue::Integer(e.timestamp as i64),
+            RedisValue::Integer(e.duration.as_micros() as i64),
+            RedisValue::Nested(vec![RedisValue::from(e.command.clone().into_bytes())]),
+          ])
+        });
+        Ok(RedisValue::Nested(entries))
+      }
+      Command::SlowlogLen => Ok(RedisValue::from(self.slowlog.len())),
+      Command::SlowlogReset => {
+        self.slowlog.reset();
+        Ok(RedisValue::Ok)
+      }
+      Command::LastSave => Ok(RedisValue::Integer(
+        self.last_save.load(Ordering::Relaxed) as i64
+      )),
+      Command::Shutdown(nosave) => {
+        self.shutdown(*nosave).await?;
+        Ok(RedisValue::Ok)
+      }
+      // there are never replicas to fail over to, only the errors are there
+      // so tooling gets a sensible answer
+      Command::Failover(false) => Err(RedisError::Other(
+        "FAILOVER requires connected replicas".to_string(),
+      )),
+      Command::Failover(true) => Err(RedisError::Other("No failover in progress".to_string())),
+      Command::Role => Ok(self.role().await),
+      Command::ClusterInfo => Ok(RedisValue::Verbatim(
+        "txt",
+        b"cluster_enabled:0\r\ncluster_state:ok\r\ncluster_slots_assigned:0\r\n\
+          cluster_known_nodes:1\r\ncluster_size:0\r\n"
+          .to_vec(),
+      )),
+      Command::ClusterSlots | Command::ClusterShards => Ok(RedisValue::Nested(Vec::new())),
+      Command::MemoryUsage(key) => match self.memory_usage(key).await {
+        Option::None => Ok(RedisValue::NullBulk),
+        Option::Some(size) => Ok(RedisValue::from(size)),
+      },
+      Command::MemoryStats => {
+        let stats = self.memory_stats().await;
+        Ok(RedisValue::Nested(vec![
+          RedisValue::from("keys.count"),
+          RedisValue::from(stats.keys),
+          RedisValue::from("dataset.bytes"),
+          RedisValue::from(stats.dataset_bytes),
+          RedisValue::from("overhead.total"),
+          RedisValue::from(stats.overhead_bytes),
+        ]))
+      }
+      Command::MemoryDoctor => {
+        let report = memory_doctor(&self.memory_stats().await);
+        Ok(RedisValue::from(report.into_bytes()))
+      }
+      Command::ObjectRefCount(key) => match self.refcount(key).await {
+        Option::None => Err(RedisError::Other("no such key".to_string())),
+        Option::Some(count) => Ok(RedisValue::from(count)),
+      },
+      Command::ObjectEncoding(key) => match self.encoding(key).await {
+        Option::None => Err(RedisError::Other("no such key".to_string())),
+        Option::Some(encoding) => Ok(RedisValue::from(encoding)),
+      },
+      Command::ObjectFreq(key) => self.freq(key).await,
+      Command::ReplicaOf(host, port) => {
+        self.replica_of(host, port).await?;
+        Ok(RedisValue::Ok)
+      }
+    }
+  }
+
+  /// Overwrites `key`, dropping whatever expiry it had like Redis does.
+  async fn set(&self, key: &str, value: &[u8]) {
+    let mut write_handle = self.shared_data.write().await;
+    write_handle.clear_deadline(key);
+    write_handle
+      .dict
+      .insert(key.to_string(), Value::string(value));
+  }
+
+  /// SET honoring NX, XX, KEEPTTL and PXAT. Replies with the previous value under GET,
+  /// which then has to be a string, and with `OK` or nil otherwise.
+  async fn set_with(
+    &self,
+    key: &str,
+    value: &[u8],
+    options: &SetOptions,
+  ) -> Result<RedisValue, RedisError> {
+    let mut write_handle = self.shared_data.write().await;
+    write_handle.expire_if_needed(key);
+
+    let old = write_handle.dict.get(key);
+    let reply = match old {
+      Some(old) if options.get => old.as_reply().ok_or(RedisError::Type)?,
+      _ if options.get => RedisValue::NullBulk,
+      _ => RedisValue::Ok,
+    };
+    let allowed = match options.condition {
+      Some(SetCondition::Nx) => old.is_none(),
+      Some(SetCondition::Xx) => old.is_some(),
+      None => true,
+    };
+    if !allowed {
+      return Ok(if options.get {
+        reply
+      } else {
+        RedisValue::NullBulk
+      });
+    }
+
+    if !options.keep_ttl {
+      write_handle.clear_deadline(key);
+    }
+    write_handle
+      .dict
+      .insert(key.to_string(), Value::string(value));
+    let deadline = options
+      .expire_at
+      .or_else(|| options.ttl.map(|ttl| now_millis() + ttl));
+    match deadline {
+      // a deadline already past, as a replayed journal can have
+      Some(at) if at <= now_millis() => {
+        write_handle.remove(key);
+      }
+      Some(at) => write_handle.set_deadline(key, at),
+      None => {}
+    }
+    Ok(reply)
+  }
+
+  /// Appends `value` to the string at `key`, returns its new length.
+  async fn append(&self, key: &str, value: &[u8]) -> Result<usize, RedisError> {
+    self.setrange_at(key, value, |len| len).await
+  }
+
+  /// Overwrites the string at `key` from `offset` on, zero-padding it up to
+  /// there, returns its new length. An empty `value` changes nothing.
+  async fn setrange(&self, key: &str, offset: usize, value: &[u8]) -> Result<usize, RedisError> {
+    if value.is_empty() {
+      let read_from = self.read_live(key).await;
+      return match read_from.dict.get(key) {
+        Some(current) => current
+          .as_string()
+          .map(|data| data.len())
+          .ok_or(RedisError::Type),
+        None => Ok(0),
+      };
+    }
+    self.setrange_at(key, value, |_| offset).await
+  }
+
+  /// Writes `value` into the string at `key`, created empty if missing, at
+  /// the offset `at` picks given its length.
+  async fn setrange_at(
+    &self,
+    key: &str,
+    value: &[u8],
+    at: impl FnOnce(usize) -> usize,
+  ) -> Result<usize, RedisError> {
+    let mut write_handle = self.shared_data.write().await;
+    write_handle.expire_if_needed(key);
+    let current = match write_handle.dict.get(key) {
+      Some(current) => current.as_string().ok_or(RedisError::Type)?.len(),
+      None => 0,
+    };
+    let offset = at(current);
+    if offset + value.len() > MAX_STRING_LEN {
+      return Err(RedisError::Other(
+        "string exceeds maximum allowed size (proto-max-bulk-len)".to_string(),
+      ));
+    }
+
+    let data = write_handle
+      .dict
+      .entry(key.to_string())
+      .or_insert_with(|| Value::Raw(Arc::new(Vec::new())))
+      .raw_mut(offset + value.len())
+      .ok_or(RedisError::Type)?;
+    data[offset..offset + value.len()].copy_from_slice(value);
+    Ok(data.len())
+  }
+
+  async fn setex(&self, key: &str, value: &[u8], ttl: usize) {
+    let s_data = &mut self.shared_data.write().await;
+
+    s_data.dict.insert(key.to_string(), Value::string(value));
+
+    let at = now_millis() + ttl as u64 * 1000;
+
+    s_data.set_deadline(key, at);
+
+    debug!(
+      "set deadline of key={}, ttl_heap_len={}",
+      key,
+      s_data.ttl_heap.len()
+    );
+  }
+
+  /// Bumps the access frequency of those of `keys` that exist, the first
+  /// access to a key starting its counter.
+  async fn touch(&self, keys: &[&str]) {
+    if keys.is_empty() {
+      return;
+    }
+    let now = now_millis() / 60_000;
+    let read_from = self.shared_data.read().await;
+    let mut counters = sync::lock(&read_from.lfu);
+    let mut rng = sync::lock(&self.rng);
+    for key in keys {
+      if !read_from.dict.contains_key(*key) {
+        continue;
+      }
+      match counters.entry(key.to_string()) {
+        Entry::Occupied(mut counter) => counter.get_mut().touch(now, rng.gen_range(0.0..1.0)),
+        Entry::Vacant(counter) => {
+          counter.insert(lfu::Counter::new(now));
+        }
+      }
+    }
+  }
+
+  /// OBJECT FREQ, the access counter of `key` with its decay applied.
+  async fn freq(&self, key: &str) -> Result<RedisValue, RedisError> {
+    if self.config.maxmemory_policy != MaxMemoryPolicy::AllKeysLfu {
+      return Err(RedisError::Other(
+        "An LFU maxmemory policy is not selected, access frequency not tracked".to_string(),
+      ));
+    }
+    let read_from = self.read_live(key).await;
+    if !read_from.dict.contains_key(key) {
+      return Err(RedisError::Other("no such key".to_string()));
+    }
+    let now = now_millis() / 60_000;
+    let freq = sync::lock(&read_from.lfu)
+      .get(key)
+      .map_or(lfu::INIT_VAL, |counter| counter.value(now));
+    Ok(RedisValue::from(freq as usize))
+  }
+
+  /// Takes the read lock, lazily expiring `key` first if its deadline passed.
+  async fn read_live(&self, key: &str) -> RwLockReadGuard<'_, SharedData> {
+    let read_from = self.shared_data.read().await;
+    if !read_from.is_expired(key) {
+      return read_from;
+    }
+    drop(read_from);
+    self.shared_data.write().await.expire_if_needed(key);
+    self.shared_data.read().await
+  }
+
+  /// Position of the first `bit` of the string at `key` between the bytes
+  /// `start` and `end`. A missing key reads as all zeros, and so does the
+  /// space past the end of the string when there is no range.
+  async fn bitpos(
+    &self,
+    key: &str,
+    bit: bool,
+    range: Option<(i64, i64)>,
+  ) -> Result<i64, RedisError> {
+    let read_from = self.read_live(key).await;
+    let value = match read_from.dict.get(key) {
+      Some(value) => value.as_string().ok_or(RedisError::Type)?,
+      None => return Ok(if bit { -1 } else { 0 }),
+    };
+    drop(read_from);
+
+    let len = value.len() as i64;
+    let (start, end) = range.unwrap_or((0, -1));
+    let start = if start < 0 {
+      (len + start).max(0)
+    } else {
+      start
+    };
+    let end = if end < 0 { len + end } else { end.min(len - 1) };
+    if start > end {
+      return Ok(-1);
+    }
+
+    let bytes = &value[start as usize..=end as usize];
+    for (offset, byte) in (start..).zip(bytes) {
+      let byte = if bit { *byte } else { !byte };
+      if byte != 0 {
+        return Ok(offset * 8 + byte.leading_zeros() as i64);
+      }
+    }
+    Ok(if !bit && range.is_none() { len * 8 } else { -1 })
+  }
+
+  async fn get(&self, key: &str) -> Result<Option<Arc<Vec<u8>>>, RedisError> {
+    let read_from = self.read_live(key).await;
+
+    match read_from.dict.get(key) {
+      Some(value) => value.as_string().map(Some).ok_or(RedisError::Type),
+      None => Ok(None),
+    }
+  }
+
+  /// The strings at `keys`, `None` for a key that is missing or holds another
+  /// type, which MGET doesn't treat as an error.
+  async fn mget(&self, keys: &[&str]) -> Vec<Option<Arc<Vec<u8>>>> {
+    let read_from = self.shared_data.read().await;
+    keys
+      .iter()
+      .map(|key| match read_from.dict.get(*key) {
+        Some(value) if !read_from.is_expired(key) => value.as_string(),
+        _ => None,
+      })
+      .collect()
+  }
+
+  /// GET rendered straight into a reply, skipping the `Arc` of short
+  /// strings.
+  async fn get_reply(&self, key: &str) -> Result<RedisValue, RedisError> {
+    let read_from = self.read_live(key).await;
+
+    match read_from.dict.get(key) {
+      Some(value) => value.as_reply().ok_or(RedisError::Type),
+      None => Ok(RedisValue::NullBulk),
+    }
+  }
+
+  /// GET that also applies `expiry` under the same write lock.
+  async fn getex(
+    &self,
+    key: &str,
+    expiry: Option<&Expiry>,
+  ) -> Result<Option<Arc<Vec<u8>>>, RedisError> {
+    let s_data = &mut self.shared_data.write().await;
+    s_data.expire_if_needed(key);
+
+    let value = match s_data.dict.get(key) {
+      Some(value) => value.as_string().ok_or(RedisError::Type)?,
+      None => return Ok(None),
+    };
+
+    let now = now_millis();
+    let at = match expiry {
+      None => return Ok(Some(value)),
+      Some(Expiry::Persist) => {
+        s_data.clear_deadline(key);
+        return Ok(Some(value));
+      }
+      Some(Expiry::Ex(0) | Expiry::Px(0)) => {
+        return Err(RedisError::Other(
+          "invalid expire time in 'getex' command".to_string(),
+        ))
+      }
+      Some(Expiry::Ex(seconds)) => now + seconds * 1000,
+      Some(Expiry::Px(millis)) => now + millis,
+      Some(Expiry::PxAt(at)) => *at,
+    };
+
+    if at <= now {
+      s_data.remove(key);
+    } else {
+      s_data.set_deadline(key, at);
+    }
+    Ok(Some(value))
+  }
+
+  async fn push(
+    &self,
+    key: &str,
+    values: &Vec<&[u8]>,
+    allow_creation: bool,
+    front: bool,
+  ) -> Result<usize, RedisError> {
+    let threshold = self.list_packed_threshold.load(Ordering::Relaxed);
+    let mut write_from = self.shared_data.write().await;
+    write_from.expire_if_needed(key);
+
+    match write_from.list_mut(key)? {
+      Some(ll) => {
+        values
+          .iter()
+          .for_each(|v| ll.push(v.to_vec(), front, threshold));
+        Ok(ll.len())
+      }
+      None if !allow_creation => Ok(values.len()),
+      None => {
+        let mut ll = List::new();
+
+        values
+          .iter()
+          .for_each(|v| ll.push(v.to_vec(), front, threshold));
+        write_from.dict.insert(key.to_string(), Value::List(ll));
+
+        Ok(values.len())
+      }
+    }
+  }
+
+  /// Pops up to `times` elements, `None` when there is no list to pop from.
+  async fn pop(
+    &self,
+    key: &str,
+    mut times: usize,
+    front: bool,
+  ) -> Result<Option<Vec<Vec<u8>>>, RedisError> {
+    let threshold = self.list_packed_threshold.load(Ordering::Relaxed);
+    let mut write_from = self.shared_data.write().await;
+    write_from.expire_if_needed(key);
+
+    match write_from.list_mut(key)? {
+      None => Ok(None),
+      Some(ll) if ll.len() == 0 => Ok(None),
+      Some(ll) => {
+        let mut r = Vec::new();
+        while times > 0
+          && let Some(v) = ll.pop(front, threshold)
+        {
+          times -= 1;
+          r.push(v);
+        }
+        Ok(Some(r))
+      }
+    }
+  }
+
+  async fn llen(&self, key: &str) -> Result<usize, RedisError> {
+    let read_from = self.read_live(key).await;
+
+    Ok(read_from.list(key)?.map_or(0, List::len))
+  }
+
+  async fn lindex(&self, key: &str, index: i64) -> Result<Option<Vec<u8>>, RedisError> {
+    let read_from = self.read_live(key).await;
+
+    Ok(read_from.list(key)?.and_then(|ll| ll.get(index).cloned()))
+  }
+
+  async fn lrange(&self, key: &str, start: i64, stop: i64) -> Result<Vec<Vec<u8>>, RedisError> {
+    let read_from = self.read_live(key).await;
+
+    Ok(
+      read_from
+        .list(key)?
+        .map(|ll| ll.range(start, stop))
+        .unwrap_or_default(),
+    )
+  }
+
+  /// LRANGE encoded straight from the list. A range of up to
+  /// `STREAM_WINDOW` elements goes into a single buffer, a longer one is
+  /// streamed a window at a time so it never sits in memory whole. Either
+  /// way the socket is written with the lock released, so a slow reader
+  /// holds up no writers.
+  async fn lrange_reply(&self, key: &str, start: i64, stop: i64) -> Result<RedisValue, RedisError> {
+    let read_from = self.read_live(key).await;
+    let Some(list) = read_from.list(key)? else {
+      return Ok(RedisValue::Encoded(b"*0\r\n".to_vec()));
+    };
+
+    let elements = list.range_iter(start, stop);
+    if elements.len() > STREAM_WINDOW {
+      let start = match start {
+        start if start < 0 => (list.len() as i64 + start).max(0),
+        start => start,
+      };
+      let windows = ListWindows {
+        data: self.shared_data.clone(),
+        key: key.to_string(),
+        start: start as usize,
+      };
+      return Ok(RedisValue::Streamed(elements.len(), Arc::new(windows)));
+    }
+    let mut reply = format!("*{}\r\n", elements.len()).into_bytes();
+    for element in elements {
+      encode_bulk(&mut reply, element);
+    }
+    Ok(RedisValue::Encoded(reply))
+  }
+
+  /// Elements of the list at `key` sorted as numbers, or as bytes with
+  /// ALPHA, and cut to the LIMIT window. Equal numbers are ordered by their
+  /// bytes as Redis does.
+  async fn sort(&self, key: &str, options: &SortOptions) -> Result<Vec<Vec<u8>>, RedisError> {
+    let mut elements = self.lrange(key, 0, -1).await?;
+    if options.alpha {
+      elements.sort();
+    } else {
+      let mut scored = elements
+        .into_iter()
+        .map(|element| {
+          let score = std::str::from_utf8(&element)
+            .ok()?
+            .parse::<f64>()
+            .ok()
+            .filter(|score| !score.is_nan())?;
+          Some((score, element))
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| {
+          RedisError::Other("One or more scores can't be converted into double".to_string())
+        })?;
+      scored
+        .sort_by(|(a, a_bytes), (b, b_bytes)| a.total_cmp(b).then_with(|| a_bytes.cmp(b_bytes)));
+      elements = scored.into_iter().map(|(_, element)| element).collect();
+    }
+    if options.desc {
+      elements.reverse();
+    }
+
+    let (offset, count) = options.limit.unwrap_or((0, -1));
+    let count = usize::try_from(count).unwrap_or(usize::MAX);
+    Ok(elements.into_iter().skip(offset).take(count).collect())
+  }
+
+  /// Removes `keys`, returns how many of them were live; ones past their
+  /// deadline are dropped uncounted. The values are freed once the write
+  /// lock is released, big ones on a blocking thread so DEL of a huge list
+  /// doesn't stall other connections.
+  async fn delete(&self, keys: &[&str]) -> usize {
+    let (removed, count) = {
+      let mut write_handle = self.shared_data.write().await;
+      let mut count = 0;
+      let removed = keys
+        .iter()
+        .filter_map(|key| {
+          let expired = write_handle.is_expired(key);
+          let value = write_handle.remove(key)?;
+          count += usize::from(!expired);
+          Some(value)
+        })
+        .collect::<Vec<_>>();
+      (removed, count)
+    };
+    if removed.iter().any(|v| v.elements() > LAZYFREE_THRESHOLD) {
+      tokio::task::spawn_blocking(move || drop(removed));
+    }
+    count
+  }
+
+  /// Copies `source` and its deadline to `destination`, which has to be
+  /// missing unless `replace`, returns whether it did. The value is cloned
+  /// under the read lock so a big one holds up only writers.
+  async fn copy(&self, source: &str, destination: &str, replace: bool) -> Result<bool, RedisError> {
+    if source == destination {
+      return Err(RedisError::Other(
+        "source and destination objects are the same".to_string(),
+      ));
+    }
+    let (value, deadline) = {
+      let read_handle = self.read_live(source).await;
+      match read_handle.dict.get(source) {
+        Some(value) => (value.clone(), read_handle.expires.get(source).copied()),
+        None => return Ok(false),
+      }
+    };
+
+    let replaced = {
+      let mut write_handle = self.shared_data.write().await;
+      write_handle.expire_if_needed(destination);
+      if !replace && write_handle.dict.contains_key(destination) {
+        return Ok(false);
+      }
+      let replaced = write_handle.remove(destination);
+      write_handle.dict.insert(destination.to_string(), value);
+      if let Some(at) = deadline {
+        write_handle.set_deadline(destination, at);
+      }
+      replaced
+    };
+    if replaced
+      .as_ref()
+      .is_some_and(|v| v.elements() > LAZYFREE_THRESHOLD)
+    {
+      tokio::task::spawn_blocking(move || drop(replaced));
+    }
+    Ok(true)
+  }
+
+  /// Number of keys a client could still read, leaving out the ones past
+  /// their deadline that no expiry has removed yet.
+  async fn keys_count(&self) -> usize {
+    let read_handle = self.shared_data.read().await;
+    let expired = read_handle
+      .ttl_heap
+      .range(..(now_millis() + 1, String::new()))
+      .count();
+    read_handle.dict.len() - expired
+  }
+
+  /// Adds `by` to the integer at `key`, starting from 0 if it is missing.
+  /// The result is kept as `Value::Int` so a hot counter isn't parsed and
+  /// formatted on every call.
+  async fn incr_by(&self, key: &str, by: i64) -> Result<i64, RedisError> {
+    let mut write_handle = self.shared_data.write().await;
+    write_handle.expire_if_needed(key);
+
+    let value = write_handle
+      .dict
+      .entry(key.to_owned())
+      .or_insert(Value::Int(0));
+    let current = match value {
+      Value::Int(n) => *n,
+      Value::Raw(v) => parse_counter(v)?,
+      Value::Inline(v) => parse_counter(v.as_slice())?,
+      _ => return Err(RedisError::Type),
+    };
+
+    let new_value = current
+      .checked_add(by)
+      .ok_or_else(|| RedisError::Other("increment or decrement would overflow".to_string()))?;
+    *value = Value::Int(new_value);
+    Ok(new_value)
+  }
+
+  /// Adds `by` to the float at `key`, starting from 0 if it is missing,
+  /// returns the new value as stored.
+  async fn incr_by_float(&self, key: &str, by: f64) -> Result<String, RedisError> {
+    let mut write_handle = self.shared_data.write().await;
+    write_handle.expire_if_needed(key);
+
+    let current = match write_handle.dict.get(key) {
+      None => 0.0,
+      Some(value) => {
+        let value = value.as_string().ok_or(RedisError::Type)?;
+        parse_float(&value)?
+      }
+    };
+    let new_value = current + by;
+    if !new_value.is_finite() {
+      return Err(RedisError::Other(
+        "increment would produce NaN or Infinity".to_string(),
+      ));
+    }
+
+    let formatted = add_floats(current, by);
+    write_handle
+      .dict
+      .insert(key.to_string(), Value::string(formatted.as_bytes()));
+    Ok(formatted)
+  }
+
+  async fn hset(
+    &self,
+    key: &str,
+    pairs: &[(&[u8], &[u8])],
+    overwrite: bool,
+  ) -> Result<usize, RedisError> {
+    let mut write_from = self.shared_data.write().await;
+    write_from.expire_if_needed(key);
+
+    let hash = match write_from
+      .dict
+      .entry(key.to_string())
+      .or_insert_with(|| Value::Hash(HashMap::new()))
+    {
+      Value::Hash(hash) => hash,
+      _ => return Err(RedisError::Type),
+    };
+
+    let mut added = 0;
+    for (field, value) in pairs {
+      match hash.entry(field.to_vec()) {
+        Entry::Occupied(mut e) if overwrite => {
+          e.insert(value.to_vec());
+        }
+        Entry::Occupied(_) => {}
+        Entry::Vacant(e) => {
+          e.insert(value.to_vec());
+          added += 1;
+        }
+      }
+    }
+    Ok(added)
+  }
+
+  async fn hmget(&self, key: &str, fields: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>, RedisError> {
+    let read_from = self.read_live(key).await;
+
+    match read_from.hash(key)? {
+      Some(hash) => Ok(fields.iter().map(|f| hash.get(*f).cloned()).collect()),
+      None => Ok(vec![None; fields.len()]),
+    }
+  }
+
+  async fn hlen(&self, key: &str) -> Result<usize, RedisError> {
+    let read_from = self.read_live(key).await;
+
+    Ok(read_from.hash(key)?.map_or(0, HashMap::len))
+  }
+
+  /// Field names of the hash at `key` if `keys`, its values otherwise.
+  async fn hfields(&self, key: &str, keys: bool) -> Result<Vec<Vec<u8>>, RedisError> {
+    let read_from = self.read_live(key).await;
+
+    match read_from.hash(key)? {
+      Some(hash) if keys => Ok(hash.keys().cloned().collect()),
+      Some(hash) => Ok(hash.values().cloned().collect()),
+      None => Ok(Vec::new()),
+    }
+  }
+
+  /// Up to `count` random fields of the hash at `key`, each followed by its
+  /// value if `with_values`. A negative `count` picks exactly that many,
+  /// allowing repeats.
+  async fn hrandfield(
+    &self,
+    key: &str,
+    count: i64,
+    with_values: bool,
+  ) -> Result<Vec<Vec<u8>>, RedisError> {
+    let read_from = self.read_live(key).await;
+    let hash = match read_from.hash(key)? {
+      Some(hash) => hash,
+      None => return Ok(Vec::new()),
+    };
+
+    let mut rng = sync::lock(&self.rng);
+    let picked = if count >= 0 {
+      hash.iter().choose_multiple(&mut *rng, count as usize)
+    } else {
+      let entries = hash.iter().collect::<Vec<_>>();
+      (0..count.unsigned_abs())
+        .filter_map(|_| entries.choose(&mut *rng).copied())
+        .collect()
+    };
+
+    let mut out = Vec::new();
+    for (field, value) in picked {
+      out.push(field.clone());
+      if with_values {
+        out.push(value.clone());
+      }
+    }
+    Ok(out)
+  }
+
+  /// Returns the next cursor (0 once done) and a flat field/value batch.
+  ///
+  /// The cursor is an offset into the hash's iteration order, so fields are
+  /// only guaranteed to be returned once if the hash isn't rehashed meanwhile.
+  async fn hscan(
+    &self,
+    key: &str,
+    cursor: usize,
+    options: &ScanOptions<'_>,
+  ) -> Result<(usize, Vec<Vec<u8>>), RedisError> {
+    let read_from = self.read_live(key).await;
+
+    let hash = match read_from.hash(key)? {
+      Some(hash) => hash,
+      None => return Ok((0, Vec::new())),
+    };
+
+    let count = options.count.unwrap_or(DEFAULT_SCAN_COUNT).max(1);
+    let mut batch = Vec::new();
+    for (field, value) in hash.iter().skip(cursor).take(count) {
+      if options.pattern.is_none_or(|p| glob::matches(p, field)) {
+        batch.push(field.clone());
+        batch.push(value.clone());
+      }
+    }
+
+    let next = cursor + count;
+    Ok((if next >= hash.len() { 0 } else { next }, batch))
+  }
+
+  /// Like `hscan`, a batch of the members of the set at `key`.
+  async fn sscan(
+    &self,
+    key: &str,
+    cursor: usize,
+    options: &ScanOptions<'_>,
+  ) -> Result<(usize, Vec<Vec<u8>>), RedisError> {
+    let read_from = self.read_live(key).await;
+
+    let set = match read_from.set(key)? {
+      Some(set) => set,
+      None => return Ok((0, Vec::new())),
+    };
+
+    let count = options.count.unwrap_or(DEFAULT_SCAN_COUNT).max(1);
+    let batch = set
+      .iter()
+      .skip(cursor)
+      .take(count)
+      .filter(|member| options.pattern.is_none_or(|p| glob::matches(p, member)))
+      .cloned()
+      .collect();
+
+    let next = cursor + count;
+    Ok((if next >= set.len() { 0 } else { next }, batch))
+  }
+
+  /// Like `hscan`, a batch of member and score pairs of the sorted set at
+  /// `key`, in score order.
+  async fn zscan(
+    &self,
+    key: &str,
+    cursor: usize,
+    options: &ScanOptions<'_>,
+  ) -> Result<(usize, Vec<Vec<u8>>), RedisError> {
+    let read_from = self.read_live(key).await;
+
+    let zset = match read_from.zset(key)? {
+      Some(zset) => zset,
+      None => return Ok((0, Vec::new())),
+    };
+
+    let count = options.count.unwrap_or(DEFAULT_SCAN_COUNT).max(1);
+    let mut batch = Vec::new();
+    for (member, score) in zset.iter().skip(cursor).take(count) {
+      if options.pattern.is_none_or(|p| glob::matches(p, member)) {
+        batch.push(member.to_vec());
+        batch.push(format_float(score).into_bytes());
+      }
+    }
+
+    let next = cursor + count;
+    Ok((if next >= zset.len() { 0 } else { next }, batch))
+  }
+
+  /// Names of the live keys matching the glob `pattern`. Every key in the
+  /// dataset is visited under the read lock, SCAN is the way to go over a
+  /// large one without holding up writers.
+  async fn keys(&self, pattern: &str) -> Vec<Vec<u8>> {
+    let read_from = self.shared_data.read().await;
+    read_from
+      .dict
+      .keys()
+      .filter(|key| glob::matches(pattern.as_bytes(), key.as_bytes()) && !read_from.is_expired(key))
+      .map(|key| key.as_bytes().to_vec())
+      .collect()
+  }
+
+  /// Returns the next cursor (0 once done) and a batch of keys. Expired
+  /// keys are left out.
+  ///
+  /// Cursor 0 snapshots the key names and later cursors page through the
+  /// snapshot, so every key existing for the whole scan is returned however
+  /// the dict rehashes meanwhile. Keys deleted since are skipped, ones added
+  /// since aren't returned. A scan whose snapshot was dropped for newer ones
+  /// starts over, returning some keys twice.
+  ///
+  /// Keyspaces past `MAX_SCAN_SNAPSHOT_KEYS` aren't copied, the cursor is
+  /// then an offset into the live dict and keys can be missed if it grows
+  /// meanwhile.
+  async fn scan(
+    &self,
+    cursor: usize,
+    options: &ScanOptions<'_>,
+  ) -> Result<(usize, Vec<Vec<u8>>), RedisError> {
+    let read_from = self.shared_data.read().await;
+    let mut scans = sync::lock(&self.scans);
+
+    let mut id = cursor as u64 >> SCAN_OFFSET_BITS;
+    let mut offset = cursor & ((1 << SCAN_OFFSET_BITS) - 1);
+    if id > scans.last_id {
+      return Err(RedisError::Other("invalid cursor".to_string()));
+    }
+    if id != 0 && !scans.snapshots.contains_key(&id) {
+      debug!("scan {} lost its snapshot, starting over", id);
+      (id, offset) = (0, 0);
+    }
+    let snapshotting = id == 0 && offset == 0 && read_from.dict.len() <= MAX_SCAN_SNAPSHOT_KEYS;
+    let fresh = snapshotting.then(|| read_from.dict.keys().cloned().collect::<Vec<_>>());
+    let count = options.count.unwrap_or(DEFAULT_SCAN_COUNT).max(1);
+    let (page, total) = match fresh.as_ref().or_else(|| scans.snapshots.get(&id)) {
+      Some(keys) => (
+        keys.iter().skip(offset).take(count).collect::<Vec<_>>(),
+        keys.len(),
+      ),
+      None => (
+        read_from.dict.keys().skip(offset).take(count).collect(),
+        read_from.dict.len(),
+      ),
+    };
+
+    let mut batch = Vec::new();
+    for key in page {
+      let Some(value) = read_from.dict.get(key) else {
+        continue;
+      };
+      if options
+        .pattern
+        .is_none_or(|p| glob::matches(p, key.as_bytes()))
+        && options
+          .kind
+          .is_none_or(|kind| kind.eq_ignore_ascii_case(value.type_name()))
+        && !read_from.is_expired(key)
+      {
+        batch.push(key.as_bytes().to_vec());
+      }
+    }
+
+    let next = offset + count;
+    if next >= total {
+      scans.snapshots.remove(&id);
+      return Ok((0, batch));
+    }
+    let id = match fresh {
+      Some(keys) => scans.start(keys),
+      None => id,
+    };
+    Ok(((id << SCAN_OFFSET_BITS) as usize | next, batch))
+  }
+
+  /// The INFO text of `section`, or of every section if `None`.
+  fn info(&self, section: Option<&str>) -> String {
+    let wanted = |name: &str| {
+      section.is_none_or(|s| {
+        s.eq_ignore_ascii_case(name)
+          || s.eq_ignore_ascii_case("all")
+          || s.eq_ignore_ascii_case("default")
+      })
+    };
+
+    let mut info = String::new();
+    if wanted("stats") {
+      info.push_str("# Stats\r\n");
+      info.push_str(&format!(
+        "total_commands_processed:{}\r\n",
+        self.stats.commands_processed.load(Ordering::Relaxed)
+      ));
+      info.push_str(&format!(
+        "instantaneous_ops_per_sec:{}\r\n",
+        self.stats.ops_per_sec.load(Ordering::Relaxed)
+      ));
+    }
+    info
+  }
+
+  /// Journals `cmd`, fsyncing right away under the `always` policy.
+  /// Replayed commands are left out, the journal would otherwise grow by
+  /// its own length on every restart.
+  async fn journal_write<'a>(&self, ctx: ExecContext, cmd: &'a Command<'a>) {
+    if ctx.from_replication {
+      return;
+    }
+    self.journal.write(cmd).await;
+    self.journal_written.fetch_add(1, Ordering::SeqCst);
+    if self.config.fsync == FsyncPolicy::Always {
+      self.fsync_journal().await;
+    }
+  }
+
+  /// Fsyncs the journal and wakes up the WAITAOF callers whose writes made
+  /// it to disk.
+  async fn fsync_journal(&self) {
+    let written = self.journal_written.load(Ordering::SeqCst);
+    self.journal.fsync().await;
+    self.journal_synced.send_if_modified(|synced| {
+      let behind = *synced < written;
+      if behind {
+        *synced = written;
+      }
+      behind
+    });
+  }
+
+  /// Fsyncs the journal every second under the `everysec` policy, returns
+  /// right away under the others.
+  pub async fn fsync_every_second(&self) {
+    if self.config.fsync != FsyncPolicy::EverySec {
+      return;
+    }
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+      interval.tick().await;
+      if *self.journal_synced.borrow() < self.journal_written.load(Ordering::SeqCst) {
+        self.fsync_journal().await;
+      }
+    }
+  }
+
+  /// Waits up to `timeout` milliseconds, forever if 0, for the journal to
+  /// be fsynced past every write made so far. Replies with the number of
+  /// local fsyncs that acknowledged them, this server having no replicas.
+  async fn waitaof(&self, numlocal: usize, timeout: u64) -> Result<RedisValue, RedisError> {
+    if !self.journal.is_enabled() {
+      if numlocal > 0 {
+        return Err(RedisError::Other(
+          "WAITAOF cannot be used when numlocal is set but the journal is disabled".to_string(),
+        ));
+      }
+      return Ok(RedisValue::Integer(0));
+    }
+
+    let written = self.journal_written.load(Ordering::SeqCst);
+    if self.config.fsync == FsyncPolicy::No {
+      self.fsync_journal().await;
+    }
+    let mut synced = self.journal_synced.subscribe();
+    let wait = synced.wait_for(|synced| *synced >= written);
+    let acked = if timeout == 0 {
+      wait.await.is_ok()
+    } else {
+      matches!(
+        tokio::time::timeout(Duration::from_millis(timeout), wait).await,
+        Ok(Ok(_))
+      )
+    };
+    Ok(RedisValue::Integer(acked as i64))
+  }
+
+  /// Completes once SHUTDOWN is executed.
+  pub async fn shutdown_requested(&self) {
+    self.shutdown.notified().await
+  }
+
+  async fn shutdown(&self, nosave: bool) -> Result<(), RedisError> {
+    if !self.config.allow_shutdown {
+      return Err(RedisError::Other(
+        "SHUTDOWN is disabled, start the server with --allow-shutdown".to_string(),
+      ));
+    }
+    if !nosave && self.config.snapshot_path.is_some() {
+      self.save().await?;
+    }
+    self.journal.flush().await;
+
+    info!("shutting down");
+    self.shutdown.notify_one();
+    Ok(())
+  }
+
+  /// Writes every live key to the snapshot path as the RESP command that
+  /// recreates it. The file is written aside and renamed over the old one.
+  async fn save(&self) -> Result<(), RedisError> {
+    let path = match &self.config.snapshot_path {
+      Some(path) => path,
+      None => return Err(RedisError::Other("no snapshot path configured".to_string())),
+    };
+
+    let commands = self.shared_data.read().await.commands();
+    let mut out = Vec::new();
+    for args in commands {
+      let args = args.iter().map(|arg| arg.as_slice()).collect::<Vec<_>>();
+      out.extend(cmd::to_resp(&args));
+    }
+
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, out).await?;
+    fs::rename(&tmp_path, path).await?;
+    self.last_save.store(now_millis() / 1000, Ordering::Relaxed);
+    info!("saved snapshot to {}", path.display());
+    Ok(())
+  }
+
+  /// BGREWRITEAOF, replaces the journal with one command per live key.
+  /// Reads carry on meanwhile, writes wait until the new journal is in
+  /// place.
+  pub async fn rewrite_journal(&self) -> Result<(), RedisError> {
+    let _rewrite_guard = self.rewrite_lock.write().await;
+    let commands = self.shared_data.read().await.commands();
+    let commands = commands
+      .iter()
+      .map(|args| parse(args))
+      .collect::<Result<Vec<_>, _>>()?;
+    self.journal.rewrite(commands).await
+  }
+
+  async fn debug(&self, subcommand: &str, args: &[&str]) -> Result<RedisValue, RedisError> {
+    let invalid = || RedisError::Other(format!("invalid arguments for DEBUG {subcommand}"));
+
+    match (subcommand.to_ascii_uppercase().as_str(), args) {
+      ("SLEEP", [seconds]) => {
+        let duration = seconds
+          .parse::<f64>()
+          .ok()
+          .and_then(|s| Duration::try_from_secs_f64(s).ok())
+          .ok_or_else(invalid)?;
+        tokio::time::sleep(duration).await;
+        Ok(RedisValue::Ok)
+      }
+      #[cfg(test)]
+      ("PANIC", []) => panic!("DEBUG PANIC"),
+      ("OBJECT", [key]) => {
+        let read_from = self.read_live(key).await;
+        let value = read_from
+          .dict
+          .get(*key)
+          .ok_or_else(|| RedisError::Other("no such key".to_string()))?;
+        let mut info = format!(
+          "Value at:0 refcount:{} encoding:{} serializedlength:{}",
+          value.refcount(),
+          value.encoding(),
+          value.mem_size()
+        );
+        // not in Redis, lets tests follow a list across encodings
+        if let Value::List(list) = value {
+          info.push_str(&format!(" length:{}", list.len()));
+        }
+        Ok(RedisValue::from(info.into_bytes()))
+      }
+      ("RELOAD", []) => {
+        self.reload().await?;
+        Ok(RedisValue::Ok)
+      }
+      ("SET-ACTIVE-EXPIRE", [enabled]) => {
+        let enabled = match *enabled {
+          "0" => false,
+          "1" => true,
+          _ => return Err(invalid()),
+        };
+        self.active_expire.store(enabled, Ordering::Relaxed);
+        Ok(RedisValue::Ok)
+      }
+      ("QUICKLIST-PACKED-THRESHOLD", [threshold]) => {
+        let threshold = threshold.parse::<usize>().map_err(|_| invalid())?;
+        self
+          .list_packed_threshold
+          .store(threshold, Ordering::Relaxed);
+        Ok(RedisValue::Ok)
+      }
+      // there is no replication to give a new id to
+      ("CHANGE-REPL-ID", []) => Ok(RedisValue::Ok),
+      ("STRINGMATCH-LEN", [pattern, string]) => Ok(RedisValue::Integer(glob::matches(
+        pattern.as_bytes(),
+        string.as_bytes(),
+      ) as i64)),
+      (
+        "SLEEP"
+        | "OBJECT"
+        | "RELOAD"
+        | "SET-ACTIVE-EXPIRE"
+        | "QUICKLIST-PACKED-THRESHOLD"
+        | "STRINGMATCH-LEN",
+        _,
+      ) => Err(invalid()),
+      _ => Err(RedisError::Other(
+        "DEBUG subcommand not supported".to_string(),
+      )),
+    }
+  }
+
+  /// Saves, empties the dataset and loads the snapshot back, a change in
+  /// the data afterwards points at a bug in the snapshot format.
+  async fn reload(&self) -> Result<(), RedisError> {
+    self.save().await?;
+    {
+      let mut s_data = self.shared_data.write().await;
+      s_data.dict.clear();
+      s_data.expires.clear();
+      s_data.ttl_heap.clear();
+      sync::get_mut(&mut s_data.lfu).clear();
+    }
+    Box::pin(self.load()).await?;
+    Ok(())
+  }
+
+  /// Replays the snapshot, if one exists, returns the number of commands.
+  pub async fn load(&self) -> Result<usize, RedisError> {
+    let path = match &self.config.snapshot_path {
+      Some(path) if path.exists() => path,
+      _ => return Ok(0),
+    };
+
+    let mut read = BufReader::new(File::open(path).await?);
+    let source = path.display().to_string();
+    let mut progress = LoadProgress::new(&source, self.config.load_timeout);
+    while let Some(args) = read_cmd(&mut read, self.config.max_inline_len).await? {
+      if let Err(e) = self.apply(&parse(&args)?, ExecContext::REPLAY).await {
+        warn!("skipping a snapshot command that failed: {}", e);
+      }
+      progress.tick()?;
+    }
+    info!("loaded {} keys from {}", progress.count, source);
+    Ok(progress.count)
+  }
+
+  /// Replays a journal written by `journal::Json`, returns the number of
+  /// commands.
+  ///
+  /// An unterminated last line that doesn't parse was cut short by a crash
+  /// mid-write, it is dropped with a warning. A bad line anywhere else
+  /// fails the load since skipping it would silently lose data. A command
+  /// that fails is logged and skipped, journals written before only
+  /// successful commands were kept can have those.
+  pub async fn replay_json(&self, journal: &str) -> Result<usize, RedisError> {
+    let mut progress = LoadProgress::new("the journal", self.config.load_timeout);
+    let last = journal.lines().count();
+    for (n, line) in (1..).zip(journal.lines()) {
+      if line.trim().is_empty() {
+        continue;
+      }
+      let args = match journal::from_json(line) {
+        Ok(args) => args,
+        Err(_) if n == last && !journal.ends_with('\n') => {
+          warn!("dropping incomplete last journal line {}", n);
+          break;
+        }
+        Err(e) => {
+          return Err(RedisError::Other(format!(
+            "corrupt journal at line {n}: {e}"
+          )))
+        }
+      };
+      if let Err(e) = self.apply(&parse(&args)?, ExecContext::REPLAY).await {
+        warn!("skipping journal line {} that failed: {}", n, e);
+      }
+      progress.tick()?;
+    }
+    Ok(progress.count)
+  }
+
+  fn kill_clients(&self, filter: &KillFilter) -> usize {
+    let clients = sync::lock(&self.clients);
+    let mut killed = 0;
+    for (id, client) in clients.iter() {
+      let matched = match filter {
+        KillFilter::Id(target) => id == target,
+        KillFilter::Addr(addr) => client.addr == *addr,
+      };
+      if matched {
+        info!("killing client id={} addr={}", id, client.addr);
+        client.kill.notify_one();
+        killed += 1;
+      }
+    }
+    killed
+  }
+
+  async fn memory_usage(&self, key: &str) -> Option<usize> {
+    let read_from = self.read_live(key).await;
+    let value = read_from.dict.get(key)?;
+    Some(key.len() + value.mem_size())
+  }
+
+  async fn sadd(&self, key: &str, members: &[&[u8]]) -> Result<usize, RedisError> {
+    let mut write_from = self.shared_data.write().await;
+    write_from.expire_if_needed(key);
+
+    let set = match write_from
+      .dict
+      .entry(key.to_string())
+      .or_insert_with(|| Value::Set(HashSet::new()))
+    {
+      Value::Set(set) => set,
+      _ => return Err(RedisError::Type),
+    };
+    Ok(members.iter().filter(|m| set.insert(m.to_vec())).count())
+  }
+
+  /// Sets the scores of `pairs` in the sorted set at `key`, returns how
+  /// many members were new.
+  async fn zadd(&self, key: &str, pairs: &[(Score, &[u8])]) -> Result<usize, RedisError> {
+    if pairs.is_empty() {
+      return Err(RedisError::Other(
+        "wrong number of arguments for 'zadd' command".to_string(),
+      ));
+    }
+    let mut write_from = self.shared_data.write().await;
+    write_from.expire_if_needed(key);
+
+    let zset = match write_from
+      .dict
+      .entry(key.to_string())
+      .or_insert_with(|| Value::ZSet(SortedSet::default()))
+    {
+      Value::ZSet(zset) => zset,
+      _ => return Err(RedisError::Type),
+    };
+    Ok(
+      pairs
+        .iter()
+        .filter(|(score, m)| zset.insert(m, score.0))
+        .count(),
+    )
+  }
+
+  /// Size of the intersection of `keys`, counted by probing the smallest set
+  /// against the others. Stops at `limit` unless it is 0.
+  async fn sintercard(&self, keys: &[&str], limit: Option<usize>) -> Result<usize, RedisError> {
+    let read_from = self.shared_data.read().await;
+
+    let mut sets = Vec::with_capacity(keys.len());
+    for key in keys {
+      if read_from.is_expired(key) {
+        return Ok(0);
+      }
+      match read_from.set(key)? {
+        Some(set) => sets.push(set),
+        None => return Ok(0),
+      }
+    }
+    sets.sort_by_key(|set| set.len());
+    let (smallest, others) = match sets.split_first() {
+      Some(split) => split,
+      None => return Ok(0),
+    };
+
+    let limit = limit.filter(|&limit| limit > 0).unwrap_or(usize::MAX);
+    let count = smallest
+      .iter()
+      .filter(|m| others.iter().all(|set| set.contains(*m)))
+      .take(limit)
+      .count();
+    Ok(count)
+  }
+
+  async fn memory_stats(&self) -> MemoryStats {
+    let read_from = self.shared_data.read().await;
+    let dataset_bytes = read_from
+      .dict
+      .iter()
+      .map(|(key, value)| key.len() + value.mem_size())
+      .sum();
+    let expires_bytes: usize = read_from
+      .expires
+      .keys()
+      .map(|key| size_of::<String>() + size_of::<u64>() + key.len())
+      .sum();
+    let overhead_bytes = read_from.dict.capacity() * size_of::<String>()
+      + expires_bytes
+      + read_from.ttl_heap.len() * size_of::<(u64, String)>();
+
+    MemoryStats {
+      keys: read_from.dict.len(),
+      dataset_bytes,
+      overhead_bytes,
+    }
+  }
+
+  async fn refcount(&self, key: &str) -> Option<usize> {
+    let read_from = self.read_live(key).await;
+    read_from.dict.get(key).map(Value::refcount)
+  }
+
+  async fn encoding(&self, key: &str) -> Option<&'static str> {
+    let read_from = self.read_live(key).await;
+    read_from.dict.get(key).map(Value::encoding)
+  }
+
+  async fn role(&self) -> RedisValue {
+    match &*self.replica_of.read().await {
+      // no replicas can connect yet, so the offset never moves
+      None => RedisValue::Nested(vec![
+        RedisValue::from("master"),
+        RedisValue::Integer(0),
+        RedisValue::Nested(Vec::new()),
+      ]),
+      Some((host, port)) => RedisValue::Nested(vec![
+        RedisValue::from("slave"),
+        RedisValue::from(host.clone().into_bytes()),
+        RedisValue::Integer(*port as i64),
+        RedisValue::from("connect"),
+        RedisValue::Integer(-1),
+      ]),
+    }
+  }
+
+  async fn replica_of(&self, host: &str, port: &str) -> Result<(), RedisError> {
+    let mut replica_of = self.replica_of.write().await;
+    if host.eq_ignore_ascii_case("no") && port.eq_ignore_ascii_case("one") {
+      info!("promoted to master");
+      *replica_of = None;
+      self.set_read_only(false);
+    } else {
+      let port = port.parse::<u16>()?;
+      info!("replicating from {host}:{port}");
+      *replica_of = Some((host.to_string(), port));
+      self.set_read_only(true);
+    }
+    Ok(())
+  }
+}
+
+fn spawn_ttl_heap_cleaner(
+  shared_data: Arc<RwLock<SharedData>>,
+  enabled: Arc<AtomicBool>,
+  batch: usize,
+  every: Duration,
+) -> JoinHandle<()> {
+  let batch = batch.max(1);
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(every);
+
+    loop {
+      interval.tick().await;
+      if !enabled.load(Ordering::Relaxed) {
+        continue;
+      }
+
+      // like Redis' active expire cycle, a burst of deadlines is worked off
+      // in bounded batches rather than under one long held lock
+      while shared_data.write().await.reap_expired(now_millis(), batch) == batch {
+        tokio::task::yield_now().await;
+      }
+    }
+  })
+}
+
+/// A human-readable take on `stats` for MEMORY DOCTOR.
+fn memory_doctor(stats: &MemoryStats) -> String {
+  if stats.keys == 0 {
+    return "The instance is empty, there is nothing to report.".to_string();
+  }
+  let mut report = format!(
+    "{} keys use {} bytes, with {} bytes of overhead.",
+    stats.keys, stats.dataset_bytes, stats.overhead_bytes
+  );
+  if stats.overhead_bytes > stats.dataset_bytes {
+    report.push_str(
+      " Overhead outweighs the data itself, many small keys could be grouped into hashes.",
+    );
+  } else {
+    report.push_str(" No memory issues detected.");
+  }
+  report
+}
+
+/// Samples the command counter every second into `ops_per_sec`.
+fn spawn_ops_sampler(stats: Arc<Stats>) {
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    let mut last = 0;
+
+    loop {
+      interval.tick().await;
+
+      let processed = stats.commands_processed.load(Ordering::Relaxed);
+      stats.ops_per_sec.store(processed - last, Ordering::Relaxed);
+      last = processed;
+    }
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::atomic::{AtomicBool, Ordering};
+  use std::sync::Arc;
+  use std::time::Duration;
+
+  use crate::cmd::{
+    Command, Expiry, KillFilter, ScanOptions, SetCondition, SetOptions, SortOptions,
+  };
+  use crate::err::RedisError;
+  use crate::journal::{Disabled, Json, Writer};
+  use crate::list::List;
+  use crate::value::RedisValue;
+  use crate::zset::{LexBound, Score, ScoreBound};
+
+  /// Keeps the records mentioning `MARKER`, whichever test logged them.
+  struct CapturingLogger(std::sync::Mutex<Vec<(log::Level, String)>>);
+
+  static LOGGER: CapturingLogger = CapturingLogger(std::sync::Mutex::new(Vec::new()));
+  static MARKER: &str = "log-level-key";
+
+  impl log::Log for CapturingLogger {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+      true
+    }
+
+    fn log(&self, record: &log::Record) {
+      let line = record.args().to_string();
+      if line.contains(MARKER) {
+        self.0.lock().unwrap().push((record.level(), line));
+      }
+    }
+
+    fn flush(&self) {}
+  }
+
+  #[tokio::test]
+  async fn test_command_log_level() {
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+    let config = super::Config {
+      command_log_format: super::CommandLogFormat::Json,
+      ..super::Config::default()
+    };
+    let redis = super::Redis::with_config(Disabled {}, config).await;
+    redis.exec(&Command::Lpop(MARKER, Some(1))).await.unwrap();
+    redis.exec(&Command::Del(vec![MARKER])).await.unwrap();
+
+    let records = LOGGER.0.lock().unwrap();
+    // nothing shows at --log-level warn, or even info
+    assert!(records.iter().all(|(level, _)| *level >= log::Level::Debug));
+    let lines = records
+      .iter()
+      .map(|(_, line)| line.as_str())
+      .collect::<Vec<_>>();
+    assert!(lines.contains(&r#"{"command":"lpop","keys":["log-level-key"]}"#));
+    assert!(lines.contains(&r#"{"command":"del","keys":["log-level-key"]}"#));
+  }
+
+  #[tokio::test]
+  async fn test_redis_set() {
+    let redis = super::Redis::new(Disabled {}).await;
+    for i in 0..100 {
+      redis
+        .set(
+          format!("key_{}", i).as_str(),
+          format!("value_{}", i).as_bytes(),
+        )
+        .await
+    }
+    for i in 0..100 {
+      let key = format!("key_{}", i);
+      let value = Arc::new(format!("value_{}", i).as_bytes().to_vec());
+
+      assert_eq!(redis.get(&key).await.unwrap(), Some(value));
+    }
+  }
+
+  #[tokio::test]
+  async fn test_replica_rejects_writes() {
+    let redis = super::Redis::new(Disabled {}).await;
+    let set = Command::Set("key", b"value");
+
+    redis
+      .exec(&Command::ReplicaOf("127.0.0.1", "6379"))
+      .await
+      .unwrap();
+    assert!(matches!(redis.exec(&set).await, Err(RedisError::ReadOnly)));
+
+    redis.exec(&Command::ReplicaOf("NO", "ONE")).await.unwrap();
+    assert!(matches!(redis.exec(&set).await, Ok(RedisValue::Ok)));
+  }
+
+  #[tokio::test]
+  async fn test_read_only_allows_reads() {
+    let redis = super::Redis::new(Disabled {}).await;
+    redis.set("key", b"value").await;
+    redis.set_read_only(true);
+
+    assert!(matches!(
+      redis.exec(&Command::Get("key")).await,
+      Ok(RedisValue::InlineString(v)) if v.as_slice() == b"value"
+    ));
+    assert!(matches!(
+      redis.exec(&Command::Set("key", b"other")).await,
+      Err(RedisError::ReadOnly)
+    ));
+    assert!(matches!(
+      redis.exec(&Command::Del(vec!["key"])).await,
+      Err(RedisError::ReadOnly)
+    ));
+  }
+
+  #[tokio::test]
+  async fn test_command_list() {
+    let redis = super::Redis::new(Disabled {}).await;
+    let Ok(RedisValue::Array(names)) = redis.exec(&Command::ListCommands).await else {
+      panic!("COMMAND LIST didn't reply with an array");
+    };
+    for name in ["get", "set", "lpush"] {
+      assert!(names.iter().any(|n| n.as_deref() == Some(name.as_bytes())));
+    }
+    assert!(matches!(
+      redis.exec(&Command::CountCommands).await,
+      Ok(RedisValue::Integer(count)) if count as usize == names.len()
+    ));
+  }
+
+  #[tokio::test]
+  async fn test_dbsize() {
+    let redis = super::Redis::new(Disabled {}).await;
+    let keys: Vec<String> = (0..1000).map(|i| format!("key:{i}")).collect();
+    for key in &keys {
+      redis.exec(&Command::Set(key, b"v")).await.unwrap();
+    }
+
+    assert!(matches!(
+      redis.exec(&Command::DbSize).await,
+      Ok(RedisValue::Integer(1000))
+    ));
+  }
+
+  #[tokio::test]
+  async fn test_dbsize_across_encodings() {
+    let redis = super::Redis::new(Disabled {}).await;
+    let dbsize = || async {
+      match redis.exec(&Command::DbSize).await {
+        Ok(RedisValue::Integer(n)) => n,
+        _ => panic!("DBSIZE didn't reply with an integer"),
+      }
+    };
+
+    redis.exec(&Command::Set("n", b"10")).await.unwrap();
+    assert!(matches!(
+      redis.shared_data.read().await.dict["n"],
+      super::Value::Int(10)
+    ));
+    assert_eq!(dbsize().await, 1);
+
+    // appending makes it raw in place
+    redis.exec(&Command::Append("n", b"x")).await.unwrap();
+    assert!(matches!(
+      redis.shared_data.read().await.dict["n"],
+      super::Value::Raw(_)
+    ));
+    assert_eq!(dbsize().await, 1);
+
+    redis.exec(&Command::Incr("counter")).await.unwrap();
+    redis.exec(&Command::Incr("counter")).await.unwrap();
+    assert_eq!(dbsize().await, 2);
+
+    // back to an int through SET, INCR then replaces it in place
+    redis.exec(&Command::Set("n", b"5")).await.unwrap();
+    redis.exec(&Command::Incr("n")).await.unwrap();
+    assert!(matches!(
+      redis.shared_data.read().await.dict["n"],
+      super::Value::Int(6)
+    ));
+    assert_eq!(dbsize().await, 2);
+
+    // a failed INCR of a raw string leaves it be
+    redis.exec(&Command::Set("raw", b"abc")).await.unwrap();
+    assert!(redis.exec(&Command::Incr("raw")).await.is_err());
+    assert_eq!(dbsize().await, 3);
+  }
+
+  #[tokio::test]
+  async fn test_dbsize_skips_expired() {
+    let config = super::Config {
+      active_expire: false,
+      ..super::Config::default()
+    };
+    let redis = super::Redis::with_config(Disabled {}, config).await;
+    redis.exec(&Command::Set("live", b"v")).await.unwrap();
+    redis
+      .exec(&Command::SetEx("volatile", b"v", 100))
+      .await
+      .unwrap();
+    for key in ["a", "b", "c"] {
+      redis.exec(&Command::SetEx(key, b"v", 1)).await.unwrap();
+      // past its deadline, nothing has removed it yet
+      let past = super::now_millis() - 1;
+      redis.shared_data.write().await.set_deadline(key, past);
+    }
+
+    assert!(matches!(
+      redis.exec(&Command::DbSize).await,
+      Ok(RedisValue::Integer(2))
+    ));
+    assert_eq!(redis.shared_data.read().await.dict.len(), 5);
+  }
+
+  #[test]
+  fn test_typed_lookups() {
+    let mut data = super::SharedData {
+      dict: std::collections::HashMap::new(),
+      expires: std::collections::HashMap::new(),
+      ttl_heap: std::collections::BTreeSet::new(),
+      lfu: std::sync::Mutex::new(std::collections::HashMap::new()),
+    };
+    data
+      .dict
+      .insert("string".to_string(), super::Value::string(b"value"));
+    data
+      .dict
+      .insert("list".to_string(), super::Value::List(List::new()));
+
+    assert!(matches!(data.list("string"), Err(RedisError::Type)));
+    assert!(matches!(data.list_mut("string"), Err(RedisError::Type)));
+    assert!(matches!(data.hash("list"), Err(RedisError::Type)));
+    assert!(matches!(data.set("list"), Err(RedisError::Type)));
+    assert!(matches!(data.list("list"), Ok(Some(_))));
+    assert!(matches!(data.list("missing"), Ok(None)));
+  }
+
+  #[tokio::test]
+  async fn test_flushall() {
+    let redis = super::Redis::new(Disabled {}).await;
+    redis.exec(&Command::Set("a", b"1")).await.unwrap();
+    redis.exec(&Command::SetEx("b", b"2", 100)).await.unwrap();
+
+    assert!(matches!(
+      redis.exec(&Command::FlushAll).await,
+      Ok(RedisValue::Ok)
+    ));
+    assert_eq!(redis.keys_count().await, 0);
+  }
+
+  #[tokio::test]
+  async fn test_lolwut() {
+    let redis = super::Redis::new(Disabled {}).await;
+
+    assert!(matches!(
+      redis.exec(&Command::Lolwut).await,
+      Ok(RedisValue::SimpleString(v)) if v.starts_with(b"Reddis ver.")
+    ));
+  }
+
+  #[tokio::test]
+  async fn test_cluster_stubs() {
+    let redis = super::Redis::new(Disabled {}).await;
+
+    assert!(matches!(
+      redis.exec(&Command::ClusterInfo).await,
+      Ok(RedisValue::Verbatim(_, info)) if info.starts_with(b"cluster_enabled:0\r\n")
+    ));
+    for cmd in [Command::ClusterSlots, Command::ClusterShards] {
+      assert!(matches!(
+        redis.exec(&cmd).await,
+        Ok(RedisValue::Nested(v)) if v.is_empty()
+      ));
+    }
+  }
+
+  #[tokio::test]
+  async fn test_get_expires_lazily() {
+    let redis = super::Redis::new(Disabled {}).await;
+    redis.setex("key", b"value", 1).await;
+    // keep the cleaner away from the key
+    redis.shared_data.write().await.ttl_heap.clear();
+
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+
+    assert_eq!(redis.get("key").await.unwrap(), None);
+    assert!(!redis.shared_data.read().await.dict.contains_key("key"));
+  }
+
+  #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+  async fn test_multi_key_opposite_orders() {
+    let redis = Arc::new(super::Redis::new(Disabled {}).await);
+    let mut tasks = Vec::new();
+    for keys in [vec!["a", "b"], vec!["b", "a"]] {
+      let redis = redis.clone();
+      tasks.push(tokio::spawn(async move {
+        for _ in 0..1_000 {
+          for key in &keys {
+            redis.exec(&Command::Sadd(key, vec![b"m"])).await.unwrap();
+          }
+          redis
+            .exec(&Command::SInterCard(keys.clone(), None))
+            .await
+            .unwrap();
+          redis.exec(&Command::Del(keys.clone())).await.unwrap();
+        }
+      }));
+    }
+
+    let all = async {
+      for task in tasks {
+        task.await.unwrap();
+      }
+    };
+    assert!(tokio::time::timeout(Duration::from_secs(10), all)
+      .await
+      .is_ok());
+  }
+
+  #[tokio::test]
+  async fn test_concurrent_setex_single_deadline() {
+    let redis = Arc::new(super::Redis::new(Disabled {}).await);
+    let mut tasks = Vec::new();
+    for ttl in 1..=64 {
+      let redis = redis.clone();
+      tasks.push(tokio::spawn(async move {
+        redis
+          .exec(&Command::SetEx("key", b"v", 100 + ttl))
+          .await
+          .unwrap();
+      }));
+    }
+    for task in tasks {
+      task.await.unwrap();
+    }
+    redis
+      .exec(&Command::GetEx("key", Some(Expiry::Px(50_000))))
+      .await
+      .unwrap();
+
+    let data = redis.shared_data.read().await;
+    let at = data.expires["key"];
+    assert_eq!(
+      data.ttl_heap.iter().collect::<Vec<_>>(),
+      vec![&(at, "key".to_string())]
+    );
+    drop(data);
+
+    redis
+      .exec(&Command::GetEx("key", Some(Expiry::Persist)))
+      .await
+      .unwrap();
+    assert!(redis.shared_data.read().await.ttl_heap.is_empty());
+  }
+
+  #[tokio::test]
+  async fn test_no_active_expire() {
+    let config = super::Config {
+      active_expire: false,
+      ..super::Config::default()
+    };
+    let redis = super::Redis::with_config(Disabled {}, config).await;
+    assert!(redis.ttl_cleaner.is_none());
+    assert!(redis.is_healthy());
+
+    redis.exec(&Command::SetEx("key", b"v", 1)).await.unwrap();
+    // past its deadline, only a lazy expiry can remove it now
+    let past = super::now_millis() - 1;
+    redis.shared_data.write().await.set_deadline("key", past);
+    assert!(matches!(
+      redis.exec(&Command::Get("key")).await,
+      Ok(RedisValue::NullBulk)
+    ));
+    assert!(redis.shared_data.read().await.ttl_heap.is_empty());
+  }
+
+  #[tokio::test]
+  async fn test_reap_expired_in_batches() {
+    let config = super::Config {
+      active_expire: false,
+      ..super::Config::default()
+    };
+    let redis = super::Redis::with_config(Disabled {}, config).await;
+    let past = super::now_millis() - 1;
+    for i in 0..1000 {
+      let key = format!("key{i}");
+      redis.set(&key, b"v").await;
+      redis.shared_data.write().await.set_deadline(&key, past);
+    }
+    redis.set("live", b"v").await;
+
+    let mut data = redis.shared_data.write().await;
+    let now = super::now_millis();
+    for _ in 0..10 {
+      assert_eq!(data.reap_expired(now, 100), 100);
+    }
+    assert_eq!(data.reap_expired(now, 100), 0);
+    assert_eq!(data.dict.len(), 1);
+  }
+
+  #[tokio::test]
+  async fn test_cleaner_works_off_a_burst() {
+    let config = super::Config {
+      active_expire_batch: 10,
+      active_expire_interval: Duration::from_millis(10),
+      ..super::Config::default()
+    };
+    let redis = super::Redis::with_config(Disabled {}, config).await;
+    {
+      let mut data = redis.shared_data.write().await;
+      let past = super::now_millis() - 1;
+      for i in 0..1000 {
+        let key = format!("key{i}");
+        data.dict.insert(key.clone(), super::Value::string(b"v"));
+        data.set_deadline(&key, past);
+      }
+    }
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+      while !redis.shared_data.read().await.dict.is_empty() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+      }
+    })
+    .await
+    .unwrap();
+    assert!(redis.shared_data.read().await.ttl_heap.is_empty());
+  }
+
+  #[tokio::test]
+  async fn test_del_expired() {
+    let redis = super::Redis::new(Disabled {}).await;
+    redis.exec(&Command::SetEx("gone", b"v", 1)).await.unwrap();
+    redis.exec(&Command::Set("live", b"v")).await.unwrap();
+    {
+      // past its deadline but not cleaned yet
+      let mut data = redis.shared_data.write().await;
+      data.ttl_heap.clear();
+      data
+        .expires
+        .insert("gone".to_string(), super::now_millis() - 1);
+    }
+
+    assert!(matches!(
+      redis.exec(&Command::Del(vec!["gone", "live"])).await,
+      Ok(RedisValue::Integer(1))
+    ));
+    assert!(!redis.shared_data.read().await.dict.contains_key("gone"));
+  }
+
+  #[tokio::test]
+  async fn test_mget() {
+    let redis = super::Redis::new(Disabled {}).await;
+    redis.set("a", b"1").await;
+    redis.set("b", b"two").await;
+    redis.push("list", &vec![b"x"], true, true).await.unwrap();
+
+    assert_eq!(
+      redis.mget(&["a", "missing", "list", "b"]).await,
+      vec![
+        Some(Arc::new(b"1".to_vec())),
+        None,
+        None,
+        Some(Arc::new(b"two".to_vec()))
+      ]
+    );
+  }
+
+  #[tokio::test]
+  async fn test_copy() {
+    let redis = super::Redis::new(Disabled {}).await;
+    let elements = (0..100_000)
+      .map(|i| i.to_string().into_bytes())
+      .collect::<Vec<_>>();
+    let values = elements.iter().map(Vec::as_slice).collect::<Vec<_>>();
+    redis.push("list", &values, true, false).await.unwrap();
+
+    let copy = |source, destination, replace| Command::Copy(source, destination, replace);
+    assert!(matches!(
+      redis.exec(&copy("list", "clone", false)).await,
+      Ok(RedisValue::Integer(1))
+    ));
+    redis.exec(&Command::Lpop("list", Some(10))).await.unwrap();
+    assert_eq!(redis.llen("list").await.unwrap(), 99_990);
+    assert_eq!(redis.llen("clone").await.unwrap(), 100_000);
+
+    redis.set("string", b"v").await;
+    assert!(matches!(
+      redis.exec(&copy("string", "clone", false)).await,
+      Ok(RedisValue::Integer(0))
+    ));
+    assert!(matches!(
+      redis.exec(&copy("string", "clone", true)).await,
+      Ok(RedisValue::Integer(1))
+    ));
+    assert_eq!(*redis.get("clone").await.unwrap().unwrap(), b"v");
+
+    assert!(matches!(
+      redis.exec(&copy("missing", "clone", true)).await,
+      Ok(RedisValue::Integer(0))
+    ));
+    assert!(redis.exec(&copy("list", "list", true)).await.is_err());
+
+    redis.setex("volatile", b"v", 100).await;
+    redis
+      .exec(&copy("volatile", "copied", false))
+      .await
+      .unwrap();
+    let data = redis.shared_data.read().await;
+    assert_eq!(data.expires.get("copied"), data.expires.get("volatile"));
+  }
+
+  #[tokio::test]
+  async fn test_hmget_missing_field() {
+    let redis = super::Redis::new(Disabled {}).await;
+    redis.hset("h", &[(b"a", b"1")], true).await.unwrap();
+
+    assert_eq!(
+      redis.hmget("h", &[b"a", b"b"]).await.unwrap(),
+      vec![Some(b"1".to_vec()), None]
+    );
+    assert_eq!(redis.hmget("missing", &[b"a"]).await.unwrap(), vec![None]);
+  }
+
+  #[tokio::test]
+  async fn test_hsetnx_collision() {
+    let redis = super::Redis::new(Disabled {}).await;
+
+    assert_eq!(redis.hset("h", &[(b"a", b"1")], false).await.unwrap(), 1);
+    assert_eq!(redis.hset("h", &[(b"a", b"2")], false).await.unwrap(), 0);
+    assert_eq!(
+      redis.hmget("h", &[b"a"]).await.unwrap(),
+      vec![Some(b"1".to_vec())]
+    );
+    assert_eq!(redis.hlen("h").await.unwrap(), 1);
+  }
+
+  #[tokio::test]
+  async fn test_hkeys_hvals() {
+    let redis = super::Redis::new(Disabled {}).await;
+    let pairs: [(&[u8], &[u8]); 3] = [(b"a", b"1"), (b"b", b"2"), (b"c", b"3")];
+    redis.hset("h", &pairs, true).await.unwrap();
+
+    let mut keys = redis.hfields("h", true).await.unwrap();
+    keys.sort();
+    assert_eq!(keys, vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]);
+
+    let mut vals = redis.hfields("h", false).await.unwrap();
+    vals.sort();
+    assert_eq!(vals, vec![b"1".to_vec(), b"2".to_vec(), b"3".to_vec()]);
+
+    assert_eq!(
+      reply_bytes(&redis, &Command::Hkeys("missing")).await,
+      b"*0\r\n"
+    );
+    assert_eq!(
+      reply_bytes(&redis, &Command::Hvals("missing")).await,
+      b"*0\r\n"
+    );
+    redis.hset("one", &[(b"f", b"\xff")], true).await.unwrap();
+    assert_eq!(
+      reply_bytes(&redis, &Command::Hvals("one")).await,
+      b"*1\r\n$1\r\n\xff\r\n"
+    );
+  }
+
+  #[tokio::test]
+  async fn test_hash_wrong_type() {
+    let redis = super::Redis::new(Disabled {}).await;
+    redis.set("key", b"value").await;
+
+    assert!(matches!(
+      redis.hset("key", &[(b"a", b"1")], true).await,
+      Err(RedisError::Type)
+    ));
+    assert!(matches!(redis.hlen("key").await, Err(RedisError::Type)));
+  }
+
+  #[tokio::test]
+  async fn test_hscan_visits_every_field_once() {
+    let redis = super::Redis::new(Disabled {}).await;
+    for i in 0..25 {
+      let field = format!("field_{}", i);
+      redis
+        .hset("h", &[(field.as_bytes(), b"v")], true)
+        .await
+        .unwrap();
+    }
+
+    let options = ScanOptions {
+      pattern: None,
+      count: Some(10),
+      kind: None,
+    };
+    let mut fields = Vec::new();
+    let mut cursor = 0;
+    loop {
+      let (next, batch) = redis.hscan("h", cursor, &options).await.unwrap();
+      fields.extend(batch.chunks(2).map(|pair| pair[0].clone()));
+      if next == 0 {
+        break;
+      }
+      cursor = next;
+    }
+
+    fields.sort();
+    let mut expected = (0..25)
+      .map(|i| format!("field_{}", i).into_bytes())
+      .collect::<Vec<_>>();
+    expected.sort();
+    assert_eq!(fields, expected);
+  }
+
+  #[tokio::test]
+  async fn test_hscan_match() {
+    let redis = super::Redis::new(Disabled {}).await;
+    let pairs: [(&[u8], &[u8]); 3] = [(b"a1", b"1"), (b"a2", b"2"), (b"b1", b"3")];
+    redis.hset("h", &pairs, true).await.unwrap();
+
+    let options = ScanOptions {
+      pattern: Some(b"a*"),
+      count: Some(100),
+      kind: None,
+    };
+    let (cursor, batch) = redis.hscan("h", 0, &options).await.unwrap();
+    assert_eq!(cursor, 0);
+    assert_eq!(batch.len(), 4);
+    assert!(batch.chunks(2).all(|pair| pair[0].starts_with(b"a")));
+  }
+
+  #[tokio::test]
+  async fn test_sscan_zscan_visit_every_member_once() {
+    let redis = super::Redis::new(Disabled {}).await;
+    let members = (0..25)
+      .map(|i| format!("member_{}", i).into_bytes())
+      .collect::<Vec<_>>();
+    let slices = members.iter().map(Vec::as_slice).collect::<Vec<_>>();
+    redis.sadd("s", &slices).await.unwrap();
+    let pairs = slices
+      .iter()
+      .enumerate()
+      .map(|(i, member)| (Score(i as f64), *member))
+      .collect::<Vec<_>>();
+    redis.zadd("z", &pairs).await.unwrap();
+
+    let options = ScanOptions {
+      pattern: None,
+      count: Some(10),
+      kind: None,
+    };
+    let (mut from_set, mut from_zset) = (Vec::new(), Vec::new());
+    let mut cursor = 0;
+    loop {
+      let (next, batch) = redis.sscan("s", cursor, &options).await.unwrap();
+      from_set.extend(batch);
+      if next == 0 {
+        break;
+      }
+      cursor = next;
+    }
+    cursor = 0;
+    loop {
+      let (next, batch) = redis.zscan("z", cursor, &options).await.unwrap();
+      from_zset.extend(batch.chunks(2).map(|pair| pair[0].clone()));
+      if next == 0 {
+        break;
+      }
+      cursor = next;
+    }
+
+    from_set.sort();
+    from_zset.sort();
+    let mut expected = members.clone();
+    expected.sort();
+    assert_eq!(from_set, expected);
+    assert_eq!(from_zset, expected);
+
+    let options = ScanOptions {
+      pattern: Some(b"member_2?"),
+      count: Some(100),
+      kind: None,
+    };
+    let (cursor, batch) = redis.zscan("z", 0, &options).await.unwrap();
+    assert_eq!(cursor, 0);
+    assert_eq!(batch[..2], [b"member_20".to_vec(), b"20".to_vec()]);
+    assert_eq!(batch.len(), 10);
+    assert!(matches!(
+      redis.sscan("z", 0, &options).await,
+      Err(RedisError::Type)
+    ));
+  }
+
+  #[tokio::test]
+  async fn test_role() {
+    let redis = super::Redis::new(Disabled {}).await;
+
+    match redis.exec(&Command::Role).await.unwrap() {
+      RedisValue::Nested(v) => {
+        assert!(matches!(&v[..], [
+          RedisValue::SimpleString(role),
+          RedisValue::Integer(0),
+          RedisValue::Nested(replicas),
+        ] if role.as_slice() == b"master" && replicas.is_empty()));
+      }
+      v => panic!("unexpected reply: {:?}", v),
+    }
+
+    redis
+      .exec(&Command::ReplicaOf("127.0.0.1", "6379"))
+      .await
+      .unwrap();
+    match redis.exec(&Command::Role).await.unwrap() {
+      RedisValue::Nested(v) => {
+        assert!(matches!(&v[..], [
+          RedisValue::SimpleString(role),
+          RedisValue::SimpleString(host),
+          RedisValue::Integer(6379),
+          ..
+        ] if role.as_slice() == b"slave" && host.as_slice() == b"127.0.0.1"));
+      }
+      v => panic!("unexpected reply: {:?}", v),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_memory_usage() {
+    let redis = super::Redis::new(Disabled {}).await;
+    redis.set("small", b"v").await;
+    redis.set("large", &[0; 1024]).await;
+
+    let small = redis.memory_usage("small").await.unwrap();
+    let large = redis.memory_usage("large").await.unwrap();
+    assert!(large > small);
+    assert_eq!(redis.memory_usage("missing").await, None);
+  }
+
+  #[tokio::test]
+  async fn test_list_encoding_conversion() {
+    let redis = super::Redis::new(Disabled {}).await;
+    redis
+      .exec(&Command::Debug("QUICKLIST-PACKED-THRESHOLD", vec!["4"]))
+      .await
+      .unwrap();
+
+    let values = (0..10).map(|i| i.to_string()).collect::<Vec<_>>();
+    let raw = values.iter().map(|v| v.as_bytes()).collect::<Vec<_>>();
+    redis
+      .push("list", &raw[..3].to_vec(), true, false)
+      .await
+      .unwrap();
+    assert!(matches!(
+      redis.shared_data.read().await.dict.get("list"),
+      Some(super::Value::List(List::Packed(_)))
+    ));
+    redis
+      .push("list", &raw[3..].to_vec(), true, false)
+      .await
+      .unwrap();
+    assert!(matches!(
+      redis.shared_data.read().await.dict.get("list"),
+      Some(super::Value::List(List::Deque(_)))
+    ));
+
+    let expected = values
+      .iter()
+      .map(|v| v.as_bytes().to_vec())
+      .collect::<Vec<_>>();
+    assert_eq!(redis.lrange("list", 0, -1).await.unwrap(), expected);
+    assert_eq!(redis.lrange("list", -3, -2).await.unwrap(), expected[7..9]);
+    assert_eq!(redis.llen("list").await.unwrap(), 10);
+
+    assert_eq!(
+      redis.pop("list", 8, true).await.unwrap().unwrap(),
+      expected[..8]
+    );
+    assert!(matches!(
+      redis.shared_data.read().await.dict.get("list"),
+      Some(super::Value::List(List::Packed(_)))
+    ));
+    assert_eq!(redis.lrange("list", 0, -1).await.unwrap(), expected[8..]);
+  }
+
+  /// The RESP2 bytes of `cmd`'s reply.
+  async fn reply_bytes(redis: &super::Redis<Disabled>, cmd: &Command<'_>) -> Vec<u8> {
+    let mut out = Vec::new();
+    crate::encoder::write_reply(
+      &mut out,
+      &redis.exec(cmd).await,
+      crate::encoder::Protocol::Resp2,
+    )
+    .await
+    .unwrap();
+    out
+  }
+
+  #[tokio::test]
+  async fn test_lrange_large() {
+    let redis = super::Redis::new(Disabled {}).await;
+    let elements = (0..100_000)
+      .map(|i| i.to_string().into_bytes())
+      .collect::<Vec<_>>();
+    let values = elements.iter().map(Vec::as_slice).collect::<Vec<_>>();
+    redis.push("list", &values, true, false).await.unwrap();
+
+    let expected = |range: &[Vec<u8>]| {
+      let mut out = format!("*{}\r\n", range.len()).into_bytes();
+      for element in range {
+        out.extend(format!("${}\r\n", element.len()).as_bytes());
+        out.extend(element);
+        out.extend(b"\r\n");
+      }
+      out
+    };
+    let lrange = |start, stop| Command::Lrange("list", start, stop);
+    assert_eq!(
+      reply_bytes(&redis, &lrange(0, -1)).await,
+      expected(&elements)
+    );
+    assert_eq!(
+      reply_bytes(&redis, &lrange(-3, -1)).await,
+      expected(&elements[99_997..])
+    );
+    assert_eq!(
+      reply_bytes(&redis, &lrange(10, 12)).await,
+      expected(&elements[10..=12])
+    );
+    assert_eq!(reply_bytes(&redis, &lrange(5, 1)).await, b"*0\r\n");
+    assert_eq!(
+      reply_bytes(&redis, &Command::Lrange("missing", 0, -1)).await,
+      b"*0\r\n"
+    );
+  }
+
+  #[tokio::test]
+  async fn test_lrange_streamed_while_popping() {
+    let redis = super::Redis::new(Disabled {}).await;
+    let elements = (0..3000)
+      .map(|i| i.to_string().into_bytes())
+      .collect::<Vec<_>>();
+    let values = elements.iter().map(Vec::as_slice).collect::<Vec<_>>();
+    redis.push("list", &values, true, false).await.unwrap();
+
+    let reply = redis.exec(&Command::Lrange("list", -2500, -1)).await;
+    assert!(matches!(reply, Ok(RedisValue::Streamed(2500, _))));
+    // gone before its windows are read
+    redis
+      .exec(&Command::Rpop("list", Some(1000)))
+      .await
+      .unwrap();
+
+    let mut out = Vec::new();
+    crate::encoder::write_reply(&mut out, &reply, crate::encoder::Protocol::Resp2)
+      .await
+      .unwrap();
+    let mut expected = b"*2500\r\n".to_vec();
+    for element in &elements[500..2000] {
+      expected.extend(format!("${}\r\n", element.len()).as_bytes());
+      expected.extend(element);
+      expected.extend(b"\r\n");
+    }
+    expected.extend(b"$-1\r\n".repeat(1000));
+    assert_eq!(out, expected);
+  }
+
+  #[tokio::test]
+  async fn test_pop_replies() {
+    let redis = super::Redis::new(Disabled {}).await;
+    let values: Vec<&[u8]> = vec![b"a", b"b", b"c"];
+    redis.push("list", &values, true, false).await.unwrap();
+    redis.exec(&Command::Set("string", b"v")).await.unwrap();
+
+    assert!(matches!(
+      redis.exec(&Command::Lpop("list", None)).await,
+      Ok(RedisValue::SimpleString(v)) if v.as_slice() == b"a"
+    ));
+    assert!(matches!(
+      redis.exec(&Command::Rpop("list", Some(5))).await,
+      Ok(RedisValue::Nested(v)) if v.len() == 2
+    ));
+    redis.push("list", &values, true, false).await.unwrap();
+    assert!(matches!(
+      redis.exec(&Command::Lpop("list", Some(0))).await,
+      Ok(RedisValue::Nested(v)) if v.is_empty()
+    ));
+    assert_eq!(redis.llen("list").await.unwrap(), 3);
+
+    assert!(matches!(
+      redis.exec(&Command::Lpop("missing", None)).await,
+      Ok(RedisValue::NullBulk)
+    ));
+    assert!(matches!(
+      redis.exec(&Command::Lpop("missing", Some(0))).await,
+      Ok(RedisValue::Array(v)) if v.is_empty()
+    ));
+    assert!(matches!(
+      redis.exec(&Command::Rpop("string", None)).await,
+      Err(RedisError::Type)
+    ));
+    assert!(matches!(
+      redis.exec(&Command::Rpop("string", Some(2))).await,
+      Err(RedisError::Type)
+    ));
+  }
+
+  #[tokio::test]
+  async fn test_lpush_order() {
+    let redis = super::Redis::new(Disabled {}).await;
+    let values: Vec<&[u8]> = vec![b"a", b"b", b"c"];
+    redis.push("list", &values, true, true).await.unwrap();
+    redis
+      .push("list", &vec![b"d" as &[u8]], true, true)
+      .await
+      .unwrap();
+
+    assert_eq!(
+      redis.lrange("list", 0, -1).await.unwrap(),
+      vec![b"d".to_vec(), b"c".to_vec(), b"b".to_vec(), b"a".to_vec()]
+    );
+  }
+
+  #[tokio::test]
+  async fn test_lindex() {
+    let redis = super::Redis::new(Disabled {}).await;
+    let values: Vec<&[u8]> = vec![b"a", b"b", b"c"];
+    redis.push("list", &values, true, false).await.unwrap();
+
+    assert_eq!(redis.lindex("list", 0).await.unwrap(), Some(b"a".to_vec()));
+    assert_eq!(redis.lindex("list", -1).await.unwrap(), Some(b"c".to_vec()));
+    assert_eq!(redis.lindex("list", 3).await.unwrap(), None);
+    assert_eq!(redis.lindex("list", -4).await.unwrap(), None);
+  }
+
+  #[tokio::test]
+  async fn test_incr_by_float() {
+    let redis = super::Redis::new(Disabled {}).await;
+    // outputs of the same commands against Redis
+    let cases: [(&[u8], f64, &str); 10] = [
+      (b"10.50", 0.1, "10.6"),
+      (b"10.6", -5.0, "5.6"),
+      (b"5.0e3", 2.0e2, "5200"),
+      (b"3.0", 0.0, "3"),
+      (b"0", 1e20, "100000000000000000000"),
+      (b"-0.5", 0.5, "0"),
+      (b"0.1", 0.7, "0.8"),
+      (b"0.5", -1.5, "-1"),
+      (b"-1.5", 0.25, "-1.25"),
+      (b"1e-18", 0.0, "0"),
+    ];
+    for (start, by, expected) in cases {
+      redis.set("f", start).await;
+      assert_eq!(redis.incr_by_float("f", by).await.unwrap(), expected);
+      assert_eq!(
+        redis.get("f").await.unwrap().unwrap().as_slice(),
+        expected.as_bytes()
+      );
+    }
+    assert_eq!(redis.incr_by_float("missing", 0.25).await.unwrap(), "0.25");
+    assert_eq!(redis.incr_by_float("sum", 0.1).await.unwrap(), "0.1");
+    assert_eq!(redis.incr_by_float("sum", 0.2).await.unwrap(), "0.3");
+
+    redis.set("string", b"abc").await;
+    assert!(matches!(
+      redis.incr_by_float("string", 1.0).await,
+      Err(RedisError::Other(e)) if e == "value is not a valid float"
+    ));
+    redis.set("big", b"1e308").await;
+    assert!(redis.incr_by_float("big", 1e308).await.is_err());
+  }
+
+  #[tokio::test]
+  async fn test_incr_decr() {
+    let redis = super::Redis::new(Disabled {}).await;
+    redis.set("counter", b"41").await;
+
+    assert_eq!(redis.incr_by("counter", 1).await.unwrap(), 42);
+    assert!(matches!(
+      redis.shared_data.read().await.dict["counter"],
+      super::Value::Int(42)
+    ));
+    assert_eq!(redis.incr_by("counter", -1).await.unwrap(), 41);
+    assert_eq!(
+      redis.get("counter").await.unwrap(),
+      Some(Arc::new(b"41".to_vec()))
+    );
+
+    assert!(matches!(
+      redis.exec(&Command::Decr("missing")).await,
+      Ok(RedisValue::Integer(-1))
+    ));
+
+    redis.set("string", b"abc").await;
+    assert!(matches!(
+      redis.incr_by("string", 1).await,
+      Err(RedisError::Type)
+    ));
+    redis.set("max", i64::MAX.to_string().as_bytes()).await;
+    assert!(matches!(
+      redis.incr_by("max", 1).await,
+      Err(RedisError::Other(_))
+    ));
+  }
+
+  /// INCR on a counter already stored as `Value::Int`.
+  #[bench]
+  fn bench_incr_int(b: &mut test::Bencher) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let redis = rt.block_on(super::Redis::new(Disabled {}));
+    rt.block_on(redis.incr_by("counter", 1)).unwrap();
+
+    b.iter(|| rt.block_on(redis.incr_by("counter", 1)).unwrap());
+  }
+
+  /// INCR on a counter stored as raw bytes, the parse-format round trip
+  /// every increment used to pay. Includes the SET needed to reset it.
+  #[bench]
+  fn bench_incr_raw(b: &mut test::Bencher) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let redis = rt.block_on(super::Redis::new(Disabled {}));
+
+    b.iter(|| {
+      rt.block_on(async {
+        redis.set("counter", b"123456789").await;
+        redis.incr_by("counter", 1).await.unwrap()
+      })
+    });
+  }
+
+  /// GET of a 4-byte value, copied out inline.
+  #[bench]
+  fn bench_get_inline(b: &mut test::Bencher) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let redis = rt.block_on(super::Redis::new(Disabled {}));
+    rt.block_on(redis.set("key", b"abcd"));
+
+    b.iter(|| rt.block_on(redis.exec(&Command::Get("key"))).unwrap());
+  }
+
+  /// GET of a 4-byte value through the shared `Arc`, the path every GET
+  /// used to take.
+  #[bench]
+  fn bench_get_arc(b: &mut test::Bencher) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let redis = rt.block_on(super::Redis::new(Disabled {}));
+    let value = super::Value::Raw(Arc::new(b"abcd".to_vec()));
+    rt.block_on(redis.shared_data.write())
+      .dict
+      .insert("key".to_string(), value);
+
+    b.iter(|| rt.block_on(redis.exec(&Command::Get("key"))).unwrap());
+  }
+
+  /// LRANGE of a whole 100k element list written out, some 11 MB of reply,
+  /// failing if its peak allocation gets anywhere near that.
+  #[bench]
+  fn bench_lrange_large(b: &mut test::Bencher) {
+    // one thread, the allocator counts per thread
+    let rt = tokio::runtime::Builder::new_current_thread()
+      .enable_all()
+      .build()
+      .unwrap();
+    let redis = rt.block_on(super::Redis::new(Disabled {}));
+    let elements = (0..100_000)
+      .map(|i| format!("{i:0>100}").into_bytes())
+      .collect::<Vec<_>>();
+    let values = elements.iter().map(Vec::as_slice).collect::<Vec<_>>();
+    rt.block_on(redis.push("list", &values, true, false))
+      .unwrap();
+
+    b.iter(|| {
+      let ((), peak) = crate::peak_alloc::peak(|| {
+        rt.block_on(async {
+          let reply = redis.exec(&Command::Lrange("list", 0, -1)).await;
+          let mut out = tokio::io::BufWriter::new(tokio::io::sink());
+          crate::encoder::write_reply(&mut out, &reply, crate::encoder::Protocol::Resp2)
+            .await
+            .unwrap();
+        })
+      });
+      assert!(peak < 1 << 20, "LRANGE peaked at {peak} bytes");
+      peak
+    });
+  }
+
+  /// Appends 1k small chunks to a fresh key, `hold_reply` keeping a GET reply
+  /// on its buffer across each append as a slow client would.
+  fn append_chunks(b: &mut test::Bencher, hold_reply: bool) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let redis = rt.block_on(super::Redis::new(Disabled {}));
+
+    b.iter(|| {
+      rt.block_on(async {
+        redis.exec(&Command::Set("key", b"")).await.unwrap();
+        for _ in 0..1_000 {
+          let _reply = if hold_reply {
+            Some(redis.exec(&Command::Get("key")).await.unwrap())
+          } else {
+            None
+          };
+          redis
+            .exec(&Command::Append("key", b"0123456789abcdef"))
+            .await
+            .unwrap();
+        }
+      })
+    });
+  }
+
+  #[bench]
+  fn bench_append(b: &mut test::Bencher) {
+    append_chunks(b, false);
+  }
+
+  #[bench]
+  fn bench_append_shared(b: &mut test::Bencher) {
+    append_chunks(b, true);
+  }
+
+  /// Counts how often the dict grows while 100k keys are SET into it.
+  async fn rehashes(initial_capacity: usize) -> usize {
+    let config = super::Config {
+      initial_capacity,
+      ..Default::default()
+    };
+    let redis = super::Redis::with_config(Disabled {}, config).await;
+    let mut capacity = redis.shared_data.read().await.dict.capacity();
+    let mut rehashes = 0;
+    for i in 0..100_000 {
+      redis.set(&format!("key:{i}"), b"value").await;
+      let grown = redis.shared_data.read().await.dict.capacity();
+      if grown != capacity {
+        capacity = grown;
+        rehashes += 1;
+      }
+    }
+    rehashes
+  }
+
+  #[tokio::test]
+  async fn test_initial_capacity() {
+    assert!(rehashes(super::DEFAULT_INITIAL_CAPACITY).await >= 8);
+    assert_eq!(rehashes(100_000).await, 0);
+  }
+
+  /// Loading 100k keys into a dict sized by default, rehashing as it grows.
+  #[bench]
+  fn bench_load_default_capacity(b: &mut test::Bencher) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let keys = (0..100_000).map(|i| format!("key:{i}")).collect::<Vec<_>>();
+
+    b.iter(|| {
+      rt.block_on(async {
+        let redis = super::Redis::new(Disabled {}).await;
+        for key in &keys {
+          redis.set(key, b"value").await;
+        }
+      })
+    });
+  }
+
+  /// Loading the same 100k keys into a dict presized for them.
+  #[bench]
+  fn bench_load_presized(b: &mut test::Bencher) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let keys = (0..100_000).map(|i| format!("key:{i}")).collect::<Vec<_>>();
+
+    b.iter(|| {
+      rt.block_on(async {
+        let config = super::Config {
+          initial_capacity: keys.len(),
+          ..Default::default()
+        };
+        let redis = super::Redis::with_config(Disabled {}, config).await;
+        for key in &keys {
+          redis.set(key, b"value").await;
+        }
+      })
+    });
+  }
+
+  /// Runs 8 tasks of 1000 GETs each on a multi-threaded runtime, with a
+  /// task doing SETs to another key alongside when `writer` is set.
+  fn concurrent_gets(b: &mut test::Bencher, writer: bool) {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+      .worker_threads(4)
+      .build()
+      .unwrap();
+    let redis = Arc::new(rt.block_on(super::Redis::new(Disabled {})));
+    rt.block_on(redis.set("key", b"abcd"));
+
+    b.iter(|| {
+      rt.block_on(async {
+        let done = Arc::new(AtomicBool::new(false));
+        let writing = writer.then(|| {
+          let (redis, done) = (redis.clone(), done.clone());
+          tokio::spawn(async move {
+            while !done.load(Ordering::Relaxed) {
+              redis.set("other", b"value").await;
+              tokio::task::yield_now().await;
+            }
+          })
+        });
+        let readers = (0..8)
+          .map(|_| {
+            let redis = redis.clone();
+            tokio::spawn(async move {
+              for _ in 0..1_000 {
+                redis.exec(&Command::Get("key")).await.unwrap();
+              }
+            })
+          })
+          .collect::<Vec<_>>();
+        for reader in readers {
+          reader.await.unwrap();
+        }
+        done.store(true, Ordering::Relaxed);
+        if let Some(writing) = writing {
+          writing.await.unwrap();
+        }
+      })
+    });
+  }
+
+  /// 8000 GETs from concurrent readers sharing the read lock.
+  #[bench]
+  fn bench_get_concurrent(b: &mut test::Bencher) {
+    concurrent_gets(b, false);
+  }
+
+  /// The same GETs while a writer keeps taking the write lock, the gap to
+  /// `bench_get_concurrent` is what readers lose waiting on it.
+  #[bench]
+  fn bench_get_concurrent_with_writer(b: &mut test::Bencher) {
+    concurrent_gets(b, true);
+  }
+
+  #[bench]
+  fn bench_lindex(b: &mut test::Bencher) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let redis = rt.block_on(super::Redis::new(Disabled {}));
+    let values = (0..100_000).map(|i| i.to_string()).collect::<Vec<_>>();
+    let raw = values.iter().map(|v| v.as_bytes()).collect::<Vec<_>>();
+    rt.block_on(redis.push("list", &raw, true, false)).unwrap();
+
+    b.iter(|| rt.block_on(redis.lindex("list", 50_000)).unwrap());
+  }
+
+  #[tokio::test]
+  async fn test_kill_clients() {
+    let redis = super::Redis::new(Disabled {}).await;
+    let (first, _) = redis.register_client("127.0.0.1:1000".to_string());
+    let (_, kill) = redis.register_client("127.0.0.1:2000".to_string());
+
+    assert_eq!(redis.kill_clients(&KillFilter::Addr("127.0.0.1:2000")), 1);
+    kill.notified().await;
+
+    redis.unregister_client(first);
+    assert_eq!(redis.kill_clients(&KillFilter::Id(first)), 0);
+  }
+
+  fn snapshot_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("reddis-{}-{}.rdb", name, std::process::id()))
+  }
+
+  #[tokio::test]
+  async fn test_shutdown_saves_first() {
+    let path = snapshot_path("shutdown");
+    let config = super::Config {
+      snapshot_path: Some(path.clone()),
+      allow_shutdown: true,
+      ..Default::default()
+    };
+    let redis = super::Redis::with_config(Disabled {}, config).await;
+    redis.set("key", b"value").await;
+
+    redis.exec(&Command::Shutdown(false)).await.unwrap();
+    redis.shutdown_requested().await;
+
+    let snapshot = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(snapshot, b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n");
+  }
+
+  #[tokio::test]
+  async fn test_shutdown_disabled() {
+    let redis = super::Redis::new(Disabled {}).await;
+
+    assert!(matches!(
+      redis.exec(&Command::Shutdown(true)).await,
+      Err(RedisError::Other(_))
+    ));
+  }
+
+  #[tokio::test]
+  async fn test_failover() {
+    let redis = super::Redis::new(Disabled {}).await;
+
+    assert!(matches!(
+      redis.exec(&Command::Failover(false)).await,
+      Err(RedisError::Other(e)) if e == "FAILOVER requires connected replicas"
+    ));
+    assert!(matches!(
+      redis.exec(&Command::Failover(true)).await,
+      Err(RedisError::Other(e)) if e == "No failover in progress"
+    ));
+  }
+
+  #[tokio::test]
+  async fn test_save_and_load() {
+    let path = snapshot_path("load");
+    let config = || super::Config {
+      snapshot_path: Some(path.clone()),
+      ..Default::default()
+    };
+    let redis = super::Redis::with_config(Disabled {}, config()).await;
+    redis.set("string", b"value").await;
+    redis.setex("volatile", b"value", 100).await;
+    let values: Vec<&[u8]> = vec![b"a", b"b"];
+    redis.push("list", &values, true, false).await.unwrap();
+    redis.hset("hash", &[(b"f", b"v")], true).await.unwrap();
+    redis.exec(&Command::Save).await.unwrap();
+
+    let loaded = super::Redis::with_config(Disabled {}, config()).await;
+    assert_eq!(loaded.load().await.unwrap(), 4);
+    std::fs::remove_file(&path).unwrap();
+
+    let value = Arc::new(b"value".to_vec());
+    assert_eq!(loaded.get("string").await.unwrap(), Some(value.clone()));
+    assert_eq!(loaded.get("volatile").await.unwrap(), Some(value));
+    assert!(loaded
+      .shared_data
+      .read()
+      .await
+      .expires
+      .contains_key("volatile"));
+    assert_eq!(
+      loaded.lrange("list", 0, -1).await.unwrap(),
+      vec![b"a".to_vec(), b"b".to_vec()]
+    );
+    assert_eq!(
+      loaded.hmget("hash", &[b"f"]).await.unwrap(),
+      vec![Some(b"v".to_vec())]
+    );
+  }
+
+  #[tokio::test]
+  async fn test_last_save() {
+    let path = snapshot_path("lastsave");
+    let config = super::Config {
+      snapshot_path: Some(path.clone()),
+      ..Default::default()
+    };
+    let redis = super::Redis::with_config(Disabled {}, config).await;
+    redis
+      .last_save
+      .store(0, std::sync::atomic::Ordering::Relaxed);
+
+    redis.exec(&Command::Save).await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let now = (super::now_millis() / 1000) as i64;
+    match redis.exec(&Command::LastSave).await.unwrap() {
+      RedisValue::Integer(at) => assert!(now - at <= 1, "{at} is not recent"),
+      other => panic!("unexpected reply {other:?}"),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_object_refcount() {
+    let redis = super::Redis::new(Disabled {}).await;
+    redis.set("key", &[b'x'; 64]).await;
+    assert!(matches!(
+      redis.exec(&Command::ObjectRefCount("key")).await,
+      Ok(RedisValue::Integer(1))
+    ));
+
+    let in_flight = redis.get("key").await.unwrap();
+    assert!(matches!(
+      redis.exec(&Command::ObjectRefCount("key")).await,
+      Ok(RedisValue::Integer(2))
+    ));
+    drop(in_flight);
+
+    assert!(redis
+      .exec(&Command::ObjectRefCount("missing"))
+      .await
+      .is_err());
+  }
+
+  #[tokio::test]
+  async fn test_getex_sets_ttl() {
+    let redis = super::Redis::new(Disabled {}).await;
+    redis.set("key", b"value").await;
+
+    let reply = redis
+      .exec(&Command::GetEx("key", Some(Expiry::Ex(100))))
+      .await;
+    assert!(matches!(reply, Ok(RedisValue::SimpleString(v)) if *v == b"value"));
+
+    let at = redis.shared_data.read().await.expires["key"];
+    let ttl = at - super::now_millis();
+    assert!(ttl > 99_000 && ttl <= 100_000);
+  }
+
+  #[tokio::test]
+  async fn test_getex_persist() {
+    let redis = super::Redis::new(Disabled {}).await;
+    redis.setex("key", b"value", 100).await;
+
+    let reply = redis
+      .exec(&Command::GetEx("key", Some(Expiry::Persist)))
+      .await;
+    assert!(matches!(reply, Ok(RedisValue::SimpleString(v)) if *v == b"value"));
+    assert!(!redis.shared_data.read().await.expires.contains_key("key"));
+
+    let reply = redis.exec(&Command::GetEx("missing", None)).await;
+    assert!(matches!(reply, Ok(RedisValue::NullBulk)));
+  }
+
+  #[tokio::test]
+  async fn test_sintercard() {
+    let redis = super::Redis::new(Disabled {}).await;
+    let a: Vec<&[u8]> = vec![b"1", b"2", b"3", b"4"];
+    let b: Vec<&[u8]> = vec![b"2", b"3", b"4", b"5"];
+    redis.sadd("a", &a).await.unwrap();
+    redis.sadd("b", &b).await.unwrap();
+
+    assert_eq!(redis.sintercard(&["a", "b"], None).await.unwrap(), 3);
+    assert_eq!(redis.sintercard(&["a", "b"], Some(0)).await.unwrap(), 3);
+    assert_eq!(redis.sintercard(&["a", "b"], Some(2)).await.unwrap(), 2);
+    assert_eq!(redis.sintercard(&["a", "missing"], None).await.unwrap(), 0);
+
+    redis.set("string", b"value").await;
+    assert!(matches!(
+      redis.sintercard(&["a", "string"], None).await,
+      Err(RedisError::Type)
+    ));
+  }
+
+  #[tokio::test]
+  async fn test_inline_strings() {
+    let redis = super::Redis::new(Disabled {}).await;
+    redis.set("short", b"abcd").await;
+    redis.set("long", &[b'x'; 64]).await;
+
+    assert!(matches!(
+      redis.exec(&Command::Get("short")).await,
+      Ok(RedisValue::InlineString(v)) if v.as_slice() == b"abcd"
+    ));
+    assert!(matches!(
+      redis.exec(&Command::Get("long")).await,
+      Ok(RedisValue::SimpleString(v)) if v.len() == 64
+    ));
+    assert_eq!(
+      redis.get("short").await.unwrap().unwrap().as_slice(),
+      b"abcd"
+    );
+    assert_eq!(redis.encoding("short").await, Some("embstr"));
+  }
+
+  #[tokio::test]
+  async fn test_set_get() {
+    let redis = super::Redis::new(Disabled {}).await;
+    let get = |condition| SetOptions {
+      condition,
+      get: true,
+      keep_ttl: false,
+      expire_at: None,
+      ttl: None,
+    };
+
+    assert!(matches!(
+      redis.exec(&Command::SetWith("k", b"a", get(None))).await,
+      Ok(RedisValue::NullBulk)
+    ));
+    assert!(matches!(
+      redis.exec(&Command::SetWith("k", b"b", get(None))).await,
+      Ok(RedisValue::InlineString(v)) if v.as_slice() == b"a"
+    ));
+    // NX keeps the value but still replies with it
+    assert!(matches!(
+      redis.exec(&Command::SetWith("k", b"c", get(Some(SetCondition::Nx)))).await,
+      Ok(RedisValue::InlineString(v)) if v.as_slice() == b"b"
+    ));
+    assert_eq!(redis.get("k").await.unwrap().unwrap().as_slice(), b"b");
+    assert!(matches!(
+      redis
+        .exec(&Command::SetWith("new", b"c", get(Some(SetCondition::Xx))))
+        .await,
+      Ok(RedisValue::NullBulk)
+    ));
+    assert_eq!(redis.get("new").await.unwrap(), None);
+
+    let values: Vec<&[u8]> = vec![b"a"];
+    redis.push("list", &values, true, false).await.unwrap();
+    assert!(matches!(
+      redis.exec(&Command::SetWith("list", b"c", get(None))).await,
+      Err(RedisError::Type)
+    ));
+    assert_eq!(redis.encoding("list").await, Some("listpack"));
+  }
+
+  #[tokio::test]
+  async fn test_set_clears_ttl() {
+    let redis = super::Redis::new(Disabled {}).await;
+    redis.exec(&Command::SetEx("k", b"a", 1)).await.unwrap();
+    redis.exec(&Command::Set("k", b"b")).await.unwrap();
+    redis.exec(&Command::SetEx("kept", b"a", 1)).await.unwrap();
+    let keep_ttl = SetOptions {
+      keep_ttl: true,
+      ..SetOptions::default()
+    };
+    redis
+      .exec(&Command::SetWith("kept", b"b", keep_ttl))
+      .await
+      .unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+    assert_eq!(redis.get("k").await.unwrap().unwrap().as_slice(), b"b");
+    assert_eq!(redis.get("kept").await.unwrap(), None);
+  }
+
+  #[tokio::test]
+  async fn test_sort() {
+    let redis = super::Redis::new(Disabled {}).await;
+    let values: Vec<&[u8]> = vec![b"10", b"2", b"-1.5", b"3"];
+    redis.push("nums", &values, true, false).await.unwrap();
+    assert_eq!(
+      redis.sort("nums", &SortOptions::default()).await.unwrap(),
+      vec![
+        b"-1.5".to_vec(),
+        b"2".to_vec(),
+        b"3".to_vec(),
+        b"10".to_vec()
+      ]
+    );
+    let alpha_desc = SortOptions {
+      alpha: true,
+      desc: true,
+      limit: None,
+    };
+    assert_eq!(
+      redis.sort("nums", &alpha_desc).await.unwrap(),
+      vec![
+        b"3".to_vec(),
+        b"2".to_vec(),
+        b"10".to_vec(),
+        b"-1.5".to_vec()
+      ]
+    );
+    let window = SortOptions {
+      limit: Some((1, 2)),
+      ..SortOptions::default()
+    };
+    assert_eq!(
+      redis.sort("nums", &window).await.unwrap(),
+      vec![b"2".to_vec(), b"3".to_vec()]
+    );
+
+    let values: Vec<&[u8]> = vec![b"1", b"one"];
+    redis.push("words", &values, true, false).await.unwrap();
+    assert!(matches!(
+      redis.sort("words", &SortOptions::default()).await,
+      Err(RedisError::Other(_))
+    ));
+    assert!(matches!(
+      redis.exec(&Command::Sort("missing", SortOptions::default())).await,
+      Ok(RedisValue::Nested(values)) if values.is_empty()
+    ));
+  }
+
+  #[test]
+  fn test_raw_mut_copies_shared_once() {
+    let held = Arc::new(b"abc".to_vec());
+    let mut value = super::Value::Raw(Arc::clone(&held));
+    value.raw_mut(4).unwrap()[3] = b'd';
+    assert_eq!(held.as_slice(), b"abc");
+
+    let data = value.raw_mut(4).unwrap();
+    assert_eq!(data.as_slice(), b"abcd");
+    assert!(data.capacity() >= 6);
+    let buffer = data.as_ptr();
+    assert_eq!(value.raw_mut(6).unwrap().as_ptr(), buffer);
+  }
+
+  #[tokio::test]
+  async fn test_append_to_int() {
+    let redis = super::Redis::new(Disabled {}).await;
+    redis.exec(&Command::Set("k", b"100")).await.unwrap();
+    assert_eq!(redis.encoding("k").await, Some("int"));
+
+    assert!(matches!(
+      redis.exec(&Command::Append("k", b"abc")).await,
+      Ok(RedisValue::Integer(6))
+    ));
+    let value = redis.get("k").await.unwrap().unwrap();
+    assert_eq!(value.as_slice(), b"100abc");
+    assert_eq!(redis.encoding("k").await, Some("raw"));
+
+    assert!(matches!(
+      redis.exec(&Command::Append("new", b"abc")).await,
+      Ok(RedisValue::Integer(3))
+    ));
+    let values: Vec<&[u8]> = vec![b"a"];
+    redis.push("list", &values, true, false).await.unwrap();
+    assert!(matches!(
+      redis.exec(&Command::Append("list", b"abc")).await,
+      Err(RedisError::Type)
+    ));
+  }
+
+  #[tokio::test]
+  async fn test_setrange() {
+    let redis = super::Redis::new(Disabled {}).await;
+    redis.exec(&Command::Set("k", b"42")).await.unwrap();
+    let in_flight = redis.get("k").await.unwrap().unwrap();
+
+    assert!(matches!(
+      redis.exec(&Command::SetRange("k", 1, b"7")).await,
+      Ok(RedisValue::Integer(2))
+    ));
+    assert_eq!(redis.get("k").await.unwrap().unwrap().as_slice(), b"47");
+    assert_eq!(redis.encoding("k").await, Some("raw"));
+    assert_eq!(in_flight.as_slice(), b"42");
+
+    redis
+      .exec(&Command::SetRange("padded", 3, b"x"))
+      .await
+      .unwrap();
+    let value = redis.get("padded").await.unwrap().unwrap();
+    assert_eq!(value.as_slice(), b"\0\0\0x");
+
+    assert!(matches!(
+      redis.exec(&Command::SetRange("missing", 3, b"")).await,
+      Ok(RedisValue::Integer(0))
+    ));
+    assert_eq!(redis.keys_count().await, 2);
+    assert!(redis
+      .exec(&Command::SetRange("k", super::MAX_STRING_LEN, b"x"))
+      .await
+      .is_err());
+  }
+
+  #[tokio::test]
+  async fn test_int_encoding() {
+    let redis = super::Redis::new(Disabled {}).await;
+    redis.set("int", b"123").await;
+    redis.set("padded", b"0123").await;
+    redis.set("string", b"abc").await;
+
+    assert_eq!(redis.encoding("int").await, Some("int"));
+    assert_eq!(redis.encoding("padded").await, Some("embstr"));
+    assert_eq!(redis.encoding("string").await, Some("embstr"));
+    assert!(redis
+      .exec(&Command::ObjectEncoding("missing"))
+      .await
+      .is_err());
+
+    assert_eq!(redis.incr_by("int", 1).await.unwrap(), 124);
+    assert_eq!(
+      redis.get("padded").await.unwrap(),
+      Some(Arc::new(b"0123".to_vec()))
+    );
+  }
+
+  #[tokio::test]
+  async fn test_debug_reload() {
+    let path = snapshot_path("reload");
+    let config = super::Config {
+      snapshot_path: Some(path.clone()),
+      ..Default::default()
+    };
+    let redis = super::Redis::with_config(Disabled {}, config).await;
+    redis.set("string", b"value").await;
+    redis.set("int", b"42").await;
+    redis.setex("volatile", b"value", 100).await;
+    let values: Vec<&[u8]> = vec![b"a", b"b", b"c"];
+    redis.push("list", &values, true, false).await.unwrap();
+    redis.hset("hash", &[(b"f", b"v")], true).await.unwrap();
+    redis.sadd("set", &values).await.unwrap();
+
+    assert!(matches!(
+      redis.exec(&Command::Debug("RELOAD", vec![])).await,
+      Ok(RedisValue::Ok)
+    ));
+    std::fs::remove_file(&path).unwrap();
+
+    let value = Arc::new(b"value".to_vec());
+    assert_eq!(redis.keys_count().await, 6);
+    assert_eq!(redis.get("string").await.unwrap(), Some(value.clone()));
+    assert_eq!(redis.encoding("int").await, Some("int"));
+    assert_eq!(redis.get("volatile").await.unwrap(), Some(value));
+    assert!(redis
+      .shared_data
+      .read()
+      .await
+      .expires
+      .contains_key("volatile"));
+    assert_eq!(
+      redis.lrange("list", 0, -1).await.unwrap(),
+      vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]
+    );
+    assert_eq!(
+      redis.hmget("hash", &[b"f"]).await.unwrap(),
+      vec![Some(b"v".to_vec())]
+    );
+    assert_eq!(redis.sintercard(&["set"], None).await.unwrap(), 3);
+  }
+
+  #[tokio::test]
+  async fn test_load_when_read_only() {
+    let path = snapshot_path("readonly");
+    std::fs::write(&path, crate::cmd::to_resp(&[b"SET", b"key", b"value"])).unwrap();
+    let config = super::Config {
+      read_only: true,
+      snapshot_path: Some(path.clone()),
+      ..Default::default()
+    };
+    let redis = super::Redis::with_config(Disabled {}, config).await;
+
+    let loaded = redis.load().await;
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded.unwrap(), 1);
+  }
+
+  #[tokio::test]
+  async fn test_json_journal_round_trip() {
+    let path = std::env::temp_dir().join(format!("reddis-journal-{}.jsonl", std::process::id()));
+    let file = tokio::fs::File::create(&path).await.unwrap();
+    let redis = super::Redis::new(Json::make(tokio::sync::Mutex::new(file), path.clone())).await;
+    redis
+      .exec(&Command::Set("key", "välue".as_bytes()))
+      .await
+      .unwrap();
+    let values: Vec<&[u8]> = vec![b"a", b"b"];
+    redis.exec(&Command::Lpush("list", values)).await.unwrap();
+    redis.exec(&Command::Get("key")).await.unwrap();
+    redis.journal.flush().await;
+
+    let journal = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(journal.lines().count(), 2);
+
+    let replayed = super::Redis::new(Disabled {}).await;
+    assert_eq!(replayed.replay_json(&journal).await.unwrap(), 2);
+    let value = replayed.get("key").await.unwrap().unwrap();
+    assert_eq!(value.as_slice(), "välue".as_bytes());
+    assert_eq!(
+      replayed.lrange("list", 0, -1).await.unwrap(),
+      vec![b"b".to_vec(), b"a".to_vec()]
+    );
+  }
+
+  #[tokio::test]
+  async fn test_replay_not_journaled() {
+    let path = std::env::temp_dir().join(format!("reddis-replay-{}.jsonl", std::process::id()));
+    let file = tokio::fs::File::create(&path).await.unwrap();
+    let redis = super::Redis::new(Json::make(tokio::sync::Mutex::new(file), path.clone())).await;
+    let journal = "{\"cmd\":\"SET\",\"key\":\"a\",\"value\":\"MQ==\"}\n\
+                   {\"cmd\":\"SET\",\"key\":\"b\",\"value\":\"Mg==\"}\n";
+
+    assert_eq!(redis.replay_json(journal).await.unwrap(), 2);
+    redis.journal.flush().await;
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+
+    redis.exec(&Command::Set("c", b"3")).await.unwrap();
+    redis.journal.flush().await;
+    let written = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(written.lines().count(), 1);
+    assert_eq!(redis.keys_count().await, 3);
+  }
+
+  #[tokio::test]
+  async fn test_failed_commands_not_journaled() {
+    let path = std::env::temp_dir().join(format!("reddis-failed-{}.jsonl", std::process::id()));
+    let file = tokio::fs::File::create(&path).await.unwrap();
+    let redis = super::Redis::new(Json::make(tokio::sync::Mutex::new(file), path.clone())).await;
+    redis.exec(&Command::Set("k", b"abc")).await.unwrap();
+    let values: Vec<&[u8]> = vec![b"a"];
+    assert!(redis.exec(&Command::Lpush("k", values)).await.is_err());
+    assert!(redis.exec(&Command::Incr("k")).await.is_err());
+    redis.journal.flush().await;
+
+    let journal = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(journal.lines().count(), 1);
+  }
+
+  #[tokio::test]
+  async fn test_replay_skips_failed_commands() {
+    let redis = super::Redis::new(Disabled {}).await;
+    let journal = "{\"cmd\":\"SET\",\"key\":\"k\",\"value\":\"YWJj\"}\n\
+                   {\"cmd\":\"LPUSH\",\"key\":\"k\",\"values\":[\"YQ==\"]}\n\
+                   {\"cmd\":\"SET\",\"key\":\"b\",\"value\":\"Mg==\"}\n";
+
+    assert_eq!(redis.replay_json(journal).await.unwrap(), 3);
+    assert_eq!(redis.keys_count().await, 2);
+    let value = redis.get("k").await.unwrap().unwrap();
+    assert_eq!(value.as_slice(), b"abc");
+  }
+
+  #[tokio::test]
+  async fn test_journal_keeps_deadlines() {
+    let path = std::env::temp_dir().join(format!("reddis-deadline-{}.jsonl", std::process::id()));
+    let file = tokio::fs::File::create(&path).await.unwrap();
+    let redis = super::Redis::new(Json::make(tokio::sync::Mutex::new(file), path.clone())).await;
+    let before = super::now_millis();
+    redis.exec(&Command::SetEx("a", b"1", 100)).await.unwrap();
+    redis.exec(&Command::Set("b", b"2")).await.unwrap();
+    redis
+      .exec(&Command::GetEx("b", Some(Expiry::Ex(100))))
+      .await
+      .unwrap();
+    let options = SetOptions {
+      ttl: Some(100_000),
+      ..SetOptions::default()
+    };
+    redis
+      .exec(&Command::SetWith("c", b"3", options))
+      .await
+      .unwrap();
+    redis.journal.flush().await;
+
+    let journal = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let deadlines = journal
+      .lines()
+      .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["pxat"].as_u64())
+      .collect::<Vec<_>>();
+    assert_eq!(deadlines.len(), 3);
+    for at in &deadlines {
+      assert!((before + 100_000..=super::now_millis() + 100_000).contains(at));
+    }
+
+    // replayed later, the deadlines are where they were
+    let replayed = super::Redis::new(Disabled {}).await;
+    assert_eq!(replayed.replay_json(&journal).await.unwrap(), 4);
+    let data = replayed.shared_data.read().await;
+    assert_eq!(data.expires.get("a"), Some(&deadlines[0]));
+    assert_eq!(data.expires.get("b"), Some(&deadlines[1]));
+    assert_eq!(data.expires.get("c"), Some(&deadlines[2]));
+    assert_eq!(
+      data.commands()[..]
+        .iter()
+        .filter(|args| args.contains(&b"PXAT".to_vec()))
+        .count(),
+      3
+    );
+  }
+
+  #[tokio::test]
+  async fn test_replay_drops_expired_keys() {
+    let redis = super::Redis::new(Disabled {}).await;
+    let past = super::now_millis() - 1000;
+    let journal = format!(
+      "{{\"cmd\":\"SET\",\"key\":\"gone\",\"value\":\"MQ==\",\"pxat\":{past}}}\n\
+       {{\"cmd\":\"SET\",\"key\":\"left\",\"value\":\"MQ==\"}}\n\
+       {{\"cmd\":\"GETEX\",\"key\":\"left\",\"pxat\":{past}}}\n\
+       {{\"cmd\":\"SET\",\"key\":\"live\",\"value\":\"MQ==\"}}\n"
+    );
+
+    assert_eq!(redis.replay_json(&journal).await.unwrap(), 4);
+    let data = redis.shared_data.read().await;
+    assert_eq!(data.dict.len(), 1);
+    assert!(data.dict.contains_key("live"));
+    assert!(data.ttl_heap.is_empty());
+  }
+
+  #[tokio::test]
+  async fn test_json_journal_truncated_tail() {
+    let redis = super::Redis::new(Disabled {}).await;
+    let journal = "{\"cmd\":\"SET\",\"key\":\"a\",\"value\":\"MQ==\"}\n\
+                   {\"cmd\":\"SET\",\"key\":\"b\",\"val";
+
+    assert_eq!(redis.replay_json(journal).await.unwrap(), 1);
+    assert_eq!(redis.keys_count().await, 1);
+  }
+
+  #[tokio::test]
+  async fn test_json_journal_corrupt_interior() {
+    let redis = super::Redis::new(Disabled {}).await;
+    let journal = "{\"cmd\":\"SET\",\"key\":\"a\",\"value\":\"MQ==\"}\n\
+                   {\"cmd\":\"SET\",\"key\":\"b\",\"val\n\
+                   {\"cmd\":\"SET\",\"key\":\"c\",\"value\":\"Mw==\"}\n";
+
+    assert!(matches!(
+      redis.replay_json(journal).await,
+      Err(RedisError::Other(e)) if e.starts_with("corrupt journal at line 2")
+    ));
+  }
+
+  #[tokio::test]
+  async fn test_hrandfield() {
+    let config = super::Config {
+      rng_seed: Some(7),
+      ..Default::default()
+    };
+    let redis = super::Redis::with_config(Disabled {}, config).await;
+    let pairs: Vec<(&[u8], &[u8])> = vec![(b"a", b"1"), (b"b", b"2"), (b"c", b"3")];
+    redis.hset("hash", &pairs, true).await.unwrap();
+    let value_of = |field: &[u8]| pairs.iter().find(|(f, _)| *f == field).map(|(_, v)| *v);
+
+    let picked = redis.hrandfield("hash", 2, true).await.unwrap();
+    assert_eq!(picked.len(), 4);
+    assert_ne!(picked[0], picked[2]);
+    for pair in picked.chunks(2) {
+      assert_eq!(value_of(&pair[0]), Some(pair[1].as_slice()));
+    }
+
+    let picked = redis.hrandfield("hash", -5, false).await.unwrap();
+    assert_eq!(picked.len(), 5);
+    assert!(picked.iter().all(|field| value_of(field).is_some()));
+
+    assert_eq!(redis.hrandfield("hash", 10, false).await.unwrap().len(), 3);
+    assert!(matches!(
+      redis
+        .exec(&Command::HRandField("missing", None, false))
+        .await,
+      Ok(RedisValue::NullBulk)
+    ));
+  }
+
+  #[tokio::test]
+  async fn test_zrange() {
+    let redis = super::Redis::new(Disabled {}).await;
+    let pairs: Vec<(Score, &[u8])> = vec![
+      (Score(1.0), b"one"),
+      (Score(2.0), b"two"),
+      (Score(3.0), b"three"),
+      (Score(4.0), b"four"),
+    ];
+    redis.exec(&Command::Zadd("z", pairs)).await.unwrap();
+    async fn range(redis: &super::Redis<Disabled>, cmd: Command<'_>) -> Vec<String> {
+      match redis.exec(&cmd).await {
+        Ok(RedisValue::Nested(values)) => values
+          .into_iter()
+          .map(|v| match v {
+            RedisValue::SimpleString(v) => String::from_utf8(v.to_vec()).unwrap(),
+            other => panic!("unexpected element {other:?}"),
+          })
+          .collect(),
+        other => panic!("unexpected reply {other:?}"),
+      }
+    }
+
+    let inclusive = ScoreBound::Inclusive(Score(2.0));
+    let exclusive = ScoreBound::Exclusive(Score(4.0));
+    assert_eq!(
+      range(
+        &redis,
+        Command::ZRangeByScore("z", inclusive, exclusive, true, None)
+      )
+      .await,
+      vec!["two", "2", "three", "3"]
+    );
+
+    let min = ScoreBound::Inclusive(Score(f64::NEG_INFINITY));
+    let max = ScoreBound::Inclusive(Score(f64::INFINITY));
+    assert_eq!(
+      range(
+        &redis,
+        Command::ZRangeByScore("z", min, max, false, Some((1, 2)))
+      )
+      .await,
+      vec!["two", "three"]
+    );
+    assert_eq!(
+      range(
+        &redis,
+        Command::ZRangeByScore("z", min, max, false, Some((3, -1)))
+      )
+      .await,
+      vec!["four"]
+    );
+
+    let pairs: Vec<(Score, &[u8])> =
+      vec![(Score(0.0), b"a"), (Score(0.0), b"b"), (Score(0.0), b"c")];
+    redis.exec(&Command::Zadd("lex", pairs)).await.unwrap();
+    assert_eq!(
+      range(
+        &redis,
+        Command::ZRangeByLex("lex", LexBound::Exclusive(b"a"), LexBound::Max, None)
+      )
+      .await,
+      vec!["b", "c"]
+    );
+  }
+
+  #[tokio::test]
+  async fn test_load_timeout() {
+    let journal = (0..50_000)
+      .map(|i| format!("{{\"cmd\":\"SET\",\"key\":\"key:{i}\",\"value\":\"dg==\"}}\n"))
+      .collect::<String>();
+
+    let config = super::Config {
+      load_timeout: Some(Duration::from_secs(60)),
+      ..Default::default()
+    };
+    let redis = super::Redis::with_config(Disabled {}, config).await;
+    assert_eq!(redis.replay_json(&journal).await.unwrap(), 50_000);
+    assert_eq!(redis.keys_count().await, 50_000);
+
+    let config = super::Config {
+      load_timeout: Some(Duration::ZERO),
+      ..Default::default()
+    };
+    let redis = super::Redis::with_config(Disabled {}, config).await;
+    assert!(matches!(
+      redis.replay_json(&journal).await,
+      Err(RedisError::Other(e)) if e.contains("load timeout")
+    ));
+  }
+
+  #[tokio::test]
+  async fn test_slowlog() {
+    let config = super::Config {
+      slowlog_slower_than_micros: 50_000,
+      ..Default::default()
+    };
+    let redis = super::Redis::with_config(Disabled {}, config).await;
+    redis.exec(&Command::Ping(None)).await.unwrap();
+    let sleep = Command::Debug("SLEEP", vec!["0.06"]);
+    redis.exec(&sleep).await.unwrap();
+
+    assert!(matches!(
+      redis.exec(&Command::SlowlogLen).await,
+      Ok(RedisValue::Integer(1))
+    ));
+    let entries = match redis.exec(&Command::SlowlogGet(None)).await.unwrap() {
+      RedisValue::Nested(entries) => entries,
+      other => panic!("unexpected reply {other:?}"),
+    };
+    match &entries[..] {
+      [RedisValue::Nested(entry)] => match &entry[..] {
+        [RedisValue::Integer(0), RedisValue::Integer(_), RedisValue::Integer(micros), RedisValue::Nested(args)] =>
+        {
+          assert!(*micros >= 60_000);
+          assert!(
+            matches!(&args[..], [RedisValue::SimpleString(cmd)] if cmd.starts_with(b"Debug(\"SLEEP\""))
+          );
+        }
+        other => panic!("unexpected entry {other:?}"),
+      },
+      other => panic!("unexpected entries {other:?}"),
+    }
+
+    redis.exec(&Command::SlowlogReset).await.unwrap();
+    assert!(matches!(
+      redis.exec(&Command::SlowlogLen).await,
+      Ok(RedisValue::Integer(0))
+    ));
+  }
+
+  #[tokio::test]
+  async fn test_info_commands_processed() {
+    let redis = super::Redis::new(Disabled {}).await;
+    for _ in 0..5 {
+      redis.exec(&Command::Ping(None)).await.unwrap();
+    }
+
+    let info = match redis.exec(&Command::Info(Some("stats"))).await.unwrap() {
+      RedisValue::Verbatim("txt", info) => String::from_utf8(info).unwrap(),
+      other => panic!("unexpected reply {other:?}"),
+    };
+    let processed = info
+      .lines()
+      .find_map(|line| line.strip_prefix("total_commands_processed:"))
+      .unwrap();
+    // INFO itself is counted too
+    assert!(processed.parse::<u64>().unwrap() >= 5);
+    assert!(info.contains("instantaneous_ops_per_sec:"));
+  }
+
+  #[tokio::test]
+  async fn test_memory_stats() {
+    let redis = super::Redis::new(Disabled {}).await;
+
+    let dataset_bytes = || async {
+      match redis.exec(&Command::MemoryStats).await.unwrap() {
+        RedisValue::Nested(stats) => match &stats[2..4] {
+          [RedisValue::SimpleString(name), RedisValue::Integer(bytes)] => {
+            assert_eq!(name.as_slice(), b"dataset.bytes");
+            *bytes
+          }
+          other => panic!("unexpected stats {other:?}"),
+        },
+        other => panic!("unexpected reply {other:?}"),
+      }
+    };
+
+    let empty = dataset_bytes().await;
+    redis.set("key", &[0; 1000]).await;
+    assert!(dataset_bytes().await >= empty + 1000);
+
+    assert!(matches!(
+      redis.exec(&Command::MemoryDoctor).await,
+      Ok(RedisValue::SimpleString(_))
+    ));
+  }
+
+  #[tokio::test]
+  async fn test_debug_object_list_encoding() {
+    let redis = super::Redis::new(Disabled {}).await;
+    redis
+      .exec(&Command::Debug("QUICKLIST-PACKED-THRESHOLD", vec!["4"]))
+      .await
+      .unwrap();
+    let object = || async {
+      match redis.exec(&Command::Debug("OBJECT", vec!["list"])).await {
+        Ok(RedisValue::SimpleString(info)) => String::from_utf8(info.to_vec()).unwrap(),
+        _ => panic!("DEBUG OBJECT failed"),
+      }
+    };
+
+    let values: Vec<&[u8]> = vec![b"a", b"b", b"c", b"d"];
+    redis.push("list", &values, true, false).await.unwrap();
+    let info = object().await;
+    assert!(info.contains("encoding:listpack") && info.contains("length:4"));
+
+    redis.push("list", &values, true, false).await.unwrap();
+    let info = object().await;
+    assert!(info.contains("encoding:quicklist") && info.contains("length:8"));
+
+    redis.pop("list", 6, false).await.unwrap();
+    let info = object().await;
+    assert!(info.contains("encoding:listpack") && info.contains("length:2"));
+  }
+
+  #[tokio::test]
+  async fn test_debug() {
+    let redis = super::Redis::new(Disabled {}).await;
+    redis.set("key", b"123").await;
+
+    let reply = redis.exec(&Command::Debug("object", vec!["key"])).await;
+    assert!(matches!(reply, Ok(RedisValue::SimpleString(info))
+      if std::str::from_utf8(&info).unwrap().contains("encoding:int")));
+
+    let expire = Command::Debug("SET-ACTIVE-EXPIRE", vec!["0"]);
+    assert!(matches!(redis.exec(&expire).await, Ok(RedisValue::Ok)));
+    assert!(!redis
+      .active_expire
+      .load(std::sync::atomic::Ordering::Relaxed));
+
+    let reply = redis.exec(&Command::Debug("SLEEP", vec![])).await;
+    assert!(matches!(reply, Err(RedisError::Other(_))));
+
+    let matches = Command::Debug("STRINGMATCH-LEN", vec!["user:[0-9]*", "user:42"]);
+    assert!(matches!(
+      redis.exec(&matches).await,
+      Ok(RedisValue::Integer(1))
+    ));
+    let matches = Command::Debug("STRINGMATCH-LEN", vec!["user:?", "user:42"]);
+    assert!(matches!(
+      redis.exec(&matches).await,
+      Ok(RedisValue::Integer(0))
+    ));
+
+    let reply = redis.exec(&Command::Debug("JMAP", vec![])).await;
+    assert!(matches!(reply, Err(RedisError::Other(e)) if e == "DEBUG subcommand not supported"));
+  }
+
+  #[tokio::test]
+  async fn test_rewrite_journal() {
+    let path = std::env::temp_dir().join(format!("reddis-rewrite-{}.jsonl", std::process::id()));
+    let file = tokio::fs::File::create(&path).await.unwrap();
+    let redis = super::Redis::new(Json::make(tokio::sync::Mutex::new(file), path.clone())).await;
+    for i in 0..100 {
+      let value = i.to_string();
+      redis
+        .exec(&Command::Set("key", value.as_bytes()))
+        .await
+        .unwrap();
+      let values: Vec<&[u8]> = vec![b"x"];
+      redis.exec(&Command::Rpush("list", values)).await.unwrap();
+    }
+    redis.exec(&Command::Del(vec!["list"])).await.unwrap();
+    let members: Vec<&[u8]> = vec![b"a", b"b"];
+    redis.exec(&Command::Sadd("set", members)).await.unwrap();
+
+    redis.rewrite_journal().await.unwrap();
+    // lands after the rewritten commands
+    redis.exec(&Command::Incr("key")).await.unwrap();
+    redis.journal.flush().await;
+
+    let journal = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(journal.lines().count(), 3);
+
+    let replayed = super::Redis::new(Disabled {}).await;
+    assert_eq!(replayed.replay_json(&journal).await.unwrap(), 3);
+    let value = replayed.get("key").await.unwrap().unwrap();
+    assert_eq!(value.as_slice(), b"100");
+    assert!(!replayed.shared_data.read().await.dict.contains_key("list"));
+    assert!(matches!(
+      replayed.exec(&Command::SInterCard(vec!["set"], None)).await,
+      Ok(RedisValue::Integer(2))
+    ));
+  }
+
+  #[tokio::test]
+  async fn test_waitaof() {
+    let disabled = super::Redis::new(Disabled {}).await;
+    assert!(disabled.exec(&Command::WaitAof(1, 0, 0)).await.is_err());
+
+    let path = std::env::temp_dir().join(format!("reddis-waitaof-{}.jsonl", std::process::id()));
+    let file = tokio::fs::File::create(&path).await.unwrap();
+    let redis =
+      Arc::new(super::Redis::new(Json::make(tokio::sync::Mutex::new(file), path.clone())).await);
+    let fsync = redis.clone();
+    tokio::spawn(async move { fsync.fsync_every_second().await });
+
+    redis.exec(&Command::Set("key", b"value")).await.unwrap();
+    assert_eq!(*redis.journal_synced.borrow(), 0);
+    let acked = tokio::time::timeout(
+      Duration::from_secs(5),
+      redis.exec(&Command::WaitAof(1, 0, 0)),
+    )
+    .await
+    .unwrap();
+    assert!(matches!(acked, Ok(RedisValue::Integer(1))));
+    assert_eq!(*redis.journal_synced.borrow(), 1);
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_object_freq() {
+    let redis = super::Redis::new(Disabled {}).await;
+    redis.exec(&Command::Set("key", b"value")).await.unwrap();
+    assert!(redis.exec(&Command::ObjectFreq("key")).await.is_err());
+
+    let config = super::Config {
+      maxmemory_policy: super::MaxMemoryPolicy::AllKeysLfu,
+      rng_seed: Some(42),
+      ..Default::default()
+    };
+    let redis = super::Redis::with_config(Disabled {}, config).await;
+    let freq = || async {
+      match redis.exec(&Command::ObjectFreq("key")).await {
+        Ok(RedisValue::Integer(freq)) => freq,
+        reply => panic!("unexpected OBJECT FREQ reply {:?}", reply.err()),
+      }
+    };
+    redis.exec(&Command::Set("key", b"value")).await.unwrap();
+    let initial = freq().await;
+    assert_eq!(initial, 5);
+
+    for _ in 0..1_000 {
+      redis.exec(&Command::Get("key")).await.unwrap();
+    }
+    let accessed = freq().await;
+    assert!(accessed > initial + 5, "{accessed}");
+    // inspecting the key doesn't count as an access
+    assert_eq!(freq().await, accessed);
+
+    redis.exec(&Command::Del(vec!["key"])).await.unwrap();
+    assert!(redis.exec(&Command::ObjectFreq("key")).await.is_err());
+  }
+
+  #[tokio::test]
+  async fn test_bitpos() {
+    let redis = super::Redis::new(Disabled {}).await;
+    redis.set("zeros", &[0, 0, 0]).await;
+    redis.set("ones", &[0xff, 0xff]).await;
+    redis.set("mixed", &[0xff, 0xf0, 0x00, 0x01]).await;
+
+    assert_eq!(redis.bitpos("zeros", true, None).await.unwrap(), -1);
+    assert_eq!(redis.bitpos("zeros", false, None).await.unwrap(), 0);
+    assert_eq!(redis.bitpos("ones", false, None).await.unwrap(), 16);
+    assert_eq!(
+      redis.bitpos("ones", false, Some((0, -1))).await.unwrap(),
+      -1
+    );
+    assert_eq!(redis.bitpos("ones", true, Some((1, 1))).await.unwrap(), 8);
+
+    assert_eq!(redis.bitpos("mixed", false, None).await.unwrap(), 12);
+    assert_eq!(
+      redis.bitpos("mixed", true, Some((2, -1))).await.unwrap(),
+      31
+    );
+    assert_eq!(
+      redis.bitpos("mixed", true, Some((-1, -1))).await.unwrap(),
+      31
+    );
+    assert_eq!(redis.bitpos("mixed", true, Some((3, 1))).await.unwrap(), -1);
+    assert_eq!(
+      redis.bitpos("mixed", true, Some((10, 20))).await.unwrap(),
+      -1
+    );
+
+    assert_eq!(redis.bitpos("missing", true, None).await.unwrap(), -1);
+    assert_eq!(redis.bitpos("missing", false, None).await.unwrap(), 0);
+    let values: Vec<&[u8]> = vec![b"a"];
+    redis.push("list", &values, true, false).await.unwrap();
+    assert!(matches!(
+      redis.bitpos("list", true, None).await,
+      Err(RedisError::Type)
+    ));
+  }
+
+  #[tokio::test]
+  async fn test_keys() {
+    let redis = super::Redis::new(Disabled {}).await;
+    for key in ["user:1", "user:2", "apple", "banana", "cherry", "date"] {
+      redis.set(key, b"v").await;
+    }
+    let keys = |pattern| {
+      let redis = &redis;
+      async move {
+        let mut keys = redis.keys(pattern).await;
+        keys.sort();
+        keys
+      }
+    };
+
+    assert_eq!(keys("*").await.len(), 6);
+    assert_eq!(
+      keys("user:*").await,
+      vec![b"user:1".to_vec(), b"user:2".to_vec()]
+    );
+    assert_eq!(
+      keys("[a-c]*").await,
+      vec![b"apple".to_vec(), b"banana".to_vec(), b"cherry".to_vec()]
+    );
+    assert!(keys("nothing*").await.is_empty());
+
+    redis
+      .shared_data
+      .write()
+      .await
+      .set_deadline("date", super::now_millis() - 1);
+    assert!(keys("d*").await.is_empty());
+  }
+
+  #[tokio::test]
+  async fn test_scan_type() {
+    let redis = super::Redis::new(Disabled {}).await;
+    redis.set("s:raw", &[b'x'; 64]).await;
+    redis.set("s:inline", b"abc").await;
+    redis.set("s:int", b"1").await;
+    let values: Vec<&[u8]> = vec![b"a"];
+    redis.push("list", &values, true, false).await.unwrap();
+    redis.hset("hash", &[(b"f", b"v")], true).await.unwrap();
+    redis.sadd("set", &values).await.unwrap();
+
+    let scan = |kind, pattern| ScanOptions {
+      kind: Some(kind),
+      pattern,
+      count: Some(100),
+    };
+    let (cursor, mut keys) = redis.scan(0, &scan("string", None)).await.unwrap();
+    keys.sort();
+    assert_eq!(cursor, 0);
+    assert_eq!(
+      keys,
+      vec![b"s:inline".to_vec(), b"s:int".to_vec(), b"s:raw".to_vec()]
+    );
+    let (_, keys) = redis.scan(0, &scan("STRING", Some(b"*int"))).await.unwrap();
+    assert_eq!(keys, vec![b"s:int".to_vec()]);
+    let (_, keys) = redis.scan(0, &scan("list", None)).await.unwrap();
+    assert_eq!(keys, vec![b"list".to_vec()]);
+    let (_, keys) = redis.scan(0, &scan("zset", None)).await.unwrap();
+    assert!(keys.is_empty());
+
+    // COUNT bounds the keys looked at, not the ones returned
+    let mut seen = Vec::new();
+    let mut cursor = 0;
+    loop {
+      let options = ScanOptions {
+        count: Some(2),
+        kind: Some("string"),
+        ..Default::default()
+      };
+      let (next, keys) = redis.scan(cursor, &options).await.unwrap();
+      assert!(keys.len() <= 2);
+      seen.extend(keys);
+      if next == 0 {
+        break;
+      }
+      cursor = next;
+    }
+    assert_eq!(seen.len(), 3);
+  }
+
+  #[tokio::test]
+  async fn test_scan_without_snapshot() {
+    let redis = super::Redis::new(Disabled {}).await;
+    for i in 0..20 {
+      redis.set(&format!("key:{i}"), b"v").await;
+    }
+    let options = ScanOptions {
+      count: Some(5),
+      ..ScanOptions::default()
+    };
+    let scan_all = |mut cursor| {
+      let redis = &redis;
+      let options = &options;
+      async move {
+        let mut seen = std::collections::HashSet::new();
+        loop {
+          let (next, keys) = redis.scan(cursor, options).await.unwrap();
+          seen.extend(keys);
+          if next == 0 {
+            return seen;
+          }
+          cursor = next;
+        }
+      }
+    };
+
+    // its snapshot dropped for newer scans, a scan starts over
+    let (evicted, _) = redis.scan(0, &options).await.unwrap();
+    for _ in 0..super::MAX_SCAN_SNAPSHOTS {
+      redis.scan(0, &options).await.unwrap();
+    }
+    assert_eq!(scan_all(evicted).await.len(), 20);
+
+    for i in 20..=super::MAX_SCAN_SNAPSHOT_KEYS {
+      redis.set(&format!("key:{i}"), b"v").await;
+    }
+    crate::sync::lock(&redis.scans).snapshots.clear();
+    assert_eq!(scan_all(0).await.len(), super::MAX_SCAN_SNAPSHOT_KEYS + 1);
+    assert!(crate::sync::lock(&redis.scans).snapshots.is_empty());
+  }
+
+  #[tokio::test]
+  async fn test_scan_under_concurrent_writes() {
+    let redis = Arc::new(super::Redis::new(Disabled {}).await);
+    for i in 0..500 {
+      redis.set(&format!("stable:{i}"), b"v").await;
+    }
+    let capacity = redis.shared_data.read().await.dict.capacity();
+
+    let writer = redis.clone();
+    let churn = tokio::spawn(async move {
+      for i in 0..5_000 {
+        writer.set(&format!("churn:{i}"), b"v").await;
+        if i % 3 == 0 {
+          writer.delete(&[format!("churn:{}", i / 2).as_str()]).await;
+        }
+        if i % 50 == 0 {
+          tokio::task::yield_now().await;
+        }
+      }
+    });
+
+    let mut seen = std::collections::HashSet::new();
+    let mut cursor = 0;
+    loop {
+      let (next, keys) = redis.scan(cursor, &ScanOptions::default()).await.unwrap();
+      seen.extend(keys);
+      if next == 0 {
+        break;
+      }
+      cursor = next;
+      tokio::task::yield_now().await;
+    }
+    // the dict rehashed while scanning
+    assert!(redis.shared_data.read().await.dict.capacity() > capacity);
+    churn.await.unwrap();
+
+    for i in 0..500 {
+      assert!(seen.contains(format!("stable:{i}").as_bytes()));
     }
+    assert!(matches!(
+      redis.scan(12345 << 40, &ScanOptions::default()).await,
+      Err(RedisError::Other(e)) if e == "invalid cursor"
+    ));
   }
 }
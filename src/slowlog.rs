@@ -0,0 +1,103 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::sync;
+
+pub static DEFAULT_SLOWER_THAN_MICROS: u64 = 10_000;
+pub static DEFAULT_MAX_LEN: usize = 128;
+
+pub struct Entry {
+  pub id: u64,
+  /// Unix seconds the command finished at.
+  pub timestamp: u64,
+  pub duration: Duration,
+  pub command: String,
+}
+
+/// The last `max_len` commands that took longer than the threshold, newest
+/// first.
+pub struct Slowlog {
+  slower_than: Duration,
+  max_len: usize,
+  entries: Mutex<VecDeque<Entry>>,
+  next_id: AtomicU64,
+}
+
+impl Slowlog {
+  pub fn new(slower_than_micros: u64, max_len: usize) -> Slowlog {
+    Slowlog {
+      slower_than: Duration::from_micros(slower_than_micros),
+      max_len,
+      entries: Mutex::new(VecDeque::with_capacity(max_len)),
+      next_id: AtomicU64::new(0),
+    }
+  }
+
+  /// Whether a command that ran for `duration` is over the threshold.
+  pub fn is_slow(&self, duration: Duration) -> bool {
+    duration >= self.slower_than
+  }
+
+  /// Records `command` if `duration` is over the threshold, dropping the
+  /// oldest entry once the log is full.
+  pub fn record(&self, timestamp: u64, duration: Duration, command: impl FnOnce() -> String) {
+    if !self.is_slow(duration) || self.max_len == 0 {
+      return;
+    }
+    let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+    let mut entries = sync::lock(&self.entries);
+    if entries.len() == self.max_len {
+      entries.pop_back();
+    }
+    entries.push_front(Entry {
+      id,
+      timestamp,
+      duration,
+      command: command(),
+    });
+  }
+
+  /// Runs `f` over at most `count` of the newest entries.
+  pub fn get<T>(&self, count: usize, f: impl Fn(&Entry) -> T) -> Vec<T> {
+    sync::lock(&self.entries)
+      .iter()
+      .take(count)
+      .map(f)
+      .collect()
+  }
+
+  pub fn len(&self) -> usize {
+    sync::lock(&self.entries).len()
+  }
+
+  pub fn reset(&self) {
+    sync::lock(&self.entries).clear();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::Slowlog;
+  use std::time::Duration;
+
+  #[test]
+  fn test_record() {
+    let slowlog = Slowlog::new(100, 2);
+    slowlog.record(0, Duration::from_micros(99), || "fast".to_string());
+    assert_eq!(slowlog.len(), 0);
+
+    for command in ["first", "second", "third"] {
+      slowlog.record(0, Duration::from_micros(100), || command.to_string());
+    }
+    let entries = slowlog.get(10, |e| (e.id, e.command.clone()));
+    assert_eq!(
+      entries,
+      vec![(2, "third".to_string()), (1, "second".to_string())]
+    );
+
+    slowlog.reset();
+    assert_eq!(slowlog.len(), 0);
+  }
+}
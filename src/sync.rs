@@ -0,0 +1,13 @@
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Locks `mutex` even if a session panicked while holding it. The
+/// critical sections only insert into or remove from collections, so the
+/// data is still usable and one panic doesn't take every client down.
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+  mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// `Mutex::get_mut` ignoring poisoning, see `lock`.
+pub fn get_mut<T>(mutex: &mut Mutex<T>) -> &mut T {
+  mutex.get_mut().unwrap_or_else(PoisonError::into_inner)
+}
@@ -1,14 +1,82 @@
+use crate::err::RedisError;
+use async_trait::async_trait;
+use std::borrow::Cow;
 use std::sync::Arc;
 
 #[derive(Debug)]
 pub enum RedisValue {
   Ok,
-  Nothing,
   Integer(i64),
-  EmptyString,
+  /// The nil bulk string `$-1`, as for a missing key. An empty string
+  /// value is a zero-length `SimpleString` or `InlineString` instead.
+  NullBulk,
   SimpleString(Arc<Vec<u8>>),
+  /// A short string copied out of the keyspace, written like `SimpleString`.
+  InlineString(Inline),
   BulkString(Vec<String>),
-  Array(Vec<String>),
+  /// Bulk strings, `None` elements are written as the nil bulk string. An
+  /// empty `Array` is the nil array `*-1`.
+  Array(Vec<Option<Vec<u8>>>),
+  Nested(Vec<RedisValue>),
+  /// A RESP3 map, RESP2 clients get its keys and values in a flat array.
+  Map(Vec<(&'static str, RedisValue)>),
+  /// A `+` status reply other than OK.
+  Status(Cow<'static, str>),
+  /// An error as an element of an array, like the results of EXEC.
+  Error(RedisError),
+  /// A RESP3 verbatim string with its three letter format, like `txt`.
+  /// RESP2 clients get a plain bulk string.
+  Verbatim(&'static str, Vec<u8>),
+  /// A reply already encoded, written out as is. Only for replies that are
+  /// the same in RESP2 and RESP3.
+  Encoded(Vec<u8>),
+  /// A reply prefixed by a RESP3 attribute map, like the latency of a slow
+  /// command. RESP2 clients get the reply alone.
+  Attributed(Vec<(&'static str, i64)>, Box<RedisValue>),
+  /// An array of that many elements too long to encode at once, fetched
+  /// `STREAM_WINDOW` at a time as the ones before are written.
+  Streamed(usize, Arc<dyn Windows>),
+}
+
+/// Elements a `Streamed` reply fetches at a time.
+pub const STREAM_WINDOW: usize = 1024;
+
+/// Where the elements of a `Streamed` reply come from.
+#[async_trait]
+pub trait Windows: std::fmt::Debug + Send + Sync {
+  /// Elements `from..from + count` encoded back to back, exactly `count`
+  /// of them.
+  async fn window(&self, from: usize, count: usize) -> Vec<u8>;
+}
+
+/// Longest string kept inline rather than behind an `Arc`.
+pub const INLINE_LEN: usize = 22;
+
+/// A string short enough to copy around by value, saving the allocation and
+/// the refcount bumps an `Arc` costs.
+#[derive(Clone, Copy, Debug)]
+pub struct Inline {
+  len: u8,
+  data: [u8; INLINE_LEN],
+}
+
+impl Inline {
+  /// `None` if `value` is longer than `INLINE_LEN`.
+  pub fn new(value: &[u8]) -> Option<Inline> {
+    if value.len() > INLINE_LEN {
+      return None;
+    }
+    let mut data = [0; INLINE_LEN];
+    data[..value.len()].copy_from_slice(value);
+    Some(Inline {
+      len: value.len() as u8,
+      data,
+    })
+  }
+
+  pub fn as_slice(&self) -> &[u8] {
+    &self.data[..self.len as usize]
+  }
 }
 
 impl From<&'static str> for RedisValue {
@@ -17,6 +85,12 @@ impl From<&'static str> for RedisValue {
   }
 }
 
+impl From<Vec<u8>> for RedisValue {
+  fn from(value: Vec<u8>) -> Self {
+    RedisValue::SimpleString(Arc::new(value))
+  }
+}
+
 impl From<usize> for RedisValue {
   fn from(value: usize) -> Self {
     RedisValue::Integer(value as i64)
@@ -25,11 +99,6 @@ impl From<usize> for RedisValue {
 
 impl From<Vec<Vec<u8>>> for RedisValue {
   fn from(value: Vec<Vec<u8>>) -> Self {
-    RedisValue::Array(
-      value
-        .iter()
-        .map(|v| String::from_utf8(v.to_vec()).unwrap())
-        .collect::<Vec<_>>(),
-    )
+    RedisValue::Array(value.into_iter().map(Some).collect())
   }
 }
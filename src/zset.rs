@@ -0,0 +1,176 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+
+/// A sorted set score, ordered by `f64::total_cmp` so it can key a
+/// `BTreeSet`. Scores are never NaN, the parser rejects it.
+#[derive(Clone, Copy, Debug)]
+pub struct Score(pub f64);
+
+impl PartialEq for Score {
+  fn eq(&self, other: &Self) -> bool {
+    self.cmp(other) == Ordering::Equal
+  }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Ord for Score {
+  fn cmp(&self, other: &Self) -> Ordering {
+    self.0.total_cmp(&other.0)
+  }
+}
+
+/// One end of a ZRANGEBYSCORE range, `-inf` and `+inf` being inclusive
+/// infinite scores.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScoreBound {
+  Inclusive(Score),
+  Exclusive(Score),
+}
+
+impl ScoreBound {
+  fn above_min(&self, score: Score) -> bool {
+    match self {
+      ScoreBound::Inclusive(min) => score >= *min,
+      ScoreBound::Exclusive(min) => score > *min,
+    }
+  }
+
+  fn below_max(&self, score: Score) -> bool {
+    match self {
+      ScoreBound::Inclusive(max) => score <= *max,
+      ScoreBound::Exclusive(max) => score < *max,
+    }
+  }
+}
+
+/// One end of a ZRANGEBYLEX range: `-`, `+`, `[member` or `(member`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LexBound<'a> {
+  Min,
+  Max,
+  Inclusive(&'a [u8]),
+  Exclusive(&'a [u8]),
+}
+
+impl LexBound<'_> {
+  fn above_min(&self, member: &[u8]) -> bool {
+    match self {
+      LexBound::Min => true,
+      LexBound::Max => false,
+      LexBound::Inclusive(min) => member >= *min,
+      LexBound::Exclusive(min) => member > *min,
+    }
+  }
+
+  fn below_max(&self, member: &[u8]) -> bool {
+    match self {
+      LexBound::Min => false,
+      LexBound::Max => true,
+      LexBound::Inclusive(max) => member <= *max,
+      LexBound::Exclusive(max) => member < *max,
+    }
+  }
+}
+
+/// A Redis sorted set: members ordered by score, ties broken by the member
+/// bytes, with the score of every member at hand for updates.
+#[derive(Clone, Default)]
+pub struct SortedSet {
+  scores: HashMap<Vec<u8>, Score>,
+  ordered: BTreeSet<(Score, Vec<u8>)>,
+}
+
+impl SortedSet {
+  pub fn len(&self) -> usize {
+    self.scores.len()
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (&[u8], f64)> {
+    self
+      .ordered
+      .iter()
+      .map(|(score, m)| (m.as_slice(), score.0))
+  }
+
+  /// Sets the score of `member`, returns whether it is new.
+  pub fn insert(&mut self, member: &[u8], score: f64) -> bool {
+    let score = Score(score);
+    match self.scores.insert(member.to_vec(), score) {
+      Some(old) => {
+        self.ordered.remove(&(old, member.to_vec()));
+        self.ordered.insert((score, member.to_vec()));
+        false
+      }
+      None => {
+        self.ordered.insert((score, member.to_vec()));
+        true
+      }
+    }
+  }
+
+  /// Members with a score between `min` and `max`, lowest first.
+  pub fn range_by_score(
+    &self,
+    min: ScoreBound,
+    max: ScoreBound,
+  ) -> impl Iterator<Item = (&[u8], f64)> {
+    self
+      .iter()
+      .skip_while(move |(_, score)| !min.above_min(Score(*score)))
+      .take_while(move |(_, score)| max.below_max(Score(*score)))
+  }
+
+  /// Members between `min` and `max`, only meaningful when every member has
+  /// the same score as in Redis.
+  pub fn range_by_lex<'a>(
+    &'a self,
+    min: LexBound<'a>,
+    max: LexBound<'a>,
+  ) -> impl Iterator<Item = (&'a [u8], f64)> {
+    self
+      .iter()
+      .skip_while(move |(member, _)| !min.above_min(member))
+      .take_while(move |(member, _)| max.below_max(member))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{LexBound, Score, ScoreBound, SortedSet};
+
+  #[test]
+  fn test_ranges() {
+    let mut zset = SortedSet::default();
+    assert!(zset.insert(b"a", 1.0));
+    assert!(zset.insert(b"b", 2.0));
+    assert!(zset.insert(b"c", 3.0));
+    assert!(!zset.insert(b"a", 4.0));
+
+    let members = |range: Vec<(&[u8], f64)>| {
+      range
+        .into_iter()
+        .map(|(m, _)| m.to_vec())
+        .collect::<Vec<_>>()
+    };
+    let min = ScoreBound::Exclusive(Score(2.0));
+    let max = ScoreBound::Inclusive(Score(f64::INFINITY));
+    assert_eq!(
+      members(zset.range_by_score(min, max).collect()),
+      vec![b"c".to_vec(), b"a".to_vec()]
+    );
+
+    let mut lex = SortedSet::default();
+    for member in [b"a", b"b", b"c", b"d"] {
+      lex.insert(member, 0.0);
+    }
+    let range = lex.range_by_lex(LexBound::Inclusive(b"b"), LexBound::Exclusive(b"d"));
+    assert_eq!(members(range.collect()), vec![b"b".to_vec(), b"c".to_vec()]);
+  }
+}